@@ -0,0 +1,114 @@
+//! Announcements which are repeated periodically so they don't expire from the DHT.
+
+use crate::worker::LookupEvent;
+use futures_util::Stream;
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::sync::mpsc;
+
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(15 * 60);
+const DEFAULT_JITTER: Duration = Duration::from_secs(60);
+
+/// Whether a search announces ourselves to the nodes closest to the InfoHash, and as what.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AnnounceMode {
+    /// Don't announce.
+    Disabled,
+    /// Announce ourselves as a peer still downloading the torrent.
+    Leecher,
+    /// Announce ourselves as a seed ([BEP33](https://www.bittorrent.org/beps/bep_0033.html)).
+    Seed,
+}
+
+impl From<bool> for AnnounceMode {
+    /// `true` announces as a leecher.
+    fn from(announce: bool) -> Self {
+        if announce {
+            Self::Leecher
+        } else {
+            Self::Disabled
+        }
+    }
+}
+
+/// Options for [`MainlineDht::announce()`](crate::MainlineDht::announce).
+#[derive(Clone, Copy, Debug)]
+pub struct AnnounceOptions {
+    interval: Duration,
+    jitter: Duration,
+    seed: bool,
+}
+
+impl AnnounceOptions {
+    /// Create the default options: announce as a leecher every 15 minutes with up to a minute of
+    /// jitter.
+    pub fn new() -> Self {
+        Self {
+            interval: DEFAULT_INTERVAL,
+            jitter: DEFAULT_JITTER,
+            seed: false,
+        }
+    }
+
+    /// Set whether to announce ourselves as a seed rather than a leecher.
+    pub fn set_seed(mut self, seed: bool) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Set how long to wait after one announce round finishes before starting the next one.
+    pub fn set_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Set the maximum random delay added to each interval, so that announces started at the
+    /// same time don't stay in lockstep.
+    pub fn set_jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    pub(crate) fn next_delay(&self) -> Duration {
+        self.interval + self.jitter.mul_f64(rand::random())
+    }
+
+    pub(crate) fn mode(&self) -> AnnounceMode {
+        if self.seed {
+            AnnounceMode::Seed
+        } else {
+            AnnounceMode::Leecher
+        }
+    }
+}
+
+impl Default for AnnounceOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Handle returned from [`MainlineDht::announce()`](crate::MainlineDht::announce). Yields the
+/// progress of each announce round the same way as
+/// [`MainlineDht::lookup()`](crate::MainlineDht::lookup): the peers found, the outcome of each
+/// announce and the summary which ends the round. When running dual-stack, each address family
+/// runs its own rounds. The announcing stops when the handle is dropped.
+#[must_use = "the announcing stops when the handle is dropped"]
+pub struct Announcement(mpsc::UnboundedReceiver<LookupEvent>);
+
+impl Announcement {
+    pub(crate) fn new(rx: mpsc::UnboundedReceiver<LookupEvent>) -> Self {
+        Self(rx)
+    }
+}
+
+impl Stream for Announcement {
+    type Item = LookupEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.0).poll_recv(cx)
+    }
+}
@@ -0,0 +1,129 @@
+//! Bloom filters of peer IP addresses used for DHT scrapes as per
+//! [BEP33](https://www.bittorrent.org/beps/bep_0033.html).
+
+use serde::{
+    de::{Deserialize, Deserializer, Error as _},
+    ser::{Serialize, Serializer},
+};
+use serde_bytes::ByteBuf;
+use sha1::{Digest, Sha1};
+use std::{convert::TryInto, fmt, net::IpAddr};
+
+/// Size of the filter in bytes.
+pub(crate) const BLOOM_FILTER_LEN: usize = 256;
+// Size of the filter in bits.
+const M: usize = BLOOM_FILTER_LEN * 8;
+// Number of hash functions.
+const K: f64 = 2.0;
+
+/// Estimated size of a swarm, returned from [`MainlineDht::scrape()`](crate::MainlineDht::scrape).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ScrapeResult {
+    /// Number of peers which have the complete torrent.
+    pub seeds: usize,
+    /// Number of peers which are still downloading the torrent.
+    pub leechers: usize,
+}
+
+// Boxed to keep the messages which contain it small.
+#[derive(Clone, Eq, PartialEq)]
+pub(crate) struct BloomFilter(Box<[u8; BLOOM_FILTER_LEN]>);
+
+impl BloomFilter {
+    /// Create an empty filter.
+    pub fn new() -> Self {
+        Self(Box::new([0; BLOOM_FILTER_LEN]))
+    }
+
+    /// Add the IP address to the filter.
+    pub fn insert(&mut self, ip: IpAddr) {
+        let hash = match ip {
+            IpAddr::V4(ip) => Sha1::digest(&ip.octets()),
+            IpAddr::V6(ip) => Sha1::digest(&ip.octets()),
+        };
+
+        let index1 = (hash[0] as usize | (hash[1] as usize) << 8) % M;
+        let index2 = (hash[2] as usize | (hash[3] as usize) << 8) % M;
+
+        self.0[index1 / 8] |= 1 << (index1 % 8);
+        self.0[index2 / 8] |= 1 << (index2 % 8);
+    }
+
+    /// Add all the addresses in the other filter to this one.
+    pub fn merge(&mut self, other: &Self) {
+        for (a, b) in self.0.iter_mut().zip(other.0.iter()) {
+            *a |= b
+        }
+    }
+
+    /// Estimate the number of distinct addresses in the filter.
+    pub fn estimate(&self) -> f64 {
+        let zeros: u32 = self.0.iter().map(|byte| byte.count_zeros()).sum();
+        // Saturated filter, the estimate would be infinite.
+        let zeros = zeros.max(1) as f64;
+        let m = M as f64;
+
+        (zeros / m).ln() / (K * (1.0 - 1.0 / m).ln())
+    }
+}
+
+impl fmt::Debug for BloomFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "BloomFilter(~{:.0})", self.estimate())
+    }
+}
+
+impl Serialize for BloomFilter {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_bytes(&self.0[..])
+    }
+}
+
+impl<'de> Deserialize<'de> for BloomFilter {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let bytes = ByteBuf::deserialize(d)?;
+        let bytes: [u8; BLOOM_FILTER_LEN] = bytes
+            .as_slice()
+            .try_into()
+            .map_err(|_| D::Error::invalid_length(bytes.len(), &"256"))?;
+
+        Ok(Self(Box::new(bytes)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    #[test]
+    fn positive_estimate() {
+        // Test vector from BEP33.
+        let mut filter = BloomFilter::new();
+
+        for i in 0..=255 {
+            filter.insert(Ipv4Addr::new(192, 0, 2, i).into());
+        }
+
+        for i in 0..=0x3e7 {
+            filter.insert(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, i).into());
+        }
+
+        assert!((filter.estimate() - 1224.9308).abs() < 0.001);
+    }
+
+    #[test]
+    fn positive_merge() {
+        let mut a = BloomFilter::new();
+        a.insert(Ipv4Addr::new(192, 0, 2, 1).into());
+        a.insert(Ipv4Addr::new(192, 0, 2, 2).into());
+
+        let mut b = BloomFilter::new();
+        b.insert(Ipv4Addr::new(192, 0, 2, 2).into());
+        b.insert(Ipv4Addr::new(192, 0, 2, 3).into());
+
+        a.merge(&b);
+        assert_eq!(a.estimate().round(), 3.0);
+        assert_eq!(BloomFilter::new().estimate(), 0.0);
+    }
+}
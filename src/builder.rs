@@ -1,20 +1,42 @@
 use crate::{
-    id::{InfoHash, NodeId},
-    routing::table::RoutingTable,
-    worker::{DhtHandler, OneshotTask, Socket, StartLookup, State},
-    SocketTrait,
+    announce::{AnnounceMode, AnnounceOptions, Announcement},
+    bloom::ScrapeResult,
+    config::DhtConfig,
+    id::{self, InfoHash, NodeId},
+    item::{self, Item, MutableItem, PutError},
+    routing::{
+        node::{NodeHandle, NodeStatus},
+        snapshot::{NodeSnapshot, RoutingTableInfo, RoutingTableSnapshot},
+        table::RoutingTable,
+    },
+    sample::{InfoHashSample, InfoHashWalker},
+    worker::{
+        DhtEvent, DhtHandler, IpVersion, LookupEvent, LookupKind, OneshotTask, PingError,
+        PingResponse, Sibling, Socket, StartAnnounce, StartLookup, State, Stats,
+    },
+    SocketTrait, CLIENT_IDENTIFICATION,
 };
-use futures_util::Stream;
+use ed25519_dalek::{SigningKey, VerifyingKey};
+use futures_util::{
+    future, ready,
+    stream::{self, SelectAll},
+    Stream, StreamExt,
+};
+use serde_bencode::value::Value;
 use std::{
-    collections::HashSet,
+    any::Any,
+    collections::{HashMap, HashSet},
     io,
-    net::SocketAddr,
+    net::{IpAddr, SocketAddr},
     pin::Pin,
     task::{Context, Poll},
     time::Duration,
 };
 use tokio::{
-    sync::{mpsc, oneshot},
+    sync::{
+        broadcast::{self, error::RecvError},
+        mpsc, oneshot,
+    },
     task,
 };
 
@@ -26,13 +48,41 @@ use tokio::{
 /// # IPv6
 ///
 /// This implementation supports IPv6 as per [BEP32](https://www.bittorrent.org/beps/bep_0032.html).
-/// To enable dual-stack DHT (use both IPv4 and IPv6), one needs to create two separate
-/// `MainlineDht` instances, one bound to an IPv4 and the other to an IPv6 address. It is
-/// recommended that both instances use the same node id ([`DhtBuilder::set_node_id`]). Any lookup
-/// should then be performed on both instances and their results aggregated.
+/// To enable dual-stack DHT (use both IPv4 and IPv6), start it with [`DhtBuilder::start_dual`]
+/// which binds it to one IPv4 and one IPv6 socket. Both address families then share the same node
+/// id but each has its own routing table. Lookups are performed on both of them and their results
+/// aggregated.
 #[derive(Clone)]
 pub struct MainlineDht {
-    send: mpsc::UnboundedSender<OneshotTask>,
+    // One for each address family we run on.
+    send: Vec<(IpVersion, mpsc::UnboundedSender<OneshotTask>)>,
+    bucket_size: usize,
+}
+
+/// Handle to a [`MainlineDht`] which doesn't keep it running.
+pub(crate) struct WeakMainlineDht {
+    send: Vec<(IpVersion, mpsc::WeakUnboundedSender<OneshotTask>)>,
+    bucket_size: usize,
+}
+
+impl WeakMainlineDht {
+    /// Returns the `MainlineDht` unless all its handles were dropped.
+    pub fn upgrade(&self) -> Option<MainlineDht> {
+        let send: Vec<_> = self
+            .send
+            .iter()
+            .filter_map(|(ip_version, send)| Some((*ip_version, send.upgrade()?)))
+            .collect();
+
+        if send.is_empty() {
+            None
+        } else {
+            Some(MainlineDht {
+                send,
+                bucket_size: self.bucket_size,
+            })
+        }
+    }
 }
 
 impl MainlineDht {
@@ -44,118 +94,715 @@ impl MainlineDht {
             read_only: true,
             announce_port: None,
             node_id: None,
+            external_ips: Vec::new(),
+            enforce_node_id: true,
+            client_version: Some(CLIENT_IDENTIFICATION.to_vec()),
+            restored_nodes: Vec::new(),
+            config: DhtConfig::new(),
         }
     }
 
-    /// Start the MainlineDht with the given DhtBuilder.
-    fn with_builder(builder: DhtBuilder, socket: Socket) -> Self {
-        let (command_tx, command_rx) = mpsc::unbounded_channel();
+    /// Start the MainlineDht with the given DhtBuilder, with one handler for each of the sockets.
+    fn with_builder(builder: DhtBuilder, sockets: Vec<Socket>) -> Self {
+        let node_id = builder
+            .node_id
+            .unwrap_or_else(|| secure_node_id(&builder, &sockets));
+
+        let mut handlers: Vec<_> = sockets
+            .into_iter()
+            .map(|socket| {
+                let (command_tx, command_rx) = mpsc::unbounded_channel();
+                let ip_version = socket.ip_version();
+
+                // Only the nodes of the same address family are any use to the handler.
+                let ipv4 = ip_version == IpVersion::V4;
+                let restored_nodes = builder
+                    .restored_nodes
+                    .iter()
+                    .filter(|node| node.addr.is_ipv4() == ipv4);
+
+                let mut routing_table = RoutingTable::new(node_id, builder.config.bucket_size);
+                routing_table.set_enforce_node_id(builder.enforce_node_id);
+                routing_table.restore(restored_nodes.clone());
+
+                // Bootstrap from the nodes which were good when the snapshot was taken, along with
+                // the explicitly added ones.
+                let nodes = restored_nodes
+                    .filter(|node| node.status == NodeStatus::Good)
+                    .map(|node| node.addr)
+                    .chain(builder.nodes.iter().copied())
+                    .filter(|addr| addr.is_ipv4() == ipv4)
+                    .collect();
 
-        // TODO: Utilize the security extension.
-        let routing_table = RoutingTable::new(builder.node_id.unwrap_or_else(rand::random));
-        let handler = DhtHandler::new(
-            routing_table,
-            socket,
-            builder.read_only,
-            builder.routers,
-            builder.nodes,
-            builder.announce_port,
-            command_rx,
-        );
+                let handler = DhtHandler::new(
+                    routing_table,
+                    socket,
+                    builder.config,
+                    builder.read_only,
+                    builder.routers.clone(),
+                    nodes,
+                    builder.announce_port,
+                    command_rx,
+                );
 
-        if command_tx.send(OneshotTask::StartBootstrap()).is_err() {
-            // `unreachable` is OK here because the corresponding receiver definitely exists at
-            // this point inside `handler`.
-            unreachable!()
+                if command_tx.send(OneshotTask::StartBootstrap()).is_err() {
+                    // `unreachable` is OK here because the corresponding receiver definitely exists
+                    // at this point inside `handler`.
+                    unreachable!()
+                }
+
+                (handler, (ip_version, command_tx))
+            })
+            .collect();
+
+        if let [(a, _), (b, _)] = &mut handlers[..] {
+            let (sibling_a, sibling_b) = Sibling::pair();
+            a.set_sibling(sibling_a);
+            b.set_sibling(sibling_b);
         }
 
-        task::spawn(handler.run());
+        let send = handlers
+            .into_iter()
+            .map(|(handler, send)| {
+                task::spawn(handler.run());
+                send
+            })
+            .collect();
 
-        Self { send: command_tx }
+        Self {
+            send,
+            bucket_size: builder.config.bucket_size,
+        }
     }
 
-    /// Get the state of the DHT state machine, can be used for debugging.
-    pub async fn get_state(&self) -> Option<State> {
-        let (tx, rx) = oneshot::channel();
+    pub(crate) fn downgrade(&self) -> WeakMainlineDht {
+        WeakMainlineDht {
+            send: self
+                .send
+                .iter()
+                .map(|(ip_version, send)| (*ip_version, send.downgrade()))
+                .collect(),
+            bucket_size: self.bucket_size,
+        }
+    }
 
-        if self.send.send(OneshotTask::GetState(tx)).is_err() {
-            None
-        } else {
-            rx.await.ok()
+    // Send the task created by `make_task` to the handler of each address family. Returns whether
+    // at least one of the handlers is still running.
+    fn send_all<F>(&self, mut make_task: F) -> bool
+    where
+        F: FnMut() -> OneshotTask,
+    {
+        let mut sent = false;
+
+        for (_, send) in &self.send {
+            sent |= send.send(make_task()).is_ok();
         }
+
+        sent
+    }
+
+    // Start the lookup on the handler of each address family. As each of them gets its own clone of
+    // the senders in `kind`, their results end up in the same receiver.
+    fn start_lookup(&self, target: InfoHash, kind: LookupKind) -> bool {
+        self.send_all(|| {
+            OneshotTask::StartLookup(StartLookup {
+                target,
+                kind: kind.clone(),
+            })
+        })
+    }
+
+    // Send the query created by `make_task` to the handler of each address family and collect the
+    // responses of the ones which are still running.
+    async fn query_all<T, F>(&self, mut make_task: F) -> Vec<T>
+    where
+        F: FnMut(oneshot::Sender<T>) -> OneshotTask,
+    {
+        let rxs = self.send.iter().filter_map(|(_, send)| {
+            let (tx, rx) = oneshot::channel();
+            send.send(make_task(tx)).ok().map(|()| rx)
+        });
+
+        future::join_all(rxs)
+            .await
+            .into_iter()
+            .filter_map(Result::ok)
+            .collect()
+    }
+
+    /// Get the state of the DHT state machine, can be used for debugging. When running dual-stack,
+    /// the node and bucket counts are those of both routing tables combined.
+    pub async fn get_state(&self) -> Option<State> {
+        self.query_all(OneshotTask::GetState)
+            .await
+            .into_iter()
+            .reduce(|a, b| State {
+                is_running: a.is_running || b.is_running,
+                bootstrapped: a.bootstrapped || b.bootstrapped,
+                good_node_count: a.good_node_count + b.good_node_count,
+                questionable_node_count: a.questionable_node_count + b.questionable_node_count,
+                bucket_count: a.bucket_count + b.bucket_count,
+            })
+    }
+
+    /// Get the traffic and health statistics of the DHT: message, error, timeout and byte counters
+    /// along with the current load of the handler. Returns `None` if the DHT has shut down.
+    pub async fn stats(&self) -> Option<Stats> {
+        self.query_all(OneshotTask::GetStats)
+            .await
+            .into_iter()
+            .reduce(|mut a, b| {
+                a.merge(b);
+                a
+            })
     }
 
     /// Waits until the DHT bootstrap completes, or returns immediately if it already completed.
-    /// Returns whether the bootstrap was successful.
+    /// Returns whether the bootstrap was successful. When running dual-stack, it's enough if it
+    /// succeeded for one of the address families.
     pub async fn bootstrapped(&self, timeout: Option<Duration>) -> bool {
-        let (tx, rx) = oneshot::channel();
+        // If the handlers have shut down, consider this as bootstrap failure.
+        self.query_all(|tx| OneshotTask::CheckBootstrap(tx, timeout))
+            .await
+            .into_iter()
+            .any(|bootstrapped| bootstrapped)
+    }
 
-        if self
-            .send
-            .send(OneshotTask::CheckBootstrap(tx, timeout))
-            .is_err()
-        {
-            // handler has shut down, consider this as bootstrap failure.
-            false
-        } else {
-            rx.await.unwrap_or(false)
+    /// Perform an iterative lookup of the nodes closest to the given target using `find_node`
+    /// queries. Returns the closest nodes (up to the bucket size, 8 by default) which responded to
+    /// us, ordered by their distance to the target. When running dual-stack, the nodes of both
+    /// address families are considered.
+    pub async fn find_node(&self, target: NodeId) -> Vec<NodeHandle> {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+
+        if !self.start_lookup(target, LookupKind::FindNode(tx)) {
+            return Vec::new();
         }
+
+        let mut nodes = Vec::new();
+
+        while let Some(node) = rx.recv().await {
+            nodes.push(node);
+        }
+
+        nodes.sort_by_key(|node| node.id ^ target);
+        nodes.truncate(self.bucket_size);
+        nodes
+    }
+
+    /// Ping the node at the given address and measure the round-trip time. If the node responds,
+    /// it's added to our routing table (if there is room for it and its id is valid).
+    pub async fn ping(&self, addr: SocketAddr) -> Result<PingResponse, PingError> {
+        let ip_version = if addr.is_ipv4() {
+            IpVersion::V4
+        } else {
+            IpVersion::V6
+        };
+
+        // Use the handler of the same address family, if there is one.
+        let (_, send) = self
+            .send
+            .iter()
+            .find(|(version, _)| *version == ip_version)
+            .or_else(|| self.send.first())
+            .ok_or(PingError::Shutdown)?;
+
+        let (tx, rx) = oneshot::channel();
+
+        send.send(OneshotTask::Ping(addr, tx))
+            .map_err(|_| PingError::Shutdown)?;
+
+        rx.await.map_err(|_| PingError::Shutdown)?
     }
 
     /// Perform a search for the given InfoHash with an optional announce on the closest nodes.
-    ///
+    /// `announce` is an [`AnnounceMode`], or a `bool` where `true` means announcing as a leecher.
     ///
     /// Announcing will place your contact information in the DHT so others performing lookups
     /// for the InfoHash will be able to find your contact information and initiate a handshake.
+    /// Announcing as a seed lets them know you have the whole torrent.
     ///
     /// If the initial bootstrap has not finished, the search will be queued and executed once
     /// the bootstrap has completed.
-    pub fn search(&self, info_hash: InfoHash, announce: bool) -> SearchStream {
+    ///
+    /// When running dual-stack, the search is performed over both address families and the stream
+    /// yields the peers found by either of them.
+    ///
+    /// Dropping the stream aborts the search, including the announce if it hasn't been sent yet.
+    pub fn search(&self, info_hash: InfoHash, announce: impl Into<AnnounceMode>) -> SearchStream {
         let (tx, rx) = mpsc::unbounded_channel();
+        let announce = announce.into();
 
-        if self
-            .send
-            .send(OneshotTask::StartLookup(StartLookup {
-                info_hash,
-                announce,
-                tx,
-            }))
-            .is_err()
-        {
+        if !self.start_lookup(info_hash, LookupKind::GetPeers { announce, tx }) {
             log::error!("failed to start search - DhtHandler has shut down");
         }
 
         SearchStream(rx)
     }
 
-    /// Get the local address this DHT instance is bound to
+    /// Perform the same search as [`MainlineDht::search()`], but yield the progress of the lookup
+    /// in detail: which node each peer came from, which nodes responded or timed out, the outcome
+    /// of each announce and finally a summary of the lookup. When running dual-stack, each
+    /// address family sends its own summary, tagged with its [`IpVersion`].
+    ///
+    /// Dropping the stream aborts the lookup.
+    pub fn lookup(&self, info_hash: InfoHash, announce: impl Into<AnnounceMode>) -> LookupStream {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let announce = announce.into();
+
+        if !self.start_lookup(info_hash, LookupKind::GetPeers { announce, tx }) {
+            log::error!("failed to start lookup - DhtHandler has shut down");
+        }
+
+        LookupStream(rx)
+    }
+
+    /// Announce ourselves for the given InfoHash and keep re-announcing periodically, as
+    /// configured by the options, so the announce doesn't expire. Each round performs the same
+    /// lookup as [`MainlineDht::lookup()`] and the returned handle yields its events.
+    ///
+    /// The announcing stops when the handle is dropped or the DHT shuts down.
+    pub fn announce(&self, info_hash: InfoHash, options: AnnounceOptions) -> Announcement {
+        let (tx, rx) = mpsc::unbounded_channel();
+
+        if !self.send_all(|| {
+            OneshotTask::StartAnnounce(StartAnnounce {
+                info_hash,
+                options,
+                tx: tx.clone(),
+            })
+        }) {
+            log::error!("failed to start announce - DhtHandler has shut down");
+        }
+
+        Announcement::new(rx)
+    }
+
+    /// Estimate the number of seeds and leechers of the torrent with the given InfoHash, without
+    /// connecting to any peers, using the bloom filters of the nodes closest to it as per
+    /// [BEP33](https://www.bittorrent.org/beps/bep_0033.html). Returns `None` if none of the
+    /// nodes supports it.
+    pub async fn scrape(&self, info_hash: InfoHash) -> Option<ScrapeResult> {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+
+        if !self.start_lookup(info_hash, LookupKind::Scrape(tx)) {
+            return None;
+        }
+
+        // Peers of different address families are distinct, so the estimates add up.
+        let mut total: Option<ScrapeResult> = None;
+
+        while let Some(result) = rx.recv().await {
+            let total = total.get_or_insert(ScrapeResult {
+                seeds: 0,
+                leechers: 0,
+            });
+            total.seeds += result.seeds;
+            total.leechers += result.leechers;
+        }
+
+        total
+    }
+
+    /// Ask the nodes closest to the given target for samples of the info hashes they store, as per
+    /// [BEP51](https://www.bittorrent.org/beps/bep_0051.html). Yields one sample for each node
+    /// that supports it.
+    ///
+    /// Each sample specifies the interval the node asked us to wait before requesting another one
+    /// from it. To sample the whole DHT while honoring those intervals, use
+    /// [`MainlineDht::walk_infohashes()`] instead.
+    pub fn sample_infohashes(&self, target: NodeId) -> SampleStream {
+        self.sample_infohashes_skipping(target, None)
+    }
+
+    /// Like [`MainlineDht::sample_infohashes()`], but the nodes at the given addresses are not
+    /// asked for samples (they are still used to find the other nodes).
+    pub(crate) fn sample_infohashes_skipping<I>(&self, target: NodeId, skip: I) -> SampleStream
+    where
+        I: IntoIterator<Item = SocketAddr>,
+    {
+        let (tx, rx) = mpsc::unbounded_channel();
+
+        if !self.start_lookup(
+            target,
+            LookupKind::SampleInfohashes {
+                skip: skip.into_iter().collect(),
+                tx,
+            },
+        ) {
+            log::error!("failed to start sampling - DhtHandler has shut down");
+        }
+
+        SampleStream(rx)
+    }
+
+    /// Returns a stream of the info hashes stored in the DHT, discovered by repeatedly sampling
+    /// nodes across the whole keyspace ([BEP51](https://www.bittorrent.org/beps/bep_0051.html)).
+    /// Every info hash is yielded at most once and no node is asked for a sample again before the
+    /// interval it requested elapses.
+    ///
+    /// The stream never ends while the DHT is running. It doesn't keep the DHT running: it ends
+    /// once all the `MainlineDht` handles are dropped.
+    pub fn walk_infohashes(&self) -> InfoHashWalker {
+        InfoHashWalker::new(self.downgrade())
+    }
+
+    /// Store an immutable item in the DHT as per
+    /// [BEP44](https://www.bittorrent.org/beps/bep_0044.html). Returns the target under which the
+    /// item can be retrieved with [`MainlineDht::get_immutable()`], which is the SHA-1 hash of the
+    /// bencoded value.
+    ///
+    /// Succeeds if at least one of the nodes closest to the target stored the item.
+    pub async fn put_immutable(&self, value: Value) -> Result<InfoHash, PutError> {
+        self.put(Item::Immutable(value), None).await
+    }
+
+    /// Retrieve an immutable item previously stored with [`MainlineDht::put_immutable()`].
+    /// Returns `None` if the item wasn't found.
+    pub async fn get_immutable(&self, target: InfoHash) -> Option<Value> {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+
+        if !self.start_lookup(target, LookupKind::GetImmutable(tx)) {
+            return None;
+        }
+
+        rx.recv().await
+    }
+
+    /// Store a mutable item in the DHT as per
+    /// [BEP44](https://www.bittorrent.org/beps/bep_0044.html), signed with the given keypair.
+    /// Returns the target under which the item can be retrieved with
+    /// [`MainlineDht::get_mutable()`].
+    ///
+    /// The `salt` (empty for none) allows storing multiple items under the same keypair. The
+    /// `seq` must be higher than that of the currently stored item, if any. If `cas` is given,
+    /// the item is stored only if the sequence number of the currently stored item is equal to
+    /// it, otherwise [`PutError::CasMismatch`] is returned.
+    ///
+    /// Succeeds if at least one of the nodes closest to the target stored the item, even if others
+    /// reported a conflict. Otherwise the conflict, if any, is returned in preference to the other
+    /// errors.
+    pub async fn put_mutable(
+        &self,
+        keypair: &SigningKey,
+        salt: &[u8],
+        seq: i64,
+        value: Value,
+        cas: Option<i64>,
+    ) -> Result<InfoHash, PutError> {
+        let item = MutableItem::sign(keypair, salt.to_vec(), seq, value)?;
+        self.put(Item::Mutable(Box::new(item)), cas).await
+    }
+
+    /// Retrieve the most recent version (the one with the highest sequence number) of a mutable
+    /// item stored under the given public key and salt. Returns `None` if the item wasn't found.
+    pub async fn get_mutable(&self, key: &VerifyingKey, salt: &[u8]) -> Option<MutableItem> {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+
+        if !self.start_lookup(
+            item::mutable_target(key, salt),
+            LookupKind::GetMutable {
+                salt: salt.to_vec(),
+                tx,
+            },
+        ) {
+            return None;
+        }
+
+        let mut latest: Option<MutableItem> = None;
+
+        while let Some(item) = rx.recv().await {
+            if latest
+                .as_ref()
+                .map(|latest| item.seq() > latest.seq())
+                .unwrap_or(true)
+            {
+                latest = Some(item);
+            }
+        }
+
+        latest
+    }
+
+    async fn put(&self, item: Item, cas: Option<i64>) -> Result<InfoHash, PutError> {
+        if item::encode_value(item.value()).len() > item::MAX_VALUE_LEN {
+            return Err(PutError::ValueTooBig);
+        }
+
+        let target = item.target();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+
+        if !self.start_lookup(target, LookupKind::PutItem { item, cas, tx }) {
+            return Err(PutError::Shutdown);
+        }
+
+        let mut stored = false;
+        let mut conflict = None;
+        let mut error = None;
+
+        // Wait for all the nodes, one of them storing the item is enough.
+        while let Some(result) = rx.recv().await {
+            match result {
+                Ok(()) => stored = true,
+                Err(e @ (PutError::CasMismatch | PutError::SeqTooLow)) => conflict = Some(e),
+                Err(e) => error = Some(e),
+            }
+        }
+
+        if stored {
+            Ok(target)
+        } else {
+            Err(conflict.or(error).unwrap_or(PutError::NotStored))
+        }
+    }
+
+    /// Get the local address this DHT instance is bound to. When running dual-stack, this is the
+    /// address of the IPv4 socket.
     pub async fn local_addr(&self) -> io::Result<SocketAddr> {
-        let (tx, rx) = oneshot::channel();
+        self.query_all(OneshotTask::GetLocalAddr)
+            .await
+            .into_iter()
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "DhtHandler has shut down"))
+    }
+
+    /// Take a snapshot of our node id and the nodes in our routing table, to be passed to
+    /// [`DhtBuilder::restore_routing_table()`] on the next start. Returns `None` if the
+    /// DhtHandler has shut down.
+    pub async fn export_routing_table(&self) -> Option<RoutingTableSnapshot> {
+        self.query_all(OneshotTask::ExportRoutingTable)
+            .await
+            .into_iter()
+            .reduce(|mut a, b| {
+                a.nodes.extend(b.nodes);
+                a
+            })
+    }
+
+    /// Describe our routing table bucket by bucket, with the status and activity of every node,
+    /// bad ones included. When running dual-stack, there is one for each address family. Returns
+    /// an empty vector if the DhtHandler has shut down.
+    pub async fn routing_table(&self) -> Vec<RoutingTableInfo> {
+        self.query_all(OneshotTask::InspectRoutingTable).await
+    }
+
+    /// Count the nodes in our routing table by the client software they run, as identified by the
+    /// `v` key of their messages. Nodes which don't identify themselves are counted under `None`.
+    ///
+    /// The identification usually consists of two characters identifying the client followed by
+    /// two bytes of its version, but that's only a convention. Returns an empty map if the
+    /// DhtHandler has shut down.
+    pub async fn client_census(&self) -> HashMap<Option<Vec<u8>>, usize> {
+        let mut total = HashMap::new();
+
+        for census in self.query_all(OneshotTask::GetClientCensus).await {
+            for (version, count) in census {
+                *total.entry(version).or_insert(0) += count;
+            }
+        }
+
+        total
+    }
+
+    /// Stop the DHT and give back the sockets it was started with, along with a snapshot of the
+    /// routing table which can be passed to [`DhtBuilder::restore_routing_table()`] on the next
+    /// start. All the other clones of this `MainlineDht` behave as if it had shut down right away.
+    ///
+    /// If `finish_lookups` is true, the ongoing lookups are finished with the nodes found so far:
+    /// their results are delivered and their announces and puts are sent. The DhtHandler then
+    /// waits for the responses to those, up to the lookup timeout, so their outcome is reported
+    /// too. Otherwise the lookups are just dropped. Either way their streams end.
+    ///
+    /// The sockets are given back boxed, to be downcast to the types they were started with.
+    ///
+    /// Returns `None` if the DhtHandler has already shut down.
+    pub async fn shutdown(self, finish_lookups: bool) -> Option<DhtShutdown> {
+        let rxs = self.send.iter().filter_map(|(ip_version, send)| {
+            let (tx, rx) = oneshot::channel();
+            send.send(OneshotTask::Shutdown { finish_lookups, tx })
+                .ok()
+                .map(|()| async move { (*ip_version, rx.await) })
+        });
+
+        let mut v4_socket = None;
+        let mut v6_socket = None;
+        let mut routing_table: Option<RoutingTableSnapshot> = None;
+
+        for (ip_version, result) in future::join_all(rxs).await {
+            let (socket, snapshot) = match result {
+                Ok(result) => result,
+                Err(_) => continue,
+            };
+
+            match ip_version {
+                IpVersion::V4 => v4_socket = Some(socket),
+                IpVersion::V6 => v6_socket = Some(socket),
+            }
+
+            match &mut routing_table {
+                Some(table) => table.nodes.extend(snapshot.nodes),
+                None => routing_table = Some(snapshot),
+            }
+        }
+
+        Some(DhtShutdown {
+            v4_socket,
+            v6_socket,
+            routing_table: routing_table?,
+        })
+    }
+
+    /// Get our external address, as reported by the remote nodes in their responses
+    /// ([BEP42](https://www.bittorrent.org/beps/bep_0042.html)). Only responses to our own
+    /// requests are taken into account and the reports expire after 30 minutes. Returns `None` if
+    /// not enough nodes agree on one (anymore) or if the DhtHandler has shut down. When running
+    /// dual-stack, the IPv4 address takes precedence.
+    pub async fn external_addr(&self) -> Option<SocketAddr> {
+        self.query_all(OneshotTask::GetExternalAddr)
+            .await
+            .into_iter()
+            .flatten()
+            .next()
+    }
+
+    /// Returns a stream which yields our new external address every time the remote nodes agree on
+    /// a different one. See [`MainlineDht::external_addr()`].
+    pub fn external_addr_changes(&self) -> ExternalAddrStream {
+        let (tx, rx) = mpsc::unbounded_channel();
+
+        if !self.send_all(|| OneshotTask::WatchExternalAddr(tx.clone())) {
+            log::error!("failed to watch external address - DhtHandler has shut down");
+        }
+
+        ExternalAddrStream(rx)
+    }
+
+    /// Returns a stream of the events of the DHT internals: bootstrap state changes, changes to
+    /// the routing table, announces received from other nodes, socket errors and lookups. Meant for
+    /// monitoring. Each call returns a new stream which yields all the events from then on.
+    ///
+    /// Only a limited number of events is buffered for each stream. If it falls behind, the oldest
+    /// ones are dropped and [`DhtEvent::Lagged`] reports how many it missed.
+    pub fn events(&self) -> EventStream {
+        let streams: Vec<_> = self
+            .send
+            .iter()
+            .filter_map(|(ip_version, send)| {
+                let (tx, rx) = oneshot::channel();
+                send.send(OneshotTask::WatchEvents(tx)).ok()?;
+                Some(event_stream(*ip_version, rx))
+            })
+            .collect();
 
-        fn error() -> io::Error {
-            io::Error::new(io::ErrorKind::Other, "DhtHandler has shut down")
+        if streams.is_empty() {
+            log::error!("failed to watch events - DhtHandler has shut down");
         }
 
-        self.send
-            .send(OneshotTask::GetLocalAddr(tx))
-            .map_err(|_| error())?;
+        EventStream(stream::select_all(streams))
+    }
+}
+
+/// Stream returned from [`MainlineDht::external_addr_changes()`]
+#[must_use = "streams do nothing unless polled"]
+pub struct ExternalAddrStream(mpsc::UnboundedReceiver<SocketAddr>);
+
+impl Stream for ExternalAddrStream {
+    type Item = SocketAddr;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.0).poll_recv(cx)
+    }
+}
+
+/// Stream returned from [`MainlineDht::events()`]
+#[must_use = "streams do nothing unless polled"]
+pub struct EventStream(SelectAll<Pin<Box<dyn Stream<Item = DhtEvent> + Send>>>);
+
+impl Stream for EventStream {
+    type Item = DhtEvent;
 
-        rx.await.map_err(|_| error())
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.0).poll_next(cx)
     }
 }
 
+// The events of a single handler, once it sends us the receiver. Ends when the handler stops.
+fn event_stream(
+    ip_version: IpVersion,
+    rx: oneshot::Receiver<broadcast::Receiver<DhtEvent>>,
+) -> Pin<Box<dyn Stream<Item = DhtEvent> + Send>> {
+    let events = stream::once(rx)
+        .filter_map(|events| future::ready(events.ok()))
+        .flat_map(move |events| {
+            stream::unfold(events, move |mut events| async move {
+                let event = match events.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(missed)) => DhtEvent::Lagged { ip_version, missed },
+                    Err(RecvError::Closed) => return None,
+                };
+
+                Some((event, events))
+            })
+        });
+
+    Box::pin(events)
+}
+
 /// Stream returned from [`MainlineDht::search()`]
 #[must_use = "streams do nothing unless polled"]
-pub struct SearchStream(mpsc::UnboundedReceiver<SocketAddr>);
+pub struct SearchStream(mpsc::UnboundedReceiver<LookupEvent>);
 
 impl Stream for SearchStream {
     type Item = SocketAddr;
 
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        loop {
+            match ready!(Pin::new(&mut self.0).poll_recv(cx)) {
+                Some(LookupEvent::PeerFound { peer, .. }) => return Poll::Ready(Some(peer)),
+                Some(_) => continue,
+                None => return Poll::Ready(None),
+            }
+        }
+    }
+}
+
+/// Stream returned from [`MainlineDht::lookup()`]
+#[must_use = "streams do nothing unless polled"]
+pub struct LookupStream(mpsc::UnboundedReceiver<LookupEvent>);
+
+impl Stream for LookupStream {
+    type Item = LookupEvent;
+
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
         Pin::new(&mut self.0).poll_recv(cx)
     }
 }
 
+/// Stream returned from [`MainlineDht::sample_infohashes()`]
+#[must_use = "streams do nothing unless polled"]
+pub struct SampleStream(mpsc::UnboundedReceiver<InfoHashSample>);
+
+impl Stream for SampleStream {
+    type Item = InfoHashSample;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.0).poll_recv(cx)
+    }
+}
+
+/// What is left of the DHT after [`MainlineDht::shutdown()`].
+pub struct DhtShutdown {
+    /// The IPv4 socket the DHT was started with, to be downcast to its original type. `None` if
+    /// it didn't run over IPv4.
+    pub v4_socket: Option<Box<dyn Any + Send>>,
+    /// The IPv6 socket the DHT was started with, to be downcast to its original type. `None` if
+    /// it didn't run over IPv6.
+    pub v6_socket: Option<Box<dyn Any + Send>>,
+    /// Snapshot of the routing table at the time the DHT stopped, of both address families if
+    /// running dual-stack.
+    pub routing_table: RoutingTableSnapshot,
+}
+
 // ----------------------------------------------------------------------------//
 
 /// Stores information for initializing a DHT.
@@ -166,6 +813,11 @@ pub struct DhtBuilder {
     read_only: bool,
     announce_port: Option<u16>,
     node_id: Option<NodeId>,
+    external_ips: Vec<IpAddr>,
+    enforce_node_id: bool,
+    client_version: Option<Vec<u8>>,
+    restored_nodes: Vec<NodeSnapshot>,
+    config: DhtConfig,
 }
 
 impl DhtBuilder {
@@ -212,22 +864,127 @@ impl DhtBuilder {
         self
     }
 
-    /// Set the id of this node. If not provided, a random node id is generated.
+    /// Set the id of this node. If not provided, a random node id is generated. The generated id
+    /// is compliant with [BEP42](https://www.bittorrent.org/beps/bep_0042.html) if our external
+    /// IP address is known (see [`DhtBuilder::set_external_ip`]).
     ///
-    /// NOTE: when creating a double-stack DHT (ipv4 + ipv6), it's recommended that both DHTs use
-    /// the same node id.
+    /// When started with [`DhtBuilder::start_dual`], both address families use this id.
     pub fn set_node_id(mut self, id: NodeId) -> Self {
         self.node_id = Some(id);
         self
     }
 
+    /// Set our external IP address, used to generate a BEP42 compliant node id. When running
+    /// dual-stack, one can be set for each address family and the IPv4 one takes precedence.
+    ///
+    /// If not provided, the address the socket is bound to is used instead, but only if it's a
+    /// public address.
+    pub fn set_external_ip(mut self, ip: IpAddr) -> Self {
+        self.external_ips
+            .retain(|other| other.is_ipv4() != ip.is_ipv4());
+        self.external_ips.push(ip);
+        self
+    }
+
+    /// Set whether nodes whose ids are not compliant with
+    /// [BEP42](https://www.bittorrent.org/beps/bep_0042.html) should be kept out of our routing
+    /// table. Nodes on local networks are exempt. When disabled, non-compliant nodes are accepted
+    /// but compliant ones are still preferred. Default value is true.
+    pub fn set_enforce_node_id(mut self, enforce: bool) -> Self {
+        self.enforce_node_id = enforce;
+        self
+    }
+
+    /// Set the client identification to send in the `v` key of all our messages, or `None` to
+    /// not send any. Defaults to the identification of this library.
+    pub fn set_client_version(mut self, version: Option<Vec<u8>>) -> Self {
+        self.client_version = version;
+        self
+    }
+
+    /// Restore the routing table from a snapshot taken with
+    /// [`MainlineDht::export_routing_table()`], so the DHT becomes usable much sooner than when
+    /// bootstrapping from the routers only. Sets the node id to the one from the snapshot.
+    ///
+    /// The restored nodes need to be verified again, so they start out as questionable. The ones
+    /// that were good at the time of the snapshot are contacted first during the bootstrap.
+    pub fn restore_routing_table(mut self, snapshot: RoutingTableSnapshot) -> Self {
+        self.node_id = Some(snapshot.node_id);
+        self.restored_nodes = snapshot.nodes;
+        self
+    }
+
+    /// Set the tuning parameters of the lookups, the bootstrap and the routing table. They are
+    /// validated when the DHT is started.
+    pub fn set_config(mut self, config: DhtConfig) -> Self {
+        self.config = config;
+        self
+    }
+
     /// Start a mainline DHT with the current configuration and bind it to the provided socket.
-    /// Fails only if `socket.local_addr()` fails.
+    /// Fails if `socket.local_addr()` fails or if the [`DhtConfig`] is invalid.
     pub fn start<S: SocketTrait + Send + Sync + 'static>(
         self,
         socket: S,
     ) -> io::Result<MainlineDht> {
-        let socket = Socket::new(socket)?;
-        Ok(MainlineDht::with_builder(self, socket))
+        self.validate_config()?;
+
+        let socket = Socket::new(socket, self.client_version.clone())?;
+        Ok(MainlineDht::with_builder(self, vec![socket]))
+    }
+
+    /// Start a dual-stack mainline DHT with the current configuration, bound to the provided IPv4
+    /// and IPv6 sockets ([BEP32](https://www.bittorrent.org/beps/bep_0032.html)). Both address
+    /// families share one node id, each has its own routing table. A generated id is compliant
+    /// with [BEP42](https://www.bittorrent.org/beps/bep_0042.html) for our IPv4 address if that
+    /// is known, otherwise for the IPv6 one.
+    /// The nodes of each family learned over the other one are added to its routing table, and
+    /// requests for nodes of both families are answered from both routing tables.
+    ///
+    /// Fails if `local_addr()` of either socket fails, if the sockets are not bound to an IPv4
+    /// and an IPv6 address respectively or if the [`DhtConfig`] is invalid.
+    pub fn start_dual<S4, S6>(self, v4_socket: S4, v6_socket: S6) -> io::Result<MainlineDht>
+    where
+        S4: SocketTrait + Send + Sync + 'static,
+        S6: SocketTrait + Send + Sync + 'static,
+    {
+        self.validate_config()?;
+
+        let v4_socket = Socket::new(v4_socket, self.client_version.clone())?;
+        let v6_socket = Socket::new(v6_socket, self.client_version.clone())?;
+
+        if v4_socket.ip_version() != IpVersion::V4 || v6_socket.ip_version() != IpVersion::V6 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "expected an IPv4 and an IPv6 socket",
+            ));
+        }
+
+        Ok(MainlineDht::with_builder(self, vec![v4_socket, v6_socket]))
+    }
+
+    fn validate_config(&self) -> io::Result<()> {
+        self.config
+            .validate()
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))
+    }
+}
+
+// Both address families share the id, but a BEP42 id is valid only for a single IP. Derive it from
+// the IPv4 address if known, otherwise the IPv6 one. The external ip of a family is preferred to
+// the address we are bound to, which is used only if it is publicly reachable.
+fn secure_node_id(builder: &DhtBuilder, sockets: &[Socket]) -> NodeId {
+    let ip = sockets.iter().find_map(|socket| {
+        let ipv4 = socket.ip_version() == IpVersion::V4;
+        let external_ip = builder.external_ips.iter().find(|ip| ip.is_ipv4() == ipv4);
+        let local_ip = Some(socket.local_addr().ip())
+            .filter(|ip| !ip.is_unspecified() && !id::is_local_ip(*ip));
+
+        external_ip.copied().or(local_ip)
+    });
+
+    match ip {
+        Some(ip) => NodeId::secure(ip),
+        None => rand::random(),
     }
 }
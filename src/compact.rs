@@ -1,6 +1,6 @@
 //! Compact representation
 
-use crate::id::NODE_ID_LEN;
+use crate::id::{INFO_HASH_LEN, NODE_ID_LEN};
 use std::{
     convert::TryInto,
     net::{Ipv4Addr, Ipv6Addr, SocketAddr},
@@ -62,6 +62,37 @@ pub(crate) mod values {
     }
 }
 
+/// Serialize/deserialize optional `SocketAddr` in compact format.
+pub(crate) mod addr {
+    use serde::{
+        de::{Deserialize, Deserializer, Error as _},
+        ser::Serializer,
+    };
+    use serde_bytes::ByteBuf;
+    use std::net::SocketAddr;
+
+    pub(crate) fn serialize<S>(addr: &Option<SocketAddr>, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match addr {
+            Some(addr) => s.serialize_bytes(&super::encode_socket_addr(addr)),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub(crate) fn deserialize<'de, D>(d: D) -> Result<Option<SocketAddr>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let bytes = ByteBuf::deserialize(d)?;
+        let addr = super::decode_socket_addr(&bytes)
+            .ok_or_else(|| D::Error::invalid_length(bytes.len(), &"6 or 18"))?;
+
+        Ok(Some(addr))
+    }
+}
+
 /// Serialize/deserialize `Vec` of `NodeHandle` in compact format. Specialized for ipv4 addresses.
 pub(crate) mod nodes_v4 {
     use crate::routing::node::NodeHandle;
@@ -162,6 +193,55 @@ mod nodes {
     }
 }
 
+/// Serialize/deserialize optional `Vec` of `InfoHash` in compact format (concatenated into a single
+/// byte string).
+pub(crate) mod info_hashes {
+    use crate::id::InfoHash;
+    use serde::{
+        de::{Deserialize, Deserializer, Error as _},
+        ser::Serializer,
+    };
+    use serde_bytes::ByteBuf;
+    use std::convert::TryFrom;
+
+    pub(crate) fn serialize<S>(info_hashes: &Option<Vec<InfoHash>>, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let info_hashes = match info_hashes {
+            Some(info_hashes) => info_hashes,
+            None => return s.serialize_none(),
+        };
+
+        let mut buffer = Vec::with_capacity(info_hashes.len() * super::INFO_HASH_LEN);
+
+        for info_hash in info_hashes {
+            buffer.extend(info_hash.as_ref());
+        }
+
+        s.serialize_bytes(&buffer)
+    }
+
+    pub(crate) fn deserialize<'de, D>(d: D) -> Result<Option<Vec<InfoHash>>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let buffer = ByteBuf::deserialize(d)?;
+        let chunks = buffer.chunks_exact(super::INFO_HASH_LEN);
+
+        if !chunks.remainder().is_empty() {
+            let msg = format!("multiple of {}", super::INFO_HASH_LEN);
+            return Err(D::Error::invalid_length(buffer.len(), &msg.as_ref()));
+        }
+
+        Ok(Some(
+            chunks
+                .filter_map(|chunk| InfoHash::try_from(chunk).ok())
+                .collect(),
+        ))
+    }
+}
+
 fn decode_socket_addr(src: &[u8]) -> Option<SocketAddr> {
     if src.len() == SOCKET_ADDR_V4_LEN {
         let addr: [u8; 4] = src.get(..4)?.try_into().ok()?;
@@ -199,7 +279,10 @@ fn encode_socket_addr(addr: &SocketAddr) -> Vec<u8> {
 
 #[cfg(test)]
 mod tests {
-    use crate::{id::NodeId, routing::node::NodeHandle};
+    use crate::{
+        id::{InfoHash, NodeId},
+        routing::node::NodeHandle,
+    };
     use serde::{Deserialize, Serialize};
     use std::{
         fmt::Debug,
@@ -300,6 +383,40 @@ mod tests {
         );
     }
 
+    #[test]
+    fn encode_decode_addr() {
+        #[derive(Debug, Eq, PartialEq, Serialize, Deserialize)]
+        #[serde(transparent)]
+        struct Wrapper {
+            #[serde(with = "super::addr")]
+            addr: Option<SocketAddr>,
+        }
+
+        encode_decode(
+            &Wrapper {
+                addr: Some((Ipv4Addr::new(127, 0, 0, 1), 6789).into()),
+            },
+            &[b'6', b':', 127, 0, 0, 1, 26, 133],
+        );
+        encode_decode(
+            &Wrapper {
+                addr: Some(
+                    (
+                        Ipv6Addr::new(
+                            0x2001, 0x0db8, 0x85a3, 0x0000, 0x0000, 0x8a2e, 0x0370, 0x7334,
+                        ),
+                        1234,
+                    )
+                        .into(),
+                ),
+            },
+            &[
+                b'1', b'8', b':', 0x20, 0x01, 0x0d, 0xb8, 0x85, 0xa3, 0x00, 0x00, 0x00, 0x00, 0x8a,
+                0x2e, 0x03, 0x70, 0x73, 0x34, 4, 210,
+            ],
+        );
+    }
+
     #[test]
     fn encode_decode_nodes_v4() {
         #[derive(Debug, Eq, PartialEq, Serialize, Deserialize)]
@@ -375,6 +492,32 @@ mod tests {
         );
     }
 
+    #[test]
+    fn encode_decode_info_hashes() {
+        #[derive(Debug, Eq, PartialEq, Serialize, Deserialize)]
+        #[serde(transparent)]
+        struct Wrapper {
+            #[serde(with = "super::info_hashes")]
+            info_hashes: Option<Vec<InfoHash>>,
+        }
+
+        encode_decode(
+            &Wrapper {
+                info_hashes: Some(Vec::new()),
+            },
+            b"0:",
+        );
+        encode_decode(
+            &Wrapper {
+                info_hashes: Some(vec![
+                    InfoHash::from(*b"0123456789abcdefghij"),
+                    InfoHash::from(*b"klmnopqrstuvwxyz0123"),
+                ]),
+            },
+            b"40:0123456789abcdefghijklmnopqrstuvwxyz0123",
+        );
+    }
+
     #[test]
     fn attempt_to_encode_v4_nodes_as_v6() {
         #[derive(Debug, Eq, PartialEq, Serialize, Deserialize)]
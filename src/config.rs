@@ -0,0 +1,274 @@
+//! Tuning parameters of the lookups, the bootstrap and the routing table refresh.
+
+use crate::routing::bucket;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Maximum bucket size. The responses to `find_node` and `get_peers` requests carry up to this
+/// many nodes of each address family and must still fit into a single UDP datagram.
+pub const MAX_BUCKET_SIZE: usize = 16;
+
+/// Tuning parameters of the DHT, set with
+/// [`DhtBuilder::set_config()`](crate::DhtBuilder::set_config).
+///
+/// The defaults suit most uses. Longer timeouts and intervals make the DHT gentler on the network
+/// and the battery at the cost of slower lookups, shorter ones make it more responsive.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct DhtConfig {
+    pub(crate) bucket_size: usize,
+    pub(crate) lookup_initial_pick: usize,
+    pub(crate) lookup_iterative_pick: usize,
+    pub(crate) lookup_store_pick: usize,
+    pub(crate) lookup_timeout: Duration,
+    pub(crate) min_lookup_timeout: Duration,
+    pub(crate) endgame_timeout: Duration,
+    pub(crate) ping_timeout: Duration,
+    pub(crate) good_node_threshold: usize,
+    pub(crate) bootstrap_initial_timeout: Duration,
+    pub(crate) bootstrap_node_timeout: Duration,
+    pub(crate) bootstrap_retry_timeout: Duration,
+    pub(crate) bootstrap_check_interval: Duration,
+    pub(crate) refresh_interval: Duration,
+    pub(crate) refresh_pick: usize,
+}
+
+impl DhtConfig {
+    /// Create the default configuration.
+    pub fn new() -> Self {
+        Self {
+            bucket_size: bucket::DEFAULT_BUCKET_SIZE,
+            lookup_initial_pick: 4,
+            lookup_iterative_pick: 3,
+            lookup_store_pick: 8,
+            lookup_timeout: Duration::from_millis(1500),
+            min_lookup_timeout: Duration::from_millis(250),
+            endgame_timeout: Duration::from_millis(1500),
+            ping_timeout: Duration::from_secs(5),
+            good_node_threshold: 10,
+            bootstrap_initial_timeout: Duration::from_millis(2500),
+            bootstrap_node_timeout: Duration::from_millis(500),
+            bootstrap_retry_timeout: Duration::from_secs(5),
+            bootstrap_check_interval: Duration::from_secs(5),
+            refresh_interval: Duration::from_secs(6),
+            refresh_pick: 4,
+        }
+    }
+
+    /// Set the maximum number of nodes in each bucket of the routing table (the Kademlia `k`).
+    /// Also the number of nodes a lookup starts from, the bootstrap pings for each bucket, we send
+    /// in our responses and [`MainlineDht::find_node()`](crate::MainlineDht::find_node) returns.
+    /// Must not exceed [`MAX_BUCKET_SIZE`]. Default is 8.
+    pub fn set_bucket_size(mut self, size: usize) -> Self {
+        self.bucket_size = size;
+        self
+    }
+
+    /// Set the number of nodes queried in the first round of a lookup (the Kademlia `alpha`).
+    /// Must not exceed the bucket size. Default is 4.
+    pub fn set_lookup_initial_pick(mut self, num: usize) -> Self {
+        self.lookup_initial_pick = num;
+        self
+    }
+
+    /// Set the maximum number of nodes queried in each following round of a lookup, out of the
+    /// ones returned by a node which got us closer to the target. Default is 3.
+    pub fn set_lookup_iterative_pick(mut self, num: usize) -> Self {
+        self.lookup_iterative_pick = num;
+        self
+    }
+
+    /// Set the number of closest nodes we announce to or put items on, and which
+    /// [`MainlineDht::scrape()`](crate::MainlineDht::scrape) takes the estimate from. Default
+    /// is 8.
+    pub fn set_lookup_store_pick(mut self, num: usize) -> Self {
+        self.lookup_store_pick = num;
+        self
+    }
+
+    /// Set how long to wait for a node to respond to a lookup, announce or put request. Nodes
+    /// whose round-trip time we measured get a timeout adapted to it instead, this is its upper
+    /// bound. Also how long the refresh waits for its requests, including the pings of the
+    /// questionable nodes. Default is 1.5 seconds.
+    pub fn set_lookup_timeout(mut self, timeout: Duration) -> Self {
+        self.lookup_timeout = timeout;
+        self
+    }
+
+    /// Set the lower bound of the timeouts adapted to the round-trip time of the nodes, so a node
+    /// which usually responds fast isn't given up on after a single hiccup. The lookup timeout
+    /// takes precedence if it's lower. Default is 0.25 seconds.
+    pub fn set_min_lookup_timeout(mut self, timeout: Duration) -> Self {
+        self.min_lookup_timeout = timeout;
+        self
+    }
+
+    /// Set how long the final round of a lookup, which queries all the nodes not queried yet,
+    /// waits for their responses. Default is 1.5 seconds.
+    pub fn set_endgame_timeout(mut self, timeout: Duration) -> Self {
+        self.endgame_timeout = timeout;
+        self
+    }
+
+    /// Set how long [`MainlineDht::ping()`](crate::MainlineDht::ping) waits for the response.
+    /// Default is 5 seconds.
+    pub fn set_ping_timeout(mut self, timeout: Duration) -> Self {
+        self.ping_timeout = timeout;
+        self
+    }
+
+    /// Set the number of good nodes the routing table needs for the DHT to be considered
+    /// bootstrapped. Falling below it triggers another bootstrap. Default is 10.
+    pub fn set_good_node_threshold(mut self, num: usize) -> Self {
+        self.good_node_threshold = num;
+        self
+    }
+
+    /// Set how long to wait for the routers and the initial nodes to respond at the start of a
+    /// bootstrap. Default is 2.5 seconds.
+    pub fn set_bootstrap_initial_timeout(mut self, timeout: Duration) -> Self {
+        self.bootstrap_initial_timeout = timeout;
+        self
+    }
+
+    /// Set how long to wait for a node to respond to a bootstrap request. Default is 0.5 seconds.
+    pub fn set_bootstrap_node_timeout(mut self, timeout: Duration) -> Self {
+        self.bootstrap_node_timeout = timeout;
+        self
+    }
+
+    /// Set how long to wait before retrying the bootstrap when none of the routers and nodes could
+    /// be resolved or sent to. Bootstraps which failed for lack of responses are retried with an
+    /// exponential backoff instead. Default is 5 seconds.
+    pub fn set_bootstrap_retry_timeout(mut self, timeout: Duration) -> Self {
+        self.bootstrap_retry_timeout = timeout;
+        self
+    }
+
+    /// Set how often to check, once bootstrapped, whether the routing table still has enough good
+    /// nodes. Default is 5 seconds.
+    pub fn set_bootstrap_check_interval(mut self, interval: Duration) -> Self {
+        self.bootstrap_check_interval = interval;
+        self
+    }
+
+    /// Set how often to refresh the next bucket of the routing table by querying its questionable
+    /// nodes. Default is 6 seconds.
+    pub fn set_refresh_interval(mut self, interval: Duration) -> Self {
+        self.refresh_interval = interval;
+        self
+    }
+
+    /// Set the maximum number of questionable nodes of the bucket queried in each refresh.
+    /// Default is 4.
+    pub fn set_refresh_pick(mut self, num: usize) -> Self {
+        self.refresh_pick = num;
+        self
+    }
+
+    /// Check that the configuration is usable. Called when the DHT is started.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let counts = [
+            ("bucket size", self.bucket_size),
+            ("lookup initial pick", self.lookup_initial_pick),
+            ("lookup iterative pick", self.lookup_iterative_pick),
+            ("lookup store pick", self.lookup_store_pick),
+            ("good node threshold", self.good_node_threshold),
+            ("refresh pick", self.refresh_pick),
+        ];
+
+        if let Some((name, _)) = counts.iter().find(|(_, num)| *num == 0) {
+            return Err(ConfigError::Zero(name));
+        }
+
+        let durations = [
+            ("lookup timeout", self.lookup_timeout),
+            ("min lookup timeout", self.min_lookup_timeout),
+            ("endgame timeout", self.endgame_timeout),
+            ("ping timeout", self.ping_timeout),
+            ("bootstrap initial timeout", self.bootstrap_initial_timeout),
+            ("bootstrap node timeout", self.bootstrap_node_timeout),
+            ("bootstrap retry timeout", self.bootstrap_retry_timeout),
+            ("bootstrap check interval", self.bootstrap_check_interval),
+            ("refresh interval", self.refresh_interval),
+        ];
+
+        if let Some((name, _)) = durations.iter().find(|(_, d)| d.is_zero()) {
+            return Err(ConfigError::Zero(name));
+        }
+
+        if self.bucket_size > MAX_BUCKET_SIZE {
+            return Err(ConfigError::TooLarge("bucket size", MAX_BUCKET_SIZE));
+        }
+
+        if self.lookup_initial_pick > self.bucket_size {
+            return Err(ConfigError::ExceedsBucketSize("lookup initial pick"));
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for DhtConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Error returned from [`DhtConfig::validate()`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Error)]
+pub enum ConfigError {
+    /// The parameter must not be zero.
+    #[error("{0} must not be zero")]
+    Zero(&'static str),
+    /// The parameter must not be greater than the given maximum.
+    #[error("{0} must not exceed {1}")]
+    TooLarge(&'static str, usize),
+    /// The parameter must not be greater than the bucket size.
+    #[error("{0} must not exceed the bucket size")]
+    ExceedsBucketSize(&'static str),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positive_default_is_valid() {
+        assert_eq!(DhtConfig::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn negative_zero_values() {
+        assert_eq!(
+            DhtConfig::new().set_bucket_size(0).validate(),
+            Err(ConfigError::Zero("bucket size"))
+        );
+        assert_eq!(
+            DhtConfig::new()
+                .set_refresh_interval(Duration::ZERO)
+                .validate(),
+            Err(ConfigError::Zero("refresh interval"))
+        );
+    }
+
+    #[test]
+    fn negative_bucket_size_too_large() {
+        assert_eq!(
+            DhtConfig::new()
+                .set_bucket_size(MAX_BUCKET_SIZE + 1)
+                .validate(),
+            Err(ConfigError::TooLarge("bucket size", MAX_BUCKET_SIZE))
+        );
+    }
+
+    #[test]
+    fn negative_initial_pick_exceeds_bucket_size() {
+        assert_eq!(
+            DhtConfig::new()
+                .set_bucket_size(4)
+                .set_lookup_initial_pick(5)
+                .validate(),
+            Err(ConfigError::ExceedsBucketSize("lookup initial pick"))
+        );
+    }
+}
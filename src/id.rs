@@ -7,6 +7,7 @@ use sha1::{Digest, Sha1};
 use std::{
     convert::{TryFrom, TryInto},
     fmt,
+    net::IpAddr,
     ops::BitXor,
 };
 use thiserror::Error;
@@ -26,6 +27,36 @@ impl Id {
         Self(hash.into())
     }
 
+    /// Create a random id which is valid for a node with the given external IP address, as per
+    /// [BEP42](https://www.bittorrent.org/beps/bep_0042.html).
+    pub fn secure(ip: IpAddr) -> Self {
+        let mut bytes: [u8; ID_LEN] = rand::random();
+        let crc = secure_prefix(ip, bytes[ID_LEN - 1]);
+
+        // Only the top 21 bits of the crc are used, the rest of the third byte stays random.
+        bytes[0] = (crc >> 24) as u8;
+        bytes[1] = (crc >> 16) as u8;
+        bytes[2] = ((crc >> 8) as u8 & 0xf8) | (bytes[2] & 0x07);
+
+        Self(bytes)
+    }
+
+    /// Check whether this is a valid id for a node with the given IP address, as per
+    /// [BEP42](https://www.bittorrent.org/beps/bep_0042.html).
+    ///
+    /// Nodes on local networks are exempt from the check, so their ids are always valid.
+    pub fn is_secure(&self, ip: IpAddr) -> bool {
+        if is_local_ip(ip) {
+            return true;
+        }
+
+        let crc = secure_prefix(ip, self.0[ID_LEN - 1]);
+
+        self.0[0] == (crc >> 24) as u8
+            && self.0[1] == (crc >> 16) as u8
+            && self.0[2] & 0xf8 == (crc >> 8) as u8 & 0xf8
+    }
+
     /// Flip the bit at the given index.
     ///
     /// # Panics
@@ -41,6 +72,18 @@ impl Id {
         bytes.into()
     }
 
+    /// Keep the given number of leading bits and clear the rest.
+    pub(crate) fn truncate(self, bits: usize) -> Self {
+        let mut bytes = self.0;
+
+        for (byte_index, byte) in bytes.iter_mut().enumerate() {
+            let kept = bits.saturating_sub(byte_index * 8).min(8);
+            *byte &= !(0xff_u16 >> kept) as u8;
+        }
+
+        bytes.into()
+    }
+
     /// Number of leading zero bits.
     pub(crate) fn leading_zeros(&self) -> u32 {
         let mut bits = 0;
@@ -121,6 +164,55 @@ impl fmt::Debug for Id {
     }
 }
 
+/// Compute the CRC32C of the masked IP address which the first 21 bits of a secure node id are
+/// taken from. `rand` is the last byte of the node id, only its 3 lowest bits are used.
+fn secure_prefix(ip: IpAddr, rand: u8) -> u32 {
+    const V4_MASK: [u8; 4] = [0x03, 0x0f, 0x3f, 0xff];
+    const V6_MASK: [u8; 8] = [0x01, 0x03, 0x07, 0x0f, 0x1f, 0x3f, 0x7f, 0xff];
+
+    let r = rand & 0x07;
+
+    match ip {
+        IpAddr::V4(ip) => {
+            let mut octets = ip.octets();
+
+            for (octet, mask) in octets.iter_mut().zip(V4_MASK.iter()) {
+                *octet &= mask;
+            }
+
+            octets[0] |= r << 5;
+            crc32c::crc32c(&octets)
+        }
+        IpAddr::V6(ip) => {
+            let mut octets = [0u8; 8];
+            octets.copy_from_slice(&ip.octets()[..8]);
+
+            for (octet, mask) in octets.iter_mut().zip(V6_MASK.iter()) {
+                *octet &= mask;
+            }
+
+            octets[0] |= r << 5;
+            crc32c::crc32c(&octets)
+        }
+    }
+}
+
+/// Is the address on a local network (as defined by BEP42)?
+pub(crate) fn is_local_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_private() || ip.is_loopback() || ip.is_link_local(),
+        IpAddr::V6(ip) => {
+            let first_segment = ip.segments()[0];
+
+            ip.is_loopback()
+                // unique local (fc00::/7)
+                || first_segment & 0xfe00 == 0xfc00
+                // link local (fe80::/10)
+                || first_segment & 0xffc0 == 0xfe80
+        }
+    }
+}
+
 mod byte_array {
     use super::ID_LEN;
     use serde::{
@@ -199,6 +291,69 @@ mod tests {
         assert_eq!(xor_hash.leading_zeros(), 1);
     }
 
+    #[test]
+    fn positive_secure_id_test_vectors() {
+        // Test vectors from BEP42.
+        let vectors = [
+            ("124.31.75.21", "5fbfbff10c5d6a4ec8a88e4c6ab4c28b95eee401"),
+            ("21.75.31.124", "5a3ce9c14e7a08645677bbd1cfe7d8f956d53256"),
+            ("65.23.51.170", "a5d43220bc8f112a3d426c84764f8c2a1150e616"),
+            ("84.124.73.14", "1b0321dd1bb1fe518101ceef99462b947a01ff41"),
+            ("43.213.53.83", "e56f6cbf5b7c4be0237986d5243b87aa6d51305a"),
+        ];
+
+        for (ip, id) in vectors {
+            let ip: IpAddr = ip.parse().unwrap();
+            let id = Id::try_from(hex::decode(id).unwrap().as_ref()).unwrap();
+
+            assert!(id.is_secure(ip));
+        }
+    }
+
+    #[test]
+    fn positive_generated_secure_id() {
+        let ips: [IpAddr; 2] = [
+            "124.31.75.21".parse().unwrap(),
+            "2001:db8:85a3::8a2e:370:7334".parse().unwrap(),
+        ];
+
+        for ip in ips {
+            assert!(Id::secure(ip).is_secure(ip));
+        }
+    }
+
+    #[test]
+    fn positive_truncate() {
+        let id = Id::from([0xff; ID_LEN]);
+
+        let mut bytes = [0u8; ID_LEN];
+        bytes[0] = 0xff;
+        bytes[1] = 0xe0;
+        assert_eq!(id.truncate(11), Id::from(bytes));
+
+        assert_eq!(id.truncate(0), Id::from([0; ID_LEN]));
+        assert_eq!(id.truncate(ID_LEN * 8), id);
+    }
+
+    #[test]
+    fn negative_insecure_id() {
+        let ip: IpAddr = "124.31.75.21".parse().unwrap();
+        let id = Id::secure(ip);
+
+        assert!(!id.flip_bit(0).is_secure(ip));
+        assert!(!id.flip_bit(20).is_secure(ip));
+    }
+
+    #[test]
+    fn positive_local_ip_exempt_from_secure_id() {
+        let id = Id::from([0u8; ID_LEN]);
+
+        assert!(id.is_secure("127.0.0.1".parse().unwrap()));
+        assert!(id.is_secure("192.168.1.10".parse().unwrap()));
+        assert!(id.is_secure("fd00::1".parse().unwrap()));
+        assert!(!id.is_secure("124.31.75.21".parse().unwrap()));
+    }
+
     #[test]
     fn positive_one_trailing_zero() {
         let zero_bits = Id::from([0u8; ID_LEN]);
@@ -0,0 +1,243 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use crate::id;
+
+const MAX_VOTERS: usize = 64;
+const MIN_VOTES: usize = 3;
+const VOTE_EXPIRATION_SEC: u64 = 30 * 60;
+
+/// Determines our external address from the addresses remote nodes report back to us in the `ip`
+/// field of their responses (BEP42).
+///
+/// Every remote ip gets a single vote (the most recent one) so a single node can't skew the result.
+/// The consensus is the address with the most votes, provided it has at least `MIN_VOTES` of them.
+/// Only responses to our own requests are counted. Votes expire after `VOTE_EXPIRATION_SEC` and
+/// the consensus is dropped as soon as the votes left for it fall below `MIN_VOTES`.
+pub struct IpVoter {
+    votes: HashMap<IpAddr, Vote>,
+    consensus: Option<SocketAddr>,
+}
+
+impl IpVoter {
+    /// Create a new IpVoter object.
+    pub fn new() -> IpVoter {
+        IpVoter {
+            votes: HashMap::new(),
+            consensus: None,
+        }
+    }
+
+    /// Record that the node at `voter` sees us as `addr`. Returns true if this changed the
+    /// consensus, false otherwise.
+    pub fn add_vote(&mut self, voter: IpAddr, addr: SocketAddr) -> bool {
+        self.add(voter, addr, Instant::now())
+    }
+
+    fn add(&mut self, voter: IpAddr, addr: SocketAddr, curr_time: Instant) -> bool {
+        // Nodes on the same local network can only tell us our local address.
+        if addr.ip().is_unspecified() || id::is_local_ip(addr.ip()) {
+            return false;
+        }
+
+        self.votes.retain(|_, vote| !vote.is_expired(curr_time));
+
+        if self.votes.len() >= MAX_VOTERS && !self.votes.contains_key(&voter) {
+            // Make room by dropping the oldest vote
+            let oldest = self
+                .votes
+                .iter()
+                .min_by_key(|(_, vote)| vote.time)
+                .map(|(voter, _)| *voter);
+
+            if let Some(oldest) = oldest {
+                self.votes.remove(&oldest);
+            }
+        }
+
+        self.votes.insert(
+            voter,
+            Vote {
+                addr,
+                time: curr_time,
+            },
+        );
+
+        self.tally()
+    }
+
+    /// Drop the expired votes. Returns true if this changed the consensus to a different address,
+    /// false otherwise (including when the consensus was lost).
+    pub fn remove_expired_votes(&mut self) -> bool {
+        self.remove_expired(Instant::now())
+    }
+
+    fn remove_expired(&mut self, curr_time: Instant) -> bool {
+        let num_votes = self.votes.len();
+        self.votes.retain(|_, vote| !vote.is_expired(curr_time));
+
+        num_votes != self.votes.len() && self.tally()
+    }
+
+    /// Our external address as agreed upon by the remote nodes, if any.
+    pub fn external_addr(&self) -> Option<SocketAddr> {
+        self.consensus
+    }
+
+    fn tally(&mut self) -> bool {
+        let mut counts = HashMap::new();
+        for vote in self.votes.values() {
+            *counts.entry(vote.addr).or_insert(0) += 1;
+        }
+
+        let current = self
+            .consensus
+            .and_then(|addr| counts.get(&addr).copied())
+            .unwrap_or(0);
+
+        // Only switch to a different address if it's strictly ahead of the current one.
+        let consensus = counts
+            .into_iter()
+            .filter(|(_, count)| *count >= MIN_VOTES && *count > current)
+            .max_by_key(|(_, count)| *count)
+            .map(|(addr, _)| addr);
+
+        if consensus.is_some() {
+            self.consensus = consensus;
+            true
+        } else {
+            // Not enough nodes agree on the current address anymore.
+            if current < MIN_VOTES {
+                self.consensus = None;
+            }
+            false
+        }
+    }
+}
+
+struct Vote {
+    addr: SocketAddr,
+    time: Instant,
+}
+
+impl Vote {
+    fn is_expired(&self, now: Instant) -> bool {
+        now - self.time >= Duration::from_secs(VOTE_EXPIRATION_SEC)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn voter(n: u8) -> IpAddr {
+        Ipv4Addr::new(1, 1, 1, n).into()
+    }
+
+    #[test]
+    fn positive_consensus_after_min_votes() {
+        let mut ip_voter = IpVoter::new();
+        let addr: SocketAddr = (Ipv4Addr::new(8, 8, 8, 8), 6881).into();
+        let time = Instant::now();
+
+        for n in 0..(MIN_VOTES - 1) {
+            assert!(!ip_voter.add(voter(n as u8), addr, time));
+            assert_eq!(ip_voter.external_addr(), None);
+        }
+
+        assert!(ip_voter.add(voter(MIN_VOTES as u8), addr, time));
+        assert_eq!(ip_voter.external_addr(), Some(addr));
+
+        // More votes for the same address don't change anything.
+        assert!(!ip_voter.add(voter(100), addr, time));
+    }
+
+    #[test]
+    fn positive_consensus_changes_to_majority() {
+        let mut ip_voter = IpVoter::new();
+        let old_addr: SocketAddr = (Ipv4Addr::new(8, 8, 8, 8), 6881).into();
+        let new_addr: SocketAddr = (Ipv4Addr::new(9, 9, 9, 9), 6881).into();
+        let time = Instant::now();
+
+        for n in 0..MIN_VOTES {
+            ip_voter.add(voter(n as u8), old_addr, time);
+        }
+
+        // A tie does not change the consensus.
+        for n in 0..MIN_VOTES {
+            assert!(!ip_voter.add(voter(100 + n as u8), new_addr, time));
+        }
+        assert_eq!(ip_voter.external_addr(), Some(old_addr));
+
+        // A voter changing its mind does.
+        assert!(ip_voter.add(voter(0), new_addr, time));
+        assert_eq!(ip_voter.external_addr(), Some(new_addr));
+    }
+
+    #[test]
+    fn negative_single_voter_counts_once() {
+        let mut ip_voter = IpVoter::new();
+        let addr: SocketAddr = (Ipv4Addr::new(8, 8, 8, 8), 6881).into();
+        let time = Instant::now();
+
+        for _ in 0..MIN_VOTES * 2 {
+            assert!(!ip_voter.add(voter(0), addr, time));
+        }
+
+        assert_eq!(ip_voter.external_addr(), None);
+    }
+
+    #[test]
+    fn negative_ignore_local_addr() {
+        let mut ip_voter = IpVoter::new();
+        let addr: SocketAddr = (Ipv4Addr::new(192, 168, 1, 2), 6881).into();
+        let time = Instant::now();
+
+        for n in 0..MIN_VOTES {
+            assert!(!ip_voter.add(voter(n as u8), addr, time));
+        }
+
+        assert_eq!(ip_voter.external_addr(), None);
+    }
+
+    #[test]
+    fn negative_expired_votes_dropped() {
+        let mut ip_voter = IpVoter::new();
+        let addr: SocketAddr = (Ipv4Addr::new(8, 8, 8, 8), 6881).into();
+        let time = Instant::now();
+
+        for n in 0..(MIN_VOTES - 1) {
+            ip_voter.add(voter(n as u8), addr, time);
+        }
+
+        let later = time + Duration::from_secs(VOTE_EXPIRATION_SEC);
+        assert!(!ip_voter.add(voter(100), addr, later));
+        assert_eq!(ip_voter.external_addr(), None);
+    }
+
+    #[test]
+    fn negative_consensus_dropped_below_min_votes() {
+        let mut ip_voter = IpVoter::new();
+        let addr: SocketAddr = (Ipv4Addr::new(8, 8, 8, 8), 6881).into();
+        let time = Instant::now();
+
+        for n in 0..MIN_VOTES {
+            ip_voter.add(voter(n as u8), addr, time);
+        }
+        assert_eq!(ip_voter.external_addr(), Some(addr));
+
+        // One vote expiring is enough to lose the consensus.
+        let later = time + Duration::from_secs(VOTE_EXPIRATION_SEC / 2);
+        ip_voter.add(voter(100), addr, later);
+        assert!(!ip_voter.remove_expired(time + Duration::from_secs(VOTE_EXPIRATION_SEC)));
+        assert_eq!(ip_voter.external_addr(), None);
+
+        // And it is back with enough new votes.
+        let later = time + Duration::from_secs(VOTE_EXPIRATION_SEC);
+        assert!(!ip_voter.add(voter(101), addr, later));
+        assert!(ip_voter.add(voter(102), addr, later));
+        assert_eq!(ip_voter.external_addr(), Some(addr));
+    }
+}
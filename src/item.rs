@@ -0,0 +1,291 @@
+//! Items stored in the DHT as per [BEP44](https://www.bittorrent.org/beps/bep_0044.html).
+
+use crate::id::InfoHash;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde_bencode::value::Value;
+use std::convert::TryInto;
+use thiserror::Error;
+
+/// Maximum length of the bencoded value of an item.
+pub const MAX_VALUE_LEN: usize = 1000;
+/// Maximum length of the salt of a mutable item.
+pub const MAX_SALT_LEN: usize = 64;
+
+/// Item as sent in a put request.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) enum Item {
+    Immutable(Value),
+    Mutable(Box<MutableItem>),
+}
+
+impl Item {
+    pub fn value(&self) -> &Value {
+        match self {
+            Self::Immutable(value) => value,
+            Self::Mutable(item) => &item.value,
+        }
+    }
+
+    pub fn target(&self) -> InfoHash {
+        match self {
+            Self::Immutable(value) => immutable_target(value),
+            Self::Mutable(item) => item.target(),
+        }
+    }
+}
+
+/// Mutable item, signed by the owner of a ed25519 keypair.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MutableItem {
+    value: Value,
+    seq: i64,
+    salt: Vec<u8>,
+    key: VerifyingKey,
+    signature: Signature,
+}
+
+impl MutableItem {
+    /// Create a mutable item and sign it with the given keypair. Empty salt means no salt. Fails
+    /// with [`PutError::SaltTooBig`] if the salt is longer than [`MAX_SALT_LEN`].
+    pub fn sign(
+        keypair: &SigningKey,
+        salt: Vec<u8>,
+        seq: i64,
+        value: Value,
+    ) -> Result<Self, PutError> {
+        if salt.len() > MAX_SALT_LEN {
+            return Err(PutError::SaltTooBig);
+        }
+
+        let signature = keypair.sign(&signable(&salt, seq, &value));
+
+        Ok(Self {
+            value,
+            seq,
+            salt,
+            key: keypair.verifying_key(),
+            signature,
+        })
+    }
+
+    /// Create a mutable item out of its raw parts as received from a remote node. Returns `None`
+    /// if the key or the signature are malformed or if the signature doesn't match.
+    pub(crate) fn verify(
+        key: &[u8],
+        signature: &[u8],
+        salt: Vec<u8>,
+        seq: i64,
+        value: Value,
+    ) -> Option<Self> {
+        let key = VerifyingKey::from_bytes(key.try_into().ok()?).ok()?;
+        let signature = Signature::from_slice(signature).ok()?;
+
+        key.verify(&signable(&salt, seq, &value), &signature).ok()?;
+
+        Some(Self {
+            value,
+            seq,
+            salt,
+            key,
+            signature,
+        })
+    }
+
+    /// The target to lookup the item by.
+    pub fn target(&self) -> InfoHash {
+        mutable_target(&self.key, &self.salt)
+    }
+
+    pub fn value(&self) -> &Value {
+        &self.value
+    }
+
+    pub fn seq(&self) -> i64 {
+        self.seq
+    }
+
+    pub fn salt(&self) -> &[u8] {
+        &self.salt
+    }
+
+    pub fn key(&self) -> &VerifyingKey {
+        &self.key
+    }
+
+    pub fn signature(&self) -> &Signature {
+        &self.signature
+    }
+}
+
+/// Bencode the value of an item.
+pub(crate) fn encode_value(value: &Value) -> Vec<u8> {
+    // `expect` is fine here, serializing a `Value` into a `Vec` can't fail.
+    serde_bencode::to_bytes(value).expect("failed to serialize value")
+}
+
+/// Target (the key to lookup by) of an immutable item, which is the SHA-1 hash of its bencoded
+/// value.
+pub(crate) fn immutable_target(value: &Value) -> InfoHash {
+    InfoHash::sha1(&encode_value(value))
+}
+
+/// Target of a mutable item, which is the SHA-1 hash of its public key concatenated with the salt.
+pub(crate) fn mutable_target(key: &VerifyingKey, salt: &[u8]) -> InfoHash {
+    let mut input = key.to_bytes().to_vec();
+    input.extend_from_slice(salt);
+
+    InfoHash::sha1(&input)
+}
+
+// The buffer to sign: the bencoded salt (if any), seq and value, as if they were entries of a
+// dictionary but without the enclosing "d" and "e".
+fn signable(salt: &[u8], seq: i64, value: &Value) -> Vec<u8> {
+    let mut buffer = Vec::new();
+
+    if !salt.is_empty() {
+        buffer.extend_from_slice(format!("4:salt{}:", salt.len()).as_bytes());
+        buffer.extend_from_slice(salt);
+    }
+
+    buffer.extend_from_slice(format!("3:seqi{}e1:v", seq).as_bytes());
+    buffer.extend_from_slice(&encode_value(value));
+    buffer
+}
+
+/// Error returned from the `put` operations of [`MainlineDht`](crate::MainlineDht).
+#[derive(Debug, Error)]
+pub enum PutError {
+    /// The bencoded value is longer than [`MAX_VALUE_LEN`].
+    #[error("value too big")]
+    ValueTooBig,
+    /// The salt is longer than [`MAX_SALT_LEN`].
+    #[error("salt too big")]
+    SaltTooBig,
+    /// The item stored in the DHT has a different sequence number than the expected one (the
+    /// `cas` argument).
+    #[error("CAS mismatch")]
+    CasMismatch,
+    /// The item stored in the DHT has a higher sequence number than the one being put.
+    #[error("sequence number less than current")]
+    SeqTooLow,
+    /// None of the nodes closest to the target accepted the item.
+    #[error("item not stored")]
+    NotStored,
+    /// A node refused to store the item.
+    #[error("item rejected: {message} ({code})")]
+    Rejected { code: u16, message: String },
+    /// The DHT has shut down.
+    #[error("DhtHandler has shut down")]
+    Shutdown,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positive_immutable_target() {
+        // Test vector from BEP44.
+        let value = Value::Bytes(b"Hello World!".to_vec());
+        let expected = hex::decode("e5f96f6f38320f0f33959cb4d3d656452117aadb").unwrap();
+
+        assert_eq!(immutable_target(&value).as_ref(), &expected[..]);
+    }
+
+    #[test]
+    fn positive_verify_mutable() {
+        // Test vectors from BEP44.
+        let key = hex::decode("77ff84905a91936367c01360803104f92432fcd904a43511876df5cdf3e7e548")
+            .unwrap();
+        let vectors = [
+            (
+                &b""[..],
+                "305ac8aeb6c9c151fa120f120ea2cfb923564e11552d06a5d856091e5e853cff\
+                 1260d3f39e4999684aa92eb73ffd136e6f4f3ecbfda0ce53a1608ecd7ae21f01",
+                "4a533d47ec9c7d95b1ad75f576cffc641853b750",
+            ),
+            (
+                &b"foobar"[..],
+                "6834284b6b24c3204eb2fea824d82f88883a3d95e8b4a21b8c0ded553d17d17d\
+                 df9a8a7104b1258f30bed3787e6cb896fca78c58f8e03b5f18f14951a87d9a08",
+                "411eba73b6f087ca51a3795d9c8c938d365e32c1",
+            ),
+        ];
+
+        for (salt, signature, target) in vectors {
+            let item = MutableItem::verify(
+                &key,
+                &hex::decode(signature).unwrap(),
+                salt.to_vec(),
+                1,
+                Value::Bytes(b"Hello World!".to_vec()),
+            )
+            .unwrap();
+
+            assert_eq!(item.target().as_ref(), &hex::decode(target).unwrap()[..]);
+        }
+    }
+
+    #[test]
+    fn positive_sign_and_verify() {
+        let keypair = SigningKey::from_bytes(&[7; 32]);
+        let item = MutableItem::sign(&keypair, b"salt".to_vec(), 3, Value::Int(42)).unwrap();
+
+        let verified = MutableItem::verify(
+            item.key().as_bytes(),
+            &item.signature().to_bytes(),
+            item.salt().to_vec(),
+            item.seq(),
+            item.value().clone(),
+        );
+
+        assert_eq!(verified, Some(item));
+    }
+
+    #[test]
+    fn negative_sign_salt_too_big() {
+        let keypair = SigningKey::from_bytes(&[7; 32]);
+
+        assert!(MutableItem::sign(&keypair, vec![0; MAX_SALT_LEN], 1, Value::Int(42)).is_ok());
+        assert!(matches!(
+            MutableItem::sign(&keypair, vec![0; MAX_SALT_LEN + 1], 1, Value::Int(42)),
+            Err(PutError::SaltTooBig)
+        ));
+    }
+
+    #[test]
+    fn negative_verify_tampered() {
+        let keypair = SigningKey::from_bytes(&[7; 32]);
+        let item = MutableItem::sign(&keypair, vec![], 3, Value::Int(42)).unwrap();
+
+        // Different seq
+        assert!(MutableItem::verify(
+            item.key().as_bytes(),
+            &item.signature().to_bytes(),
+            vec![],
+            4,
+            item.value().clone(),
+        )
+        .is_none());
+
+        // Different value
+        assert!(MutableItem::verify(
+            item.key().as_bytes(),
+            &item.signature().to_bytes(),
+            vec![],
+            3,
+            Value::Int(43),
+        )
+        .is_none());
+
+        // Malformed key
+        assert!(MutableItem::verify(
+            &item.key().as_bytes()[1..],
+            &item.signature().to_bytes(),
+            vec![],
+            3,
+            item.value().clone(),
+        )
+        .is_none());
+    }
+}
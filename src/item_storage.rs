@@ -0,0 +1,359 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+
+use crate::id::InfoHash;
+use crate::item::{self, Item, MutableItem};
+use crate::message::{error_code, PutRequest};
+
+// Total size of all the items we store for other nodes.
+const MAX_STORAGE_SIZE: usize = 1024 * 1024;
+// Items are to be republished by their owners, so forget them if they are not.
+const EXPIRATION_TIME: Duration = Duration::from_secs(2 * 60 * 60);
+// Accounts for the key, signature, seq and bookkeeping of each item.
+const ITEM_OVERHEAD: usize = 128;
+
+/// Manages storage and expiration of BEP44 items put by other nodes.
+pub struct ItemStorage {
+    items: HashMap<InfoHash, StoredItem>,
+    max_size: usize,
+    size: usize,
+}
+
+impl ItemStorage {
+    /// Create a new ItemStorage object.
+    pub fn new() -> ItemStorage {
+        ItemStorage::with_max_size(MAX_STORAGE_SIZE)
+    }
+
+    fn with_max_size(max_size: usize) -> ItemStorage {
+        ItemStorage {
+            items: HashMap::new(),
+            max_size,
+            size: 0,
+        }
+    }
+
+    /// Validate the item from a put request and store it, replacing the currently stored item
+    /// under the same target, if any. If the storage is full, the least recently put items are
+    /// evicted to make room.
+    pub fn put(&mut self, request: PutRequest) -> Result<(), StoreError> {
+        let (item, cas) = validate(request)?;
+        self.add(item, cas, Instant::now())
+    }
+
+    fn add(&mut self, item: Item, cas: Option<i64>, curr_time: Instant) -> Result<(), StoreError> {
+        self.remove_expired_items(curr_time);
+
+        let target = item.target();
+
+        if let Some(stored) = self.items.get_mut(&target) {
+            if let (Item::Mutable(new), Item::Mutable(old)) = (&item, &stored.item) {
+                if cas.map(|cas| cas != old.seq()).unwrap_or(false) {
+                    return Err(StoreError::CasMismatch);
+                }
+
+                if new.seq() < old.seq() || (new.seq() == old.seq() && new.value() != old.value()) {
+                    return Err(StoreError::SeqTooLow);
+                }
+            }
+
+            // Same item put again or a newer version of a mutable one.
+            let stored = self.remove(&target);
+            debug_assert!(stored.is_some());
+        }
+
+        let item = StoredItem::new(item, curr_time);
+
+        while self.size + item.size > self.max_size && !self.items.is_empty() {
+            self.evict_oldest();
+        }
+
+        self.size += item.size;
+        self.items.insert(target, item);
+
+        Ok(())
+    }
+
+    /// Returns the item stored under the given target, if any.
+    pub fn get(&mut self, target: &InfoHash) -> Option<&Item> {
+        self.find(target, Instant::now())
+    }
+
+    fn find(&mut self, target: &InfoHash, curr_time: Instant) -> Option<&Item> {
+        if self
+            .items
+            .get(target)
+            .map(|stored| stored.is_expired(curr_time))
+            .unwrap_or(false)
+        {
+            self.remove(target);
+        }
+
+        self.items.get(target).map(|stored| &stored.item)
+    }
+
+    fn remove(&mut self, target: &InfoHash) -> Option<StoredItem> {
+        let stored = self.items.remove(target)?;
+        self.size -= stored.size;
+        Some(stored)
+    }
+
+    fn evict_oldest(&mut self) {
+        let oldest = self
+            .items
+            .iter()
+            .min_by_key(|(_, stored)| stored.inserted)
+            .map(|(target, _)| *target);
+
+        if let Some(oldest) = oldest {
+            self.remove(&oldest);
+        }
+    }
+
+    /// Prunes all expired items.
+    fn remove_expired_items(&mut self, curr_time: Instant) {
+        let size = &mut self.size;
+
+        self.items.retain(|_, stored| {
+            if stored.is_expired(curr_time) {
+                *size -= stored.size;
+                false
+            } else {
+                true
+            }
+        });
+    }
+}
+
+// Check the put request is valid as per BEP44 and extract the item and the `cas` out of it.
+fn validate(request: PutRequest) -> Result<(Item, Option<i64>), StoreError> {
+    if item::encode_value(&request.v).len() > item::MAX_VALUE_LEN {
+        return Err(StoreError::ValueTooBig);
+    }
+
+    let (key, signature, seq) = match (request.k, request.sig, request.seq) {
+        (Some(key), Some(signature), Some(seq)) => (key, signature, seq),
+        (None, None, None) => return Ok((Item::Immutable(request.v), None)),
+        _ => return Err(StoreError::InvalidSignature),
+    };
+
+    let salt = request.salt.unwrap_or_default();
+    if salt.len() > item::MAX_SALT_LEN {
+        return Err(StoreError::SaltTooBig);
+    }
+
+    let item = MutableItem::verify(&key, &signature, salt, seq, request.v)
+        .ok_or(StoreError::InvalidSignature)?;
+
+    Ok((Item::Mutable(Box::new(item)), request.cas))
+}
+
+/// Reason for rejecting a put request, with the messages suggested by BEP44.
+#[derive(Debug, Error, Eq, PartialEq)]
+pub enum StoreError {
+    #[error("message (v field) too big")]
+    ValueTooBig,
+    #[error("invalid signature")]
+    InvalidSignature,
+    #[error("salt (salt field) too big")]
+    SaltTooBig,
+    #[error("the CAS hash mismatched, re-read value and try again")]
+    CasMismatch,
+    #[error("sequence number less than current")]
+    SeqTooLow,
+}
+
+impl StoreError {
+    /// The code to send in the error message.
+    pub fn code(&self) -> u16 {
+        match self {
+            Self::ValueTooBig => error_code::VALUE_TOO_BIG,
+            Self::InvalidSignature => error_code::INVALID_SIGNATURE,
+            Self::SaltTooBig => error_code::SALT_TOO_BIG,
+            Self::CasMismatch => error_code::CAS_MISMATCH,
+            Self::SeqTooLow => error_code::SEQ_TOO_LOW,
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------//
+
+struct StoredItem {
+    item: Item,
+    size: usize,
+    inserted: Instant,
+}
+
+impl StoredItem {
+    fn new(item: Item, inserted: Instant) -> Self {
+        let salt_len = match &item {
+            Item::Immutable(_) => 0,
+            Item::Mutable(item) => item.salt().len(),
+        };
+        let size = item::encode_value(item.value()).len() + salt_len + ITEM_OVERHEAD;
+
+        Self {
+            item,
+            size,
+            inserted,
+        }
+    }
+
+    fn is_expired(&self, now: Instant) -> bool {
+        now - self.inserted >= EXPIRATION_TIME
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::id::NodeId;
+    use ed25519_dalek::SigningKey;
+    use serde_bencode::value::Value;
+
+    fn put_request(item: &Item, cas: Option<i64>) -> PutRequest {
+        let (k, sig, seq, salt) = match item {
+            Item::Immutable(_) => (None, None, None, None),
+            Item::Mutable(item) => (
+                Some(item.key().as_bytes().to_vec()),
+                Some(item.signature().to_bytes().to_vec()),
+                Some(item.seq()),
+                Some(item.salt().to_vec()),
+            ),
+        };
+
+        PutRequest {
+            id: NodeId::from([0; 20]),
+            token: vec![],
+            v: item.value().clone(),
+            k,
+            sig,
+            seq,
+            salt,
+            cas,
+        }
+    }
+
+    fn mutable(seq: i64, value: &str) -> Item {
+        let keypair = SigningKey::from_bytes(&[1; 32]);
+        Item::Mutable(Box::new(
+            MutableItem::sign(&keypair, b"salt".to_vec(), seq, value.into()).unwrap(),
+        ))
+    }
+
+    #[test]
+    fn positive_put_and_get_immutable() {
+        let mut storage = ItemStorage::new();
+        let item = Item::Immutable("foo".into());
+
+        assert_eq!(storage.put(put_request(&item, None)), Ok(()));
+        assert_eq!(storage.get(&item.target()), Some(&item));
+        assert_eq!(storage.get(&InfoHash::sha1(b"bar")), None);
+    }
+
+    #[test]
+    fn positive_put_newer_mutable() {
+        let mut storage = ItemStorage::new();
+
+        let item = mutable(1, "foo");
+        assert_eq!(storage.put(put_request(&item, None)), Ok(()));
+
+        let item = mutable(2, "bar");
+        assert_eq!(storage.put(put_request(&item, Some(1))), Ok(()));
+        assert_eq!(storage.get(&item.target()), Some(&item));
+    }
+
+    #[test]
+    fn negative_put_older_mutable() {
+        let mut storage = ItemStorage::new();
+        let item = mutable(2, "foo");
+        assert_eq!(storage.put(put_request(&item, None)), Ok(()));
+
+        assert_eq!(
+            storage.put(put_request(&mutable(1, "bar"), None)),
+            Err(StoreError::SeqTooLow)
+        );
+        assert_eq!(
+            storage.put(put_request(&mutable(2, "bar"), None)),
+            Err(StoreError::SeqTooLow)
+        );
+        // Same seq and same value is fine.
+        assert_eq!(storage.put(put_request(&item, None)), Ok(()));
+
+        assert_eq!(storage.get(&item.target()), Some(&item));
+    }
+
+    #[test]
+    fn negative_put_cas_mismatch() {
+        let mut storage = ItemStorage::new();
+        let item = mutable(2, "foo");
+        assert_eq!(storage.put(put_request(&item, None)), Ok(()));
+
+        assert_eq!(
+            storage.put(put_request(&mutable(3, "bar"), Some(1))),
+            Err(StoreError::CasMismatch)
+        );
+        assert_eq!(storage.get(&item.target()), Some(&item));
+    }
+
+    #[test]
+    fn negative_put_invalid() {
+        let mut storage = ItemStorage::new();
+
+        // Value too big
+        let item = Item::Immutable(Value::Bytes(vec![0; item::MAX_VALUE_LEN]));
+        assert_eq!(
+            storage.put(put_request(&item, None)),
+            Err(StoreError::ValueTooBig)
+        );
+
+        // Invalid signature
+        let mut request = put_request(&mutable(1, "foo"), None);
+        request.v = "bar".into();
+        assert_eq!(storage.put(request), Err(StoreError::InvalidSignature));
+
+        // Salt too big
+        let mut request = put_request(&mutable(1, "foo"), None);
+        request.salt = Some(vec![0; item::MAX_SALT_LEN + 1]);
+        assert_eq!(storage.put(request), Err(StoreError::SaltTooBig));
+    }
+
+    #[test]
+    fn positive_evict_oldest_when_full() {
+        let item_a = Item::Immutable("a".into());
+        let item_b = Item::Immutable("b".into());
+        let item_c = Item::Immutable("c".into());
+
+        // Room for two items only
+        let mut storage = ItemStorage::with_max_size(2 * (3 + ITEM_OVERHEAD));
+        let time = Instant::now();
+
+        assert_eq!(storage.add(item_a.clone(), None, time), Ok(()));
+        assert_eq!(
+            storage.add(item_b.clone(), None, time + Duration::from_secs(1)),
+            Ok(())
+        );
+        assert_eq!(
+            storage.add(item_c.clone(), None, time + Duration::from_secs(2)),
+            Ok(())
+        );
+
+        assert_eq!(storage.find(&item_a.target(), time), None);
+        assert_eq!(storage.find(&item_b.target(), time), Some(&item_b));
+        assert_eq!(storage.find(&item_c.target(), time), Some(&item_c));
+    }
+
+    #[test]
+    fn positive_expire_items() {
+        let mut storage = ItemStorage::new();
+        let item = Item::Immutable("foo".into());
+        let time = Instant::now();
+
+        assert_eq!(storage.add(item.clone(), None, time), Ok(()));
+        assert_eq!(storage.find(&item.target(), time), Some(&item));
+        assert_eq!(storage.find(&item.target(), time + EXPIRATION_TIME), None);
+        assert_eq!(storage.size, 0);
+    }
+}
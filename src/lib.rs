@@ -2,9 +2,10 @@
 
 // Mainline DHT extensions supported on behalf of libtorrent:
 // - Always send 'nodes' on a get_peers response even if 'values' is present
-// - Unrecognized requests which contain either an 'info_hash' or 'target' arguments are interpreted as 'find_node' TODO
-// - Client identification will be present in all outgoing messages in the form of the 'v' key TODO
-// const CLIENT_IDENTIFICATION: &'static [u8] = &[b'B', b'I', b'P', 0, 1];
+// - Node ids are derived from the external IP address and verified as per BEP42
+// - Unrecognized requests which contain either an 'info_hash' or 'target' arguments are interpreted as 'find_node'
+// - Client identification will be present in all outgoing messages in the form of the 'v' key
+const CLIENT_IDENTIFICATION: &[u8] = &[b'B', b'I', b'P', 0, 1];
 
 // TODO: The Vuze dht operates over a protocol that is different than the mainline dht.
 // It would be possible to create a dht client that can work over both dhts simultaneously,
@@ -15,11 +16,18 @@
 
 pub mod router;
 
+mod announce;
+mod bloom;
 mod builder;
 mod compact;
+mod config;
 mod id;
+mod ip_voter;
+mod item;
+mod item_storage;
 mod message;
 mod routing;
+mod sample;
 mod storage;
 #[cfg(test)]
 mod test;
@@ -27,11 +35,26 @@ mod token;
 mod transaction;
 mod worker;
 
-pub use crate::builder::{DhtBuilder, MainlineDht};
+pub use crate::announce::{AnnounceMode, AnnounceOptions, Announcement};
+pub use crate::bloom::ScrapeResult;
+pub use crate::builder::{
+    DhtBuilder, DhtShutdown, EventStream, ExternalAddrStream, LookupStream, MainlineDht,
+    SampleStream,
+};
+pub use crate::config::{ConfigError, DhtConfig, MAX_BUCKET_SIZE};
 pub use crate::id::{InfoHash, LengthError, NodeId, INFO_HASH_LEN};
-pub use crate::worker::State;
-
-pub type IpVersion = crate::worker::IpVersion;
+pub use crate::item::{MutableItem, PutError, MAX_SALT_LEN, MAX_VALUE_LEN};
+pub use crate::routing::{
+    node::{NodeHandle, NodeStatus},
+    snapshot::{BucketInfo, NodeInfo, NodeSnapshot, RoutingTableInfo, RoutingTableSnapshot},
+};
+pub use crate::sample::{InfoHashSample, InfoHashWalker};
+pub use crate::worker::{
+    DhtEvent, IpVersion, LookupEvent, LookupSummary, MessageCounts, PingError, PingResponse, State,
+    Stats,
+};
+pub use ed25519_dalek::{SigningKey, VerifyingKey};
+pub use serde_bencode::value::Value;
 
 use async_trait::async_trait;
 use std::{io, net::SocketAddr};
@@ -1,4 +1,5 @@
 use crate::{
+    bloom::BloomFilter,
     compact,
     id::{InfoHash, NodeId},
     routing::node::NodeHandle,
@@ -8,12 +9,30 @@ use serde::{
     ser::{SerializeSeq, Serializer},
     Deserialize, Serialize,
 };
+use serde_bencode::value::Value;
 use std::{fmt, net::SocketAddr};
 
 #[derive(Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub(crate) struct Message {
     #[serde(rename = "t", with = "serde_bytes")]
     pub transaction_id: Vec<u8>,
+    // Set on requests from nodes that don't respond to requests themselves (BEP43).
+    #[serde(
+        rename = "ro",
+        default,
+        skip_serializing_if = "is_false",
+        deserialize_with = "deserialize_bool"
+    )]
+    pub read_only: bool,
+    // Identification of the client software of the sender. Set on all outgoing messages by
+    // `Socket::send`.
+    #[serde(
+        rename = "v",
+        with = "serde_bytes",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub version: Option<Vec<u8>>,
     #[serde(flatten)]
     pub body: MessageBody,
 }
@@ -36,6 +55,8 @@ impl fmt::Debug for Message {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("Message")
             .field("transaction_id", &HexFmt(&self.transaction_id))
+            .field("read_only", &self.read_only)
+            .field("version", &self.version.as_deref().map(HexFmt))
             .field("body", &self.body)
             .finish()
     }
@@ -56,9 +77,9 @@ impl fmt::Debug for HexFmt<'_> {
 #[derive(Clone, Eq, PartialEq, Serialize, Deserialize)]
 #[serde(tag = "y")]
 pub(crate) enum MessageBody {
-    #[serde(rename = "q")]
+    #[serde(rename = "q", with = "request")]
     Request(Request),
-    #[serde(rename = "r", with = "unflatten::response")]
+    #[serde(rename = "r", with = "response")]
     Response(Response),
     #[serde(rename = "e", with = "unflatten::error")]
     Error(Error),
@@ -71,6 +92,11 @@ impl fmt::Debug for MessageBody {
             Self::Request(Request::FindNode(r)) => r.fmt(f),
             Self::Request(Request::GetPeers(r)) => r.fmt(f),
             Self::Request(Request::AnnouncePeer(r)) => r.fmt(f),
+            Self::Request(Request::Get(r)) => r.fmt(f),
+            Self::Request(Request::Put(r)) => r.fmt(f),
+            Self::Request(Request::SampleInfohashes(r)) => r.fmt(f),
+            Self::Request(Request::Unknown(r)) => r.fmt(f),
+            Self::Request(Request::Malformed(r)) => r.fmt(f),
             Self::Response(r) => r.fmt(f),
             Self::Error(e) => e.fmt(f),
         }
@@ -107,12 +133,102 @@ mod unflatten {
         };
     }
 
-    impl_unflatten!(response, "r");
     impl_unflatten!(error, "e");
 }
 
-// TODO: unrecognized requests which contain either an 'info_hash' or 'target' arguments should be
-// interpreted as 'find_node' as per Mainline DHT extensions.
+// Like `unflatten`, but additionally moves the `ip` field of the response to the top level of the
+// message, which is where BEP42 puts it.
+mod response {
+    use super::Response;
+    use crate::compact;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::net::SocketAddr;
+
+    #[derive(Serialize, Deserialize)]
+    struct Wrapper<T> {
+        #[serde(rename = "r")]
+        field: T,
+        #[serde(
+            with = "compact::addr",
+            default,
+            skip_serializing_if = "Option::is_none"
+        )]
+        ip: Option<SocketAddr>,
+    }
+
+    pub(super) fn serialize<S: Serializer>(value: &Response, s: S) -> Result<S::Ok, S::Error> {
+        Wrapper {
+            field: value,
+            ip: value.ip,
+        }
+        .serialize(s)
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Response, D::Error> {
+        let wrapper = Wrapper::<Response>::deserialize(d)?;
+
+        Ok(Response {
+            ip: wrapper.ip,
+            ..wrapper.field
+        })
+    }
+}
+
+// Falls back to `Request::Unknown` if the method is not one of the known ones, or to
+// `Request::Malformed` if it is but its arguments are invalid.
+mod request {
+    use super::{MalformedRequest, Request, UnknownArgs, UnknownRequest};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    // The methods of `Request`.
+    const KNOWN_METHODS: &[&[u8]] = &[
+        b"ping",
+        b"find_node",
+        b"get_peers",
+        b"announce_peer",
+        b"get",
+        b"put",
+        b"sample_infohashes",
+    ];
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Repr {
+        Known(Request),
+        Unknown(UnknownRequest),
+        // Not even the arguments we could make use of are valid.
+        Method(MalformedRequest),
+    }
+
+    pub(super) fn serialize<S: Serializer>(value: &Request, s: S) -> Result<S::Ok, S::Error> {
+        value.serialize(s)
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Request, D::Error> {
+        let method = match Repr::deserialize(d)? {
+            Repr::Known(request) => return Ok(request),
+            Repr::Unknown(request) if !is_known(&request.method) => {
+                return Ok(Request::Unknown(request))
+            }
+            Repr::Unknown(UnknownRequest { method, .. }) => method,
+            Repr::Method(MalformedRequest { method }) => method,
+        };
+
+        if is_known(&method) {
+            Ok(Request::Malformed(MalformedRequest { method }))
+        } else {
+            Ok(Request::Unknown(UnknownRequest {
+                method,
+                args: UnknownArgs::default(),
+            }))
+        }
+    }
+
+    fn is_known(method: &[u8]) -> bool {
+        KNOWN_METHODS.contains(&method)
+    }
+}
+
 #[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
 #[serde(tag = "q", content = "a")]
 #[serde(rename_all = "snake_case")]
@@ -121,6 +237,16 @@ pub(crate) enum Request {
     FindNode(FindNodeRequest),
     GetPeers(GetPeersRequest),
     AnnouncePeer(AnnouncePeerRequest),
+    Get(GetRequest),
+    Put(PutRequest),
+    SampleInfohashes(SampleInfohashesRequest),
+    // Request of a method we don't understand. We only receive these, never send them.
+    #[serde(skip)]
+    Unknown(UnknownRequest),
+    // Request of a known method but with missing or invalid arguments. We only receive these,
+    // never send them.
+    #[serde(skip)]
+    Malformed(MalformedRequest),
 }
 
 #[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
@@ -144,6 +270,21 @@ pub(crate) struct GetPeersRequest {
 
     #[serde(with = "want", default, skip_serializing_if = "Option::is_none")]
     pub want: Option<Want>,
+
+    // Request the bloom filters of the seeds and the peers (BEP33).
+    #[serde(
+        default,
+        skip_serializing_if = "is_false",
+        deserialize_with = "deserialize_bool"
+    )]
+    pub scrape: bool,
+    // Don't include seeds in the values (BEP33).
+    #[serde(
+        default,
+        skip_serializing_if = "is_false",
+        deserialize_with = "deserialize_bool"
+    )]
+    pub noseed: bool,
 }
 
 #[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
@@ -154,6 +295,98 @@ pub(crate) struct AnnouncePeerRequest {
     pub port: Option<u16>,
     #[serde(with = "serde_bytes")]
     pub token: Vec<u8>,
+
+    // The announcing peer has the complete torrent (BEP33).
+    #[serde(
+        default,
+        skip_serializing_if = "is_false",
+        deserialize_with = "deserialize_bool"
+    )]
+    pub seed: bool,
+}
+
+// BEP44
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub(crate) struct GetRequest {
+    pub id: NodeId,
+    pub target: InfoHash,
+
+    // Only return the value if its sequence number is greater than this (mutable items only).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<i64>,
+}
+
+// BEP44
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub(crate) struct PutRequest {
+    pub id: NodeId,
+    #[serde(with = "serde_bytes")]
+    pub token: Vec<u8>,
+    pub v: Value,
+
+    // The rest is present only for mutable items.
+    #[serde(with = "serde_bytes", default, skip_serializing_if = "Option::is_none")]
+    pub k: Option<Vec<u8>>,
+    #[serde(with = "serde_bytes", default, skip_serializing_if = "Option::is_none")]
+    pub sig: Option<Vec<u8>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<i64>,
+    #[serde(with = "serde_bytes", default, skip_serializing_if = "Option::is_none")]
+    pub salt: Option<Vec<u8>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cas: Option<i64>,
+}
+
+// BEP51
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub(crate) struct SampleInfohashesRequest {
+    pub id: NodeId,
+    pub target: NodeId,
+
+    #[serde(with = "want", default, skip_serializing_if = "Option::is_none")]
+    pub want: Option<Want>,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Deserialize)]
+pub(crate) struct UnknownRequest {
+    #[serde(rename = "q", with = "serde_bytes")]
+    pub method: Vec<u8>,
+    #[serde(rename = "a", default)]
+    pub args: UnknownArgs,
+}
+
+impl UnknownRequest {
+    /// Unknown requests with either a `target` or an `info_hash` argument are to be interpreted as
+    /// `find_node` as per Mainline DHT extensions. Returns the request back if it has neither.
+    pub fn into_find_node(self) -> Result<FindNodeRequest, Self> {
+        match (self.args.id, self.args.target.or(self.args.info_hash)) {
+            (Some(id), Some(target)) => Ok(FindNodeRequest {
+                id,
+                target,
+                want: self.args.want,
+            }),
+            _ => Err(self),
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug, Deserialize)]
+pub(crate) struct MalformedRequest {
+    #[serde(rename = "q", with = "serde_bytes")]
+    pub method: Vec<u8>,
+}
+
+// The arguments of an unknown request we can make use of.
+#[derive(Clone, Eq, PartialEq, Debug, Default, Deserialize)]
+pub(crate) struct UnknownArgs {
+    #[serde(default)]
+    pub id: Option<NodeId>,
+    #[serde(default)]
+    pub target: Option<NodeId>,
+    #[serde(default)]
+    pub info_hash: Option<InfoHash>,
+    #[serde(with = "want", default)]
+    pub want: Option<Want>,
 }
 
 #[derive(Clone, Copy, Eq, PartialEq, Debug)]
@@ -231,6 +464,7 @@ mod want {
 }
 
 mod port {
+    use super::{deserialize_bool, is_false};
     use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
 
     #[derive(Serialize, Deserialize)]
@@ -265,15 +499,15 @@ mod port {
             Err(D::Error::missing_field("port"))
         }
     }
+}
 
-    fn is_false(b: &bool) -> bool {
-        !*b
-    }
+fn is_false(b: &bool) -> bool {
+    !*b
+}
 
-    fn deserialize_bool<'de, D: Deserializer<'de>>(d: D) -> Result<bool, D::Error> {
-        let num = u8::deserialize(d)?;
-        Ok(num > 0)
-    }
+fn deserialize_bool<'de, D: Deserializer<'de>>(d: D) -> Result<bool, D::Error> {
+    let num = u8::deserialize(d)?;
+    Ok(num > 0)
 }
 
 #[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
@@ -304,14 +538,74 @@ pub(crate) struct Response {
     )]
     pub nodes_v6: Vec<NodeHandle>,
 
-    // Only present in responses to GetPeers
+    // Only present in responses to GetPeers and Get
     #[serde(with = "serde_bytes", default, skip_serializing_if = "Option::is_none")]
     pub token: Option<Vec<u8>>,
+
+    // Only present in responses to Get, if the responder has the item.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub v: Option<Value>,
+
+    // Only present in responses to Get, if the responder has the item and it's mutable.
+    #[serde(with = "serde_bytes", default, skip_serializing_if = "Option::is_none")]
+    pub k: Option<Vec<u8>>,
+    #[serde(with = "serde_bytes", default, skip_serializing_if = "Option::is_none")]
+    pub sig: Option<Vec<u8>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<i64>,
+
+    // Only present in responses to SampleInfohashes: number of seconds the requester should wait
+    // before asking us again, number of info hashes we store and a random sample of them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub num: Option<i64>,
+    #[serde(
+        with = "compact::info_hashes",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub samples: Option<Vec<InfoHash>>,
+
+    // Only present in responses to GetPeers with the `scrape` flag (BEP33).
+    #[serde(rename = "BFsd", default, skip_serializing_if = "Option::is_none")]
+    pub bf_seeds: Option<BloomFilter>,
+    #[serde(rename = "BFpe", default, skip_serializing_if = "Option::is_none")]
+    pub bf_peers: Option<BloomFilter>,
+
+    // The address the request came from, as seen by the responder (BEP42). Encoded in the top
+    // level of the message, not inside the response.
+    #[serde(skip)]
+    pub ip: Option<SocketAddr>,
+}
+
+impl Response {
+    /// An empty response from the node with the given id. The fields specific to the method are
+    /// filled in with the struct update syntax.
+    pub fn new(id: NodeId) -> Self {
+        Self {
+            id,
+            values: vec![],
+            nodes_v4: vec![],
+            nodes_v6: vec![],
+            token: None,
+            v: None,
+            k: None,
+            sig: None,
+            seq: None,
+            interval: None,
+            num: None,
+            samples: None,
+            bf_seeds: None,
+            bf_peers: None,
+            ip: None,
+        }
+    }
 }
 
 #[derive(Clone, Eq, PartialEq, Debug)]
 pub(crate) struct Error {
-    pub code: u8,
+    pub code: u16,
     pub message: String,
 }
 
@@ -337,7 +631,7 @@ impl<'de> Deserialize<'de> for Error {
             }
 
             fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
-                let code: u8 = seq
+                let code: u16 = seq
                     .next_element()?
                     .ok_or_else(|| A::Error::invalid_length(0, &self))?;
                 let message = seq
@@ -361,10 +655,16 @@ pub mod error_code {
     // some of these codes are not used in this crate but we still list them here for completeness.
     #![allow(unused)]
 
-    pub const GENERIC_ERROR: u8 = 201;
-    pub const SERVER_ERROR: u8 = 202;
-    pub const PROTOCOL_ERROR: u8 = 203;
-    pub const METHOD_UNKNOWN: u8 = 204;
+    pub const GENERIC_ERROR: u16 = 201;
+    pub const SERVER_ERROR: u16 = 202;
+    pub const PROTOCOL_ERROR: u16 = 203;
+    pub const METHOD_UNKNOWN: u16 = 204;
+    // BEP44
+    pub const VALUE_TOO_BIG: u16 = 205;
+    pub const INVALID_SIGNATURE: u16 = 206;
+    pub const SALT_TOO_BIG: u16 = 207;
+    pub const CAS_MISMATCH: u16 = 301;
+    pub const SEQ_TOO_LOW: u16 = 302;
 }
 
 #[cfg(test)]
@@ -377,6 +677,8 @@ mod tests {
         let encoded = "d1:ad2:id20:abcdefghij0123456789e1:q4:ping1:t2:aa1:y1:qe";
         let decoded = Message {
             transaction_id: b"aa".to_vec(),
+            read_only: false,
+            version: None,
             body: MessageBody::Request(Request::Ping(PingRequest {
                 id: NodeId::from(*b"abcdefghij0123456789"),
             })),
@@ -385,11 +687,43 @@ mod tests {
         assert_serialize_deserialize(encoded, &decoded)
     }
 
+    #[test]
+    fn serialize_read_only_request() {
+        let encoded = "d1:ad2:id20:abcdefghij0123456789e1:q4:ping2:roi1e1:t2:aa1:y1:qe";
+        let decoded = Message {
+            transaction_id: b"aa".to_vec(),
+            read_only: true,
+            version: None,
+            body: MessageBody::Request(Request::Ping(PingRequest {
+                id: NodeId::from(*b"abcdefghij0123456789"),
+            })),
+        };
+
+        assert_serialize_deserialize(encoded, &decoded);
+    }
+
+    #[test]
+    fn serialize_request_with_version() {
+        let encoded = "d1:ad2:id20:abcdefghij0123456789e1:q4:ping1:t2:aa1:v4:LT\x01\x021:y1:qe";
+        let decoded = Message {
+            transaction_id: b"aa".to_vec(),
+            read_only: false,
+            version: Some(b"LT\x01\x02".to_vec()),
+            body: MessageBody::Request(Request::Ping(PingRequest {
+                id: NodeId::from(*b"abcdefghij0123456789"),
+            })),
+        };
+
+        assert_serialize_deserialize(encoded, &decoded);
+    }
+
     #[test]
     fn serialize_find_node_request() {
         let encoded = "d1:ad2:id20:abcdefghij01234567896:target20:mnopqrstuvwxyz123456e1:q9:find_node1:t2:aa1:y1:qe";
         let decoded = Message {
             transaction_id: b"aa".to_vec(),
+            read_only: false,
+            version: None,
             body: MessageBody::Request(Request::FindNode(FindNodeRequest {
                 id: NodeId::from(*b"abcdefghij0123456789"),
                 target: NodeId::from(*b"mnopqrstuvwxyz123456"),
@@ -405,6 +739,8 @@ mod tests {
         let encoded = "d1:ad2:id20:abcdefghij01234567896:target20:mnopqrstuvwxyz1234564:wantl2:n42:n6ee1:q9:find_node1:t2:aa1:y1:qe";
         let decoded = Message {
             transaction_id: b"aa".to_vec(),
+            read_only: false,
+            version: None,
             body: MessageBody::Request(Request::FindNode(FindNodeRequest {
                 id: NodeId::from(*b"abcdefghij0123456789"),
                 target: NodeId::from(*b"mnopqrstuvwxyz123456"),
@@ -420,10 +756,14 @@ mod tests {
         let encoded = "d1:ad2:id20:abcdefghij01234567899:info_hash20:mnopqrstuvwxyz123456e1:q9:get_peers1:t2:aa1:y1:qe";
         let decoded = Message {
             transaction_id: b"aa".to_vec(),
+            read_only: false,
+            version: None,
             body: MessageBody::Request(Request::GetPeers(GetPeersRequest {
                 id: NodeId::from(*b"abcdefghij0123456789"),
                 info_hash: InfoHash::from(*b"mnopqrstuvwxyz123456"),
                 want: None,
+                scrape: false,
+                noseed: false,
             })),
         };
 
@@ -435,10 +775,33 @@ mod tests {
         let encoded = "d1:ad2:id20:abcdefghij01234567899:info_hash20:mnopqrstuvwxyz1234564:wantl2:n4ee1:q9:get_peers1:t2:aa1:y1:qe";
         let decoded = Message {
             transaction_id: b"aa".to_vec(),
+            read_only: false,
+            version: None,
             body: MessageBody::Request(Request::GetPeers(GetPeersRequest {
                 id: NodeId::from(*b"abcdefghij0123456789"),
                 info_hash: InfoHash::from(*b"mnopqrstuvwxyz123456"),
                 want: Some(Want::V4),
+                scrape: false,
+                noseed: false,
+            })),
+        };
+
+        assert_serialize_deserialize(encoded, &decoded)
+    }
+
+    #[test]
+    fn serialize_get_peers_request_with_scrape() {
+        let encoded = "d1:ad2:id20:abcdefghij01234567899:info_hash20:mnopqrstuvwxyz1234566:noseedi1e6:scrapei1ee1:q9:get_peers1:t2:aa1:y1:qe";
+        let decoded = Message {
+            transaction_id: b"aa".to_vec(),
+            read_only: false,
+            version: None,
+            body: MessageBody::Request(Request::GetPeers(GetPeersRequest {
+                id: NodeId::from(*b"abcdefghij0123456789"),
+                info_hash: InfoHash::from(*b"mnopqrstuvwxyz123456"),
+                want: None,
+                scrape: true,
+                noseed: true,
             })),
         };
 
@@ -450,11 +813,14 @@ mod tests {
         let encoded = "d1:ad2:id20:abcdefghij012345678912:implied_porti1e9:info_hash20:mnopqrstuvwxyz1234565:token8:aoeusnthe1:q13:announce_peer1:t2:aa1:y1:qe";
         let decoded = Message {
             transaction_id: b"aa".to_vec(),
+            read_only: false,
+            version: None,
             body: MessageBody::Request(Request::AnnouncePeer(AnnouncePeerRequest {
                 id: NodeId::from(*b"abcdefghij0123456789"),
                 port: None,
                 info_hash: InfoHash::from(*b"mnopqrstuvwxyz123456"),
                 token: b"aoeusnth".to_vec(),
+                seed: false,
             })),
         };
 
@@ -466,11 +832,33 @@ mod tests {
         let encoded = "d1:ad2:id20:abcdefghij01234567899:info_hash20:mnopqrstuvwxyz1234564:porti6881e5:token8:aoeusnthe1:q13:announce_peer1:t2:aa1:y1:qe";
         let decoded = Message {
             transaction_id: b"aa".to_vec(),
+            read_only: false,
+            version: None,
+            body: MessageBody::Request(Request::AnnouncePeer(AnnouncePeerRequest {
+                id: NodeId::from(*b"abcdefghij0123456789"),
+                port: Some(6881),
+                info_hash: InfoHash::from(*b"mnopqrstuvwxyz123456"),
+                token: b"aoeusnth".to_vec(),
+                seed: false,
+            })),
+        };
+
+        assert_serialize_deserialize(encoded, &decoded);
+    }
+
+    #[test]
+    fn serialize_announce_peer_request_with_seed() {
+        let encoded = "d1:ad2:id20:abcdefghij01234567899:info_hash20:mnopqrstuvwxyz1234564:porti6881e4:seedi1e5:token8:aoeusnthe1:q13:announce_peer1:t2:aa1:y1:qe";
+        let decoded = Message {
+            transaction_id: b"aa".to_vec(),
+            read_only: false,
+            version: None,
             body: MessageBody::Request(Request::AnnouncePeer(AnnouncePeerRequest {
                 id: NodeId::from(*b"abcdefghij0123456789"),
                 port: Some(6881),
                 info_hash: InfoHash::from(*b"mnopqrstuvwxyz123456"),
                 token: b"aoeusnth".to_vec(),
+                seed: true,
             })),
         };
 
@@ -482,13 +870,9 @@ mod tests {
         let encoded = "d1:rd2:id20:mnopqrstuvwxyz123456e1:t2:aa1:y1:re";
         let decoded = Message {
             transaction_id: b"aa".to_vec(),
-            body: MessageBody::Response(Response {
-                id: NodeId::from(*b"mnopqrstuvwxyz123456"),
-                values: vec![],
-                nodes_v4: vec![],
-                nodes_v6: vec![],
-                token: None,
-            }),
+            read_only: false,
+            version: None,
+            body: MessageBody::Response(Response::new(NodeId::from(*b"mnopqrstuvwxyz123456"))),
         };
 
         assert_serialize_deserialize(encoded, &decoded);
@@ -500,15 +884,14 @@ mod tests {
             "d1:rd2:id20:0123456789abcdefghij5:nodes26:mnopqrstuvwxyz012345axje.ue1:t2:aa1:y1:re";
         let decoded = Message {
             transaction_id: b"aa".to_vec(),
+            read_only: false,
+            version: None,
             body: MessageBody::Response(Response {
-                id: NodeId::from(*b"0123456789abcdefghij"),
-                values: vec![],
                 nodes_v4: vec![NodeHandle {
                     id: NodeId::from(*b"mnopqrstuvwxyz012345"),
                     addr: (Ipv4Addr::new(97, 120, 106, 101), 11893).into(),
                 }],
-                nodes_v6: vec![],
-                token: None,
+                ..Response::new(NodeId::from(*b"0123456789abcdefghij"))
             }),
         };
 
@@ -521,10 +904,9 @@ mod tests {
             "d1:rd2:id20:0123456789abcdefghij6:nodes638:mnopqrstuvwxyz012345abcdefghijklmnop.ue1:t2:aa1:y1:re";
         let decoded = Message {
             transaction_id: b"aa".to_vec(),
+            read_only: false,
+            version: None,
             body: MessageBody::Response(Response {
-                id: NodeId::from(*b"0123456789abcdefghij"),
-                values: vec![],
-                nodes_v4: vec![],
                 nodes_v6: vec![NodeHandle {
                     id: NodeId::from(*b"mnopqrstuvwxyz012345"),
                     addr: (
@@ -535,7 +917,7 @@ mod tests {
                     )
                         .into(),
                 }],
-                token: None,
+                ..Response::new(NodeId::from(*b"0123456789abcdefghij"))
             }),
         };
 
@@ -548,9 +930,9 @@ mod tests {
             "d1:rd2:id20:0123456789abcdefghij5:nodes26:mnopqrstuvwxyz012345axje.u6:nodes638:6789abcdefghijklmnopabcdefghijklmnop.ue1:t2:aa1:y1:re";
         let decoded = Message {
             transaction_id: b"aa".to_vec(),
+            read_only: false,
+            version: None,
             body: MessageBody::Response(Response {
-                id: NodeId::from(*b"0123456789abcdefghij"),
-                values: vec![],
                 nodes_v4: vec![NodeHandle {
                     id: NodeId::from(*b"mnopqrstuvwxyz012345"),
                     addr: (Ipv4Addr::new(97, 120, 106, 101), 11893).into(),
@@ -565,7 +947,7 @@ mod tests {
                     )
                         .into(),
                 }],
-                token: None,
+                ..Response::new(NodeId::from(*b"0123456789abcdefghij"))
             }),
         };
 
@@ -577,15 +959,15 @@ mod tests {
         let encoded = "d1:rd2:id20:abcdefghij01234567895:token8:aoeusnth6:valuesl6:axje.u6:idhtnmee1:t2:aa1:y1:re";
         let decoded = Message {
             transaction_id: b"aa".to_vec(),
+            read_only: false,
+            version: None,
             body: MessageBody::Response(Response {
-                id: NodeId::from(*b"abcdefghij0123456789"),
                 values: vec![
                     (Ipv4Addr::new(97, 120, 106, 101), 11893).into(),
                     (Ipv4Addr::new(105, 100, 104, 116), 28269).into(),
                 ],
-                nodes_v4: vec![],
-                nodes_v6: vec![],
                 token: Some(b"aoeusnth".to_vec()),
+                ..Response::new(NodeId::from(*b"abcdefghij0123456789"))
             }),
         };
 
@@ -598,9 +980,9 @@ mod tests {
             "d1:rd2:id20:abcdefghij01234567895:nodes52:mnopqrstuvwxyz123456axje.u789abcdefghijklmnopqidhtnm5:token8:aoeusnthe1:t2:aa1:y1:re";
         let decoded = Message {
             transaction_id: b"aa".to_vec(),
+            read_only: false,
+            version: None,
             body: MessageBody::Response(Response {
-                id: NodeId::from(*b"abcdefghij0123456789"),
-                values: vec![],
                 nodes_v4: vec![
                     NodeHandle {
                         id: NodeId::from(*b"mnopqrstuvwxyz123456"),
@@ -611,19 +993,204 @@ mod tests {
                         addr: (Ipv4Addr::new(105, 100, 104, 116), 28269).into(),
                     },
                 ],
-                nodes_v6: vec![],
                 token: Some(b"aoeusnth".to_vec()),
+                ..Response::new(NodeId::from(*b"abcdefghij0123456789"))
+            }),
+        };
+
+        assert_serialize_deserialize(encoded, &decoded);
+    }
+
+    #[test]
+    fn serialize_response_with_ip() {
+        let encoded = "d2:ip6:axje.u1:rd2:id20:mnopqrstuvwxyz123456e1:t2:aa1:y1:re";
+        let decoded = Message {
+            transaction_id: b"aa".to_vec(),
+            read_only: false,
+            version: None,
+            body: MessageBody::Response(Response {
+                ip: Some((Ipv4Addr::new(97, 120, 106, 101), 11893).into()),
+                ..Response::new(NodeId::from(*b"mnopqrstuvwxyz123456"))
             }),
         };
 
         assert_serialize_deserialize(encoded, &decoded);
     }
 
+    #[test]
+    fn serialize_get_request() {
+        let encoded =
+            "d1:ad2:id20:abcdefghij01234567896:target20:mnopqrstuvwxyz123456e1:q3:get1:t2:aa1:y1:qe";
+        let decoded = Message {
+            transaction_id: b"aa".to_vec(),
+            read_only: false,
+            version: None,
+            body: MessageBody::Request(Request::Get(GetRequest {
+                id: NodeId::from(*b"abcdefghij0123456789"),
+                target: InfoHash::from(*b"mnopqrstuvwxyz123456"),
+                seq: None,
+            })),
+        };
+
+        assert_serialize_deserialize(encoded, &decoded);
+    }
+
+    #[test]
+    fn serialize_put_request() {
+        let encoded = "d1:ad2:id20:abcdefghij01234567895:token8:aoeusnth1:vd3:fooli1ei2eeee1:q3:put1:t2:aa1:y1:qe";
+        let decoded = Message {
+            transaction_id: b"aa".to_vec(),
+            read_only: false,
+            version: None,
+            body: MessageBody::Request(Request::Put(PutRequest {
+                id: NodeId::from(*b"abcdefghij0123456789"),
+                token: b"aoeusnth".to_vec(),
+                v: Value::Dict(
+                    vec![(
+                        b"foo".to_vec(),
+                        Value::List(vec![Value::Int(1), Value::Int(2)]),
+                    )]
+                    .into_iter()
+                    .collect(),
+                ),
+                k: None,
+                sig: None,
+                seq: None,
+                salt: None,
+                cas: None,
+            })),
+        };
+
+        assert_serialize_deserialize(encoded, &decoded);
+    }
+
+    #[test]
+    fn serialize_put_mutable_request() {
+        let encoded = "d1:ad3:casi4e2:id20:abcdefghij01234567891:k4:key04:salt3:foo3:seqi5e3:sig4:sig05:token8:aoeusnth1:vi42ee1:q3:put1:t2:aa1:y1:qe";
+        let decoded = Message {
+            transaction_id: b"aa".to_vec(),
+            read_only: false,
+            version: None,
+            body: MessageBody::Request(Request::Put(PutRequest {
+                id: NodeId::from(*b"abcdefghij0123456789"),
+                token: b"aoeusnth".to_vec(),
+                v: Value::Int(42),
+                k: Some(b"key0".to_vec()),
+                sig: Some(b"sig0".to_vec()),
+                seq: Some(5),
+                salt: Some(b"foo".to_vec()),
+                cas: Some(4),
+            })),
+        };
+
+        assert_serialize_deserialize(encoded, &decoded);
+    }
+
+    #[test]
+    fn serialize_get_response_with_value() {
+        let encoded =
+            "d1:rd2:id20:mnopqrstuvwxyz1234565:token8:aoeusnth1:v12:Hello World!e1:t2:aa1:y1:re";
+        let decoded = Message {
+            transaction_id: b"aa".to_vec(),
+            read_only: false,
+            version: None,
+            body: MessageBody::Response(Response {
+                token: Some(b"aoeusnth".to_vec()),
+                v: Some(Value::Bytes(b"Hello World!".to_vec())),
+                ..Response::new(NodeId::from(*b"mnopqrstuvwxyz123456"))
+            }),
+        };
+
+        assert_serialize_deserialize(encoded, &decoded);
+    }
+
+    #[test]
+    fn serialize_sample_infohashes_request() {
+        let encoded = "d1:ad2:id20:abcdefghij01234567896:target20:mnopqrstuvwxyz123456e1:q17:sample_infohashes1:t2:aa1:y1:qe";
+        let decoded = Message {
+            transaction_id: b"aa".to_vec(),
+            read_only: false,
+            version: None,
+            body: MessageBody::Request(Request::SampleInfohashes(SampleInfohashesRequest {
+                id: NodeId::from(*b"abcdefghij0123456789"),
+                target: NodeId::from(*b"mnopqrstuvwxyz123456"),
+                want: None,
+            })),
+        };
+
+        assert_serialize_deserialize(encoded, &decoded);
+    }
+
+    #[test]
+    fn serialize_sample_infohashes_response() {
+        let encoded = "d1:rd2:id20:mnopqrstuvwxyz1234568:intervali21600e3:numi3e7:samples40:0123456789abcdefghijklmnopqrstuvwxyz0123e1:t2:aa1:y1:re";
+        let decoded = Message {
+            transaction_id: b"aa".to_vec(),
+            read_only: false,
+            version: None,
+            body: MessageBody::Response(Response {
+                interval: Some(21600),
+                num: Some(3),
+                samples: Some(vec![
+                    InfoHash::from(*b"0123456789abcdefghij"),
+                    InfoHash::from(*b"klmnopqrstuvwxyz0123"),
+                ]),
+                ..Response::new(NodeId::from(*b"mnopqrstuvwxyz123456"))
+            }),
+        };
+
+        assert_serialize_deserialize(encoded, &decoded);
+    }
+
+    #[test]
+    fn serialize_empty_sample_infohashes_response() {
+        // BEP51 requires the samples key even when there are none.
+        let encoded =
+            "d1:rd2:id20:mnopqrstuvwxyz1234568:intervali21600e3:numi0e7:samples0:e1:t2:aa1:y1:re";
+        let decoded = Message {
+            transaction_id: b"aa".to_vec(),
+            read_only: false,
+            version: None,
+            body: MessageBody::Response(Response {
+                interval: Some(21600),
+                num: Some(0),
+                samples: Some(vec![]),
+                ..Response::new(NodeId::from(*b"mnopqrstuvwxyz123456"))
+            }),
+        };
+
+        assert_serialize_deserialize(encoded, &decoded);
+    }
+
+    #[test]
+    fn serialize_scrape_response() {
+        let empty = "\0".repeat(256);
+        let encoded = format!(
+            "d1:rd4:BFpe256:{}4:BFsd256:{}2:id20:mnopqrstuvwxyz1234565:token8:aoeusnthe1:t2:aa1:y1:re",
+            empty, empty
+        );
+        let decoded = Message {
+            transaction_id: b"aa".to_vec(),
+            read_only: false,
+            version: None,
+            body: MessageBody::Response(Response {
+                token: Some(b"aoeusnth".to_vec()),
+                bf_seeds: Some(BloomFilter::new()),
+                bf_peers: Some(BloomFilter::new()),
+                ..Response::new(NodeId::from(*b"mnopqrstuvwxyz123456"))
+            }),
+        };
+
+        assert_serialize_deserialize(&encoded, &decoded);
+    }
+
     #[test]
     fn serialize_error() {
         let encoded = "d1:eli201e23:A Generic Error Ocurrede1:t2:aa1:y1:ee";
         let decoded = Message {
             transaction_id: b"aa".to_vec(),
+            read_only: false,
+            version: None,
             body: MessageBody::Error(Error {
                 code: error_code::GENERIC_ERROR,
                 message: "A Generic Error Ocurred".to_owned(),
@@ -633,6 +1200,73 @@ mod tests {
         assert_serialize_deserialize(encoded, &decoded);
     }
 
+    #[test]
+    fn deserialize_unknown_request() {
+        let encoded = "d1:ad2:id20:abcdefghij01234567896:target20:mnopqrstuvwxyz123456e1:q3:foo1:t2:aa1:y1:qe";
+        let message = Message::decode(encoded.as_bytes()).unwrap();
+
+        let request = match message.body {
+            MessageBody::Request(Request::Unknown(request)) => request,
+            body => panic!("unexpected body {:?}", body),
+        };
+
+        assert_eq!(request.method, b"foo");
+        assert_eq!(
+            request.into_find_node(),
+            Ok(FindNodeRequest {
+                id: NodeId::from(*b"abcdefghij0123456789"),
+                target: NodeId::from(*b"mnopqrstuvwxyz123456"),
+                want: None,
+            })
+        );
+
+        let encoded = "d1:ad2:id20:abcdefghij0123456789e1:q3:foo1:t2:aa1:y1:qe";
+        let message = Message::decode(encoded.as_bytes()).unwrap();
+
+        match message.body {
+            MessageBody::Request(Request::Unknown(request)) => {
+                assert!(request.into_find_node().is_err())
+            }
+            body => panic!("unexpected body {:?}", body),
+        }
+
+        // Arguments we can't make use of don't matter.
+        let encoded = "d1:ad2:id3:abce1:q3:foo1:t2:aa1:y1:qe";
+        let message = Message::decode(encoded.as_bytes()).unwrap();
+
+        match message.body {
+            MessageBody::Request(Request::Unknown(request)) => {
+                assert_eq!(request.method, b"foo");
+                assert!(request.into_find_node().is_err())
+            }
+            body => panic!("unexpected body {:?}", body),
+        }
+    }
+
+    #[test]
+    fn deserialize_malformed_request() {
+        let encoded = [
+            // announce_peer without a token
+            "d1:ad2:id20:abcdefghij01234567899:info_hash20:mnopqrstuvwxyz1234564:porti6881ee1:q13:announce_peer1:t2:aa1:y1:qe",
+            // announce_peer with a port out of range
+            "d1:ad2:id20:abcdefghij01234567899:info_hash20:mnopqrstuvwxyz1234564:porti100000e5:token8:aoeusnthe1:q13:announce_peer1:t2:aa1:y1:qe",
+            // get without a target
+            "d1:ad2:id20:abcdefghij0123456789e1:q3:get1:t2:aa1:y1:qe",
+            // find_node with an id of the wrong length
+            "d1:ad2:id3:abc6:target20:mnopqrstuvwxyz123456e1:q9:find_node1:t2:aa1:y1:qe",
+        ];
+
+        for encoded in encoded {
+            let message = Message::decode(encoded.as_bytes()).unwrap();
+
+            assert!(
+                matches!(message.body, MessageBody::Request(Request::Malformed(_))),
+                "unexpected body {:?}",
+                message.body
+            );
+        }
+    }
+
     #[track_caller]
     fn assert_serialize_deserialize(encoded: &str, decoded: &Message) {
         assert_eq!(serde_bencode::to_string(decoded).unwrap(), encoded);
@@ -1,39 +1,54 @@
+use std::mem;
 use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
 use std::slice::Iter;
 
 use crate::id::{NodeId, NODE_ID_LEN};
 use crate::routing::node::{Node, NodeStatus};
 
-/// Maximum number of nodes that should reside in any bucket.
-pub const MAX_BUCKET_SIZE: usize = 8;
+/// Number of nodes that should reside in any bucket, unless configured otherwise.
+pub const DEFAULT_BUCKET_SIZE: usize = 8;
+
+/// Outcome of [`Bucket::add_node`].
+pub enum AddOutcome {
+    /// The node was not in the bucket and took the place of a bad node. That node is returned,
+    /// unless it was an empty slot.
+    Inserted { evicted: Option<Node> },
+    /// The node was already in the bucket and was updated, or it was bad and ignored.
+    Updated,
+    /// There is no room for the node.
+    Full,
+}
 
 /// Bucket containing Nodes with identical bit prefixes.
 pub struct Bucket {
-    nodes: [Node; MAX_BUCKET_SIZE],
+    nodes: Vec<Node>,
+    // Nodes waiting for a slot to free up, the most recently seen last. Bounded by the bucket size.
+    replacements: Vec<Node>,
 }
 
 impl Bucket {
-    /// Create a new Bucket with all Nodes default initialized.
-    pub fn new() -> Bucket {
+    /// Create a new Bucket holding up to `size` Nodes, with all Nodes default initialized.
+    pub fn new(size: usize) -> Bucket {
         let id = NodeId::from([0u8; NODE_ID_LEN]);
 
         let ip = Ipv4Addr::new(127, 0, 0, 1);
         let addr = SocketAddr::V4(SocketAddrV4::new(ip, 0));
 
         Bucket {
-            nodes: [
-                Node::as_bad(id, addr),
-                Node::as_bad(id, addr),
-                Node::as_bad(id, addr),
-                Node::as_bad(id, addr),
-                Node::as_bad(id, addr),
-                Node::as_bad(id, addr),
-                Node::as_bad(id, addr),
-                Node::as_bad(id, addr),
-            ],
+            nodes: vec![Node::as_bad(id, addr); size],
+            replacements: Vec::new(),
         }
     }
 
+    /// Iterator over the nodes in the bucket, whatever their status, without the initial bad
+    /// nodes of the empty slots.
+    pub fn occupied_nodes(&self) -> impl Iterator<Item = &Node> {
+        // Bad nodes are never added, so only the initial ones have never responded.
+        self.nodes
+            .iter()
+            .filter(|node| node.last_response().is_some())
+    }
+
     /// Iterator over all good nodes and questionable nodes in the bucket.
     pub fn pingable_nodes(&self) -> impl Iterator<Item = &Node> {
         self.nodes.iter().filter(|node| node.is_pingable())
@@ -52,6 +67,11 @@ impl Bucket {
         self.nodes.iter()
     }
 
+    /// Iterator over the nodes in the replacement cache, the most recently seen last.
+    pub fn replacements(&self) -> Iter<'_, Node> {
+        self.replacements.iter()
+    }
+
     /// Indicates if the bucket needs to be refreshed.
     #[allow(unused)]
     pub fn needs_refresh(&self) -> bool {
@@ -61,12 +81,10 @@ impl Bucket {
     }
 
     /// Attempt to add the given Node to the bucket if it is not in a bad state.
-    ///
-    /// Returns false if the Node could not be placed in the bucket because it is full.
-    pub fn add_node(&mut self, new_node: Node) -> bool {
+    pub fn add_node(&mut self, new_node: Node) -> AddOutcome {
         let new_node_status = new_node.status();
         if new_node_status == NodeStatus::Bad {
-            return true;
+            return AddOutcome::Updated;
         }
 
         // See if this node is already in the table, in that case replace it if it
@@ -76,25 +94,83 @@ impl Bucket {
             // old node with the new one. Doing so would erase information already stored locally.
             self.nodes[index].update(new_node);
 
-            return true;
+            return AddOutcome::Updated;
         }
 
-        // See if any lower priority nodes are present in the table, we cant do
-        // nodes that have equal status because we have to prefer longer lasting
-        // nodes in the case of a good status which helps with stability.
+        // Only bad nodes (and empty slots) are replaced. Questionable nodes have to be pinged
+        // first, long lasting nodes tend to stay around, so we prefer them to newcomers.
         let replace_index = self
             .nodes
             .iter()
-            .position(|node| node.status() < new_node_status);
+            .position(|node| node.status() == NodeStatus::Bad);
         if let Some(index) = replace_index {
-            self.nodes[index] = new_node;
+            let evicted = mem::replace(&mut self.nodes[index], new_node);
 
-            true
+            AddOutcome::Inserted {
+                // Bad nodes which ever responded are real nodes leaving the table too.
+                evicted: Some(evicted).filter(|node| node.last_response().is_some()),
+            }
         } else {
-            false
+            AddOutcome::Full
         }
     }
 
+    /// Keep the node, which found the bucket full, as a candidate to replace a node which turns
+    /// out dead. When the cache is full, the least recently seen candidate is dropped.
+    pub fn add_replacement(&mut self, new_node: Node) {
+        if new_node.status() == NodeStatus::Bad {
+            return;
+        }
+
+        let new_node = match self.replacements.iter().position(|node| *node == new_node) {
+            Some(index) => {
+                let mut node = self.replacements.remove(index);
+                node.update(new_node);
+                node
+            }
+            None => new_node,
+        };
+
+        self.replacements.push(new_node);
+
+        if self.replacements.len() > self.nodes.len() {
+            self.replacements.remove(0);
+        }
+    }
+
+    /// Move the most recently seen candidates from the replacement cache into the slots of the bad
+    /// nodes. Returns the promoted nodes along with the nodes they replaced, unless those were
+    /// empty slots.
+    pub fn promote_replacements(&mut self) -> Vec<(Node, Option<Node>)> {
+        // Candidates can go bad too while they wait.
+        self.replacements
+            .retain(|node| node.status() != NodeStatus::Bad);
+
+        let mut promoted = Vec::new();
+
+        while !self.replacements.is_empty() {
+            let index = match self
+                .nodes
+                .iter()
+                .position(|node| node.status() == NodeStatus::Bad)
+            {
+                Some(index) => index,
+                None => break,
+            };
+
+            // `unwrap` is OK because we checked the cache is non-empty.
+            let node = self.replacements.pop().unwrap();
+            let evicted = mem::replace(&mut self.nodes[index], node.clone());
+
+            promoted.push((
+                node,
+                Some(evicted).filter(|node| node.last_response().is_some()),
+            ));
+        }
+
+        promoted
+    }
+
     /// Iterator over all good nodes in the bucket.
     #[cfg(test)]
     fn good_nodes(&self) -> impl Iterator<Item = &Node> {
@@ -109,83 +185,171 @@ impl Bucket {
 #[cfg(test)]
 mod tests {
 
-    use crate::routing::bucket::Bucket;
+    use crate::routing::bucket::{AddOutcome, Bucket};
     use crate::routing::node::{Node, NodeStatus};
     use crate::test;
 
     #[test]
     fn positive_initial_no_nodes() {
-        let bucket = Bucket::new();
+        let bucket = Bucket::new(super::DEFAULT_BUCKET_SIZE);
 
         assert_eq!(bucket.good_nodes().count(), 0);
         assert_eq!(bucket.pingable_nodes().count(), 0);
     }
 
+    #[test]
+    fn positive_occupied_nodes() {
+        let mut bucket = Bucket::new(super::DEFAULT_BUCKET_SIZE);
+        assert_eq!(bucket.occupied_nodes().count(), 0);
+
+        let node = Node::as_questionable(test::dummy_node_id(), test::dummy_socket_addr_v4());
+        bucket.add_node(node.clone());
+
+        let occupied: Vec<_> = bucket.occupied_nodes().collect();
+        assert_eq!(occupied, [&node]);
+    }
+
     #[test]
     fn positive_all_questionable_nodes() {
-        let mut bucket = Bucket::new();
+        let mut bucket = Bucket::new(super::DEFAULT_BUCKET_SIZE);
 
         let dummy_addr = test::dummy_socket_addr_v4();
-        let dummy_ids = test::dummy_block_node_ids(super::MAX_BUCKET_SIZE as u8);
+        let dummy_ids = test::dummy_block_node_ids(super::DEFAULT_BUCKET_SIZE as u8);
         for id in dummy_ids {
             let node = Node::as_questionable(id, dummy_addr);
             bucket.add_node(node);
         }
 
         assert_eq!(bucket.good_nodes().count(), 0);
-        assert_eq!(bucket.pingable_nodes().count(), super::MAX_BUCKET_SIZE);
+        assert_eq!(bucket.pingable_nodes().count(), super::DEFAULT_BUCKET_SIZE);
     }
 
     #[test]
     fn positive_all_good_nodes() {
-        let mut bucket = Bucket::new();
+        let mut bucket = Bucket::new(super::DEFAULT_BUCKET_SIZE);
 
         let dummy_addr = test::dummy_socket_addr_v4();
-        let dummy_ids = test::dummy_block_node_ids(super::MAX_BUCKET_SIZE as u8);
+        let dummy_ids = test::dummy_block_node_ids(super::DEFAULT_BUCKET_SIZE as u8);
         for id in dummy_ids {
             let node = Node::as_good(id, dummy_addr);
             bucket.add_node(node);
         }
 
-        assert_eq!(bucket.good_nodes().count(), super::MAX_BUCKET_SIZE);
-        assert_eq!(bucket.pingable_nodes().count(), super::MAX_BUCKET_SIZE);
+        assert_eq!(bucket.good_nodes().count(), super::DEFAULT_BUCKET_SIZE);
+        assert_eq!(bucket.pingable_nodes().count(), super::DEFAULT_BUCKET_SIZE);
     }
 
     #[test]
     fn positive_replace_questionable_node() {
-        let mut bucket = Bucket::new();
+        let mut bucket = Bucket::new(super::DEFAULT_BUCKET_SIZE);
 
         let dummy_addr = test::dummy_socket_addr_v4();
-        let dummy_ids = test::dummy_block_node_ids(super::MAX_BUCKET_SIZE as u8);
+        let dummy_ids = test::dummy_block_node_ids(super::DEFAULT_BUCKET_SIZE as u8);
         for id in &dummy_ids {
             let node = Node::as_questionable(*id, dummy_addr);
             bucket.add_node(node);
         }
 
         assert_eq!(bucket.good_nodes().count(), 0);
-        assert_eq!(bucket.pingable_nodes().count(), super::MAX_BUCKET_SIZE);
+        assert_eq!(bucket.pingable_nodes().count(), super::DEFAULT_BUCKET_SIZE);
 
         let good_node = Node::as_good(dummy_ids[0], dummy_addr);
         bucket.add_node(good_node.clone());
 
         assert_eq!(bucket.good_nodes().next().unwrap(), &good_node);
         assert_eq!(bucket.good_nodes().count(), 1);
-        assert_eq!(bucket.pingable_nodes().count(), super::MAX_BUCKET_SIZE);
+        assert_eq!(bucket.pingable_nodes().count(), super::DEFAULT_BUCKET_SIZE);
+    }
+
+    #[test]
+    fn positive_report_evicted_bad_node() {
+        let mut bucket = Bucket::new(1);
+
+        let dummy_addr = test::dummy_socket_addr_v4();
+        // The first id is the one of the empty slots.
+        let dummy_ids = test::dummy_block_node_ids(3);
+
+        // Filling an empty slot evicts nothing.
+        let old_node = Node::as_questionable(dummy_ids[1], dummy_addr);
+        assert!(matches!(
+            bucket.add_node(old_node.clone()),
+            AddOutcome::Inserted { evicted: None }
+        ));
+
+        // Two requests timing out make the node bad, it is still reported when replaced.
+        let node = bucket.pingable_nodes_mut().next().unwrap();
+        node.request_timed_out();
+        node.request_timed_out();
+
+        match bucket.add_node(Node::as_good(dummy_ids[2], dummy_addr)) {
+            AddOutcome::Inserted { evicted } => assert_eq!(evicted, Some(old_node)),
+            _ => panic!("node not inserted"),
+        }
+    }
+
+    #[test]
+    fn positive_replace_dead_node_from_cache() {
+        let mut bucket = Bucket::new(super::DEFAULT_BUCKET_SIZE);
+
+        let dummy_addr = test::dummy_socket_addr_v4();
+        let dummy_ids = test::dummy_block_node_ids((super::DEFAULT_BUCKET_SIZE as u8) + 1);
+        let (new_id, questionable_ids) = dummy_ids.split_last().unwrap();
+        for id in questionable_ids {
+            bucket.add_node(Node::as_questionable(*id, dummy_addr));
+        }
+
+        // A good node doesn't take the place of a questionable one right away.
+        let new_node = Node::as_good(*new_id, dummy_addr);
+        assert!(matches!(
+            bucket.add_node(new_node.clone()),
+            AddOutcome::Full
+        ));
+        bucket.add_replacement(new_node.clone());
+
+        assert!(bucket.promote_replacements().is_empty());
+        assert!(!bucket.iter().any(|node| *node == new_node));
+
+        // Two requests timing out make the questionable node bad.
+        let dead_node = bucket.pingable_nodes_mut().next().unwrap();
+        dead_node.request_timed_out();
+        dead_node.request_timed_out();
+
+        let promoted = bucket.promote_replacements();
+        assert_eq!(promoted.len(), 1);
+        assert_eq!(promoted[0].0, new_node);
+        assert_eq!(promoted[0].1.as_ref().unwrap().id(), questionable_ids[0]);
+        assert_eq!(bucket.replacements().count(), 0);
+        assert!(bucket.good_nodes().any(|node| *node == new_node));
+    }
+
+    #[test]
+    fn positive_replacement_cache_bounded() {
+        let mut bucket = Bucket::new(2);
+
+        let dummy_addr = test::dummy_socket_addr_v4();
+        let dummy_ids = test::dummy_block_node_ids(3);
+        for id in &dummy_ids {
+            bucket.add_replacement(Node::as_good(*id, dummy_addr));
+        }
+
+        // The least recently seen candidate is dropped.
+        let ids: Vec<_> = bucket.replacements().map(|node| node.id()).collect();
+        assert_eq!(ids, &dummy_ids[1..]);
     }
 
     #[test]
     fn positive_resist_good_node_churn() {
-        let mut bucket = Bucket::new();
+        let mut bucket = Bucket::new(super::DEFAULT_BUCKET_SIZE);
 
         let dummy_addr = test::dummy_socket_addr_v4();
-        let dummy_ids = test::dummy_block_node_ids((super::MAX_BUCKET_SIZE as u8) + 1);
+        let dummy_ids = test::dummy_block_node_ids((super::DEFAULT_BUCKET_SIZE as u8) + 1);
         for id in &dummy_ids {
             let node = Node::as_good(*id, dummy_addr);
             bucket.add_node(node);
         }
 
         // All the nodes should be good
-        assert_eq!(bucket.good_nodes().count(), super::MAX_BUCKET_SIZE);
+        assert_eq!(bucket.good_nodes().count(), super::DEFAULT_BUCKET_SIZE);
 
         // Create a new good node
         let unused_id = dummy_ids[dummy_ids.len() - 1];
@@ -203,10 +367,10 @@ mod tests {
 
     #[test]
     fn positive_resist_questionable_node_churn() {
-        let mut bucket = Bucket::new();
+        let mut bucket = Bucket::new(super::DEFAULT_BUCKET_SIZE);
 
         let dummy_addr = test::dummy_socket_addr_v4();
-        let dummy_ids = test::dummy_block_node_ids((super::MAX_BUCKET_SIZE as u8) + 1);
+        let dummy_ids = test::dummy_block_node_ids((super::DEFAULT_BUCKET_SIZE as u8) + 1);
         for id in &dummy_ids {
             let node = Node::as_questionable(*id, dummy_addr);
             bucket.add_node(node);
@@ -218,7 +382,7 @@ mod tests {
                 .pingable_nodes()
                 .filter(|node| node.status() == NodeStatus::Questionable)
                 .count(),
-            super::MAX_BUCKET_SIZE
+            super::DEFAULT_BUCKET_SIZE
         );
 
         // Create a new questionable node
@@ -239,7 +403,7 @@ mod tests {
                 .pingable_nodes()
                 .filter(|node| node.status() == NodeStatus::Questionable)
                 .count(),
-            super::MAX_BUCKET_SIZE
+            super::DEFAULT_BUCKET_SIZE
         );
     }
 }
@@ -1,3 +1,4 @@
 pub(crate) mod bucket;
 pub(crate) mod node;
+pub(crate) mod snapshot;
 pub(crate) mod table;
@@ -3,28 +3,25 @@ use std::hash::{Hash, Hasher};
 use std::net::SocketAddr;
 use std::time::{Duration, Instant};
 
+use serde::{Deserialize, Serialize};
+
 use crate::id::NodeId;
 
 // TODO: Should remove as_* functions and replace them with from_requested, from_responded, etc to hide the logic
 // of the nodes initial status.
 
-// TODO: Should address the subsecond lookup paper where questionable nodes should not automatically be replaced with
-// good nodes, instead, questionable nodes should be pinged twice and then become available to be replaced. This reduces
-// GOOD node churn since after 15 minutes, a long lasting node could potentially be replaced by a short lived good node.
-// This strategy is actually what is vaguely specified in the standard?
-
 // TODO: Should we be storing a SocketAddr instead of a SocketAddrV4?
 
 /// Maximum wait period before a node becomes questionable.
 const MAX_LAST_SEEN_MINS: u64 = 15;
 
-/// Maximum number of requests before a Questionable node becomes Bad.
-const MAX_REFRESH_REQUESTS: usize = 2;
+/// Number of requests in a row a Questionable node fails to respond to before it becomes Bad.
+const MAX_FAILED_REQUESTS: usize = 2;
 
 /// Status of the node.
 /// Ordering of the enumerations is important, variants higher
 /// up are considered to be less than those further down.
-#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Ord, PartialOrd)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Ord, PartialOrd, Serialize, Deserialize)]
 pub enum NodeStatus {
     Bad,
     Questionable,
@@ -38,7 +35,11 @@ pub struct Node {
     last_request: Option<Instant>,
     last_response: Option<Instant>,
     last_local_request: Option<Instant>,
-    refresh_requests: usize,
+    failed_requests: usize,
+    // Client identification (the "v" key) the node sent us, if any.
+    version: Option<Vec<u8>>,
+    // How long the node takes to respond to our requests, once measured.
+    rtt: Option<Rtt>,
 }
 
 impl Node {
@@ -49,7 +50,9 @@ impl Node {
             last_response: Some(Instant::now()),
             last_request: None,
             last_local_request: None,
-            refresh_requests: 0,
+            failed_requests: 0,
+            version: None,
+            rtt: None,
         }
     }
 
@@ -63,7 +66,9 @@ impl Node {
             last_response: Some(last_response),
             last_request: None,
             last_local_request: None,
-            refresh_requests: 0,
+            failed_requests: 0,
+            version: None,
+            rtt: None,
         }
     }
 
@@ -74,7 +79,9 @@ impl Node {
             last_response: None,
             last_request: None,
             last_local_request: None,
-            refresh_requests: 0,
+            failed_requests: 0,
+            version: None,
+            rtt: None,
         }
     }
 
@@ -83,6 +90,8 @@ impl Node {
 
         let self_status = self.status();
         let other_status = other.status();
+        let version = other.version.clone().or_else(|| self.version.take());
+        let rtt = other.rtt.or(self.rtt);
 
         match (self_status, other_status) {
             (NodeStatus::Good, NodeStatus::Good) => {
@@ -91,7 +100,9 @@ impl Node {
                     last_response: other.last_response,
                     last_request: self.last_request,
                     last_local_request: self.last_local_request,
-                    refresh_requests: 0,
+                    failed_requests: 0,
+                    version: None,
+                    rtt: None,
                 };
             }
             (NodeStatus::Good, NodeStatus::Questionable) => {}
@@ -109,15 +120,21 @@ impl Node {
             }
             (NodeStatus::Bad, NodeStatus::Bad) => {}
         }
+
+        self.version = version;
+        self.rtt = rtt;
     }
 
     /// Record that we sent the node a request.
     pub fn local_request(&mut self) {
         self.last_local_request = Some(Instant::now());
+    }
 
-        if self.status() != NodeStatus::Good {
-            self.refresh_requests = self.refresh_requests.saturating_add(1);
-        }
+    /// Record that the node didn't respond to one of our requests in time. Its timeout is doubled,
+    /// as TCP does it (RFC 6298), until the next response brings it back in line.
+    pub fn request_timed_out(&mut self) {
+        self.failed_requests = self.failed_requests.saturating_add(1);
+        self.rtt = self.rtt.map(Rtt::back_off);
     }
 
     /// Record that the node sent us a request.
@@ -125,6 +142,37 @@ impl Node {
         self.last_request = Some(Instant::now());
     }
 
+    /// Record the client identification the node sent us, if any.
+    pub fn set_version(&mut self, version: Option<Vec<u8>>) {
+        if version.is_some() {
+            self.version = version;
+        }
+    }
+
+    /// Record how long the node took to respond to one of our requests.
+    pub fn record_rtt(&mut self, sample: Duration) {
+        self.rtt = Some(match self.rtt {
+            Some(rtt) => rtt.update(sample),
+            None => Rtt::new(sample),
+        });
+    }
+
+    /// Smoothed round-trip time of the node, if it ever responded to a request we timed.
+    pub fn rtt(&self) -> Option<Duration> {
+        self.rtt.map(|rtt| rtt.smoothed)
+    }
+
+    /// How long to wait for the node to respond before giving up on it, based on its round-trip
+    /// time and how much that varies. `None` if the round-trip time hasn't been measured yet.
+    pub fn rtt_timeout(&self) -> Option<Duration> {
+        self.rtt.map(|rtt| rtt.smoothed + rtt.variation * 4)
+    }
+
+    /// Client identification the node sent us, if any.
+    pub fn version(&self) -> Option<&[u8]> {
+        self.version.as_deref()
+    }
+
     /// Return true if we have sent this node a request recently.
     pub fn recently_requested_from(&self) -> bool {
         if let Some(time) = self.last_local_request {
@@ -139,10 +187,35 @@ impl Node {
         self.handle.id
     }
 
+    /// When the node last responded to us.
+    pub fn last_response(&self) -> Option<Instant> {
+        self.last_response
+    }
+
+    /// When the node last sent us a request.
+    pub fn last_request(&self) -> Option<Instant> {
+        self.last_request
+    }
+
+    /// When we last sent the node a request.
+    pub fn last_local_request(&self) -> Option<Instant> {
+        self.last_local_request
+    }
+
+    /// Number of our requests in a row the node didn't respond to in time.
+    pub fn failed_requests(&self) -> usize {
+        self.failed_requests
+    }
+
     pub fn addr(&self) -> SocketAddr {
         self.handle.addr
     }
 
+    /// Is the node id valid for the node's IP address (as per BEP42)?
+    pub fn is_secure(&self) -> bool {
+        self.handle.id.is_secure(self.handle.addr.ip())
+    }
+
     /// Current status of the node.
     ///
     /// The specification says:
@@ -167,8 +240,8 @@ impl Node {
             return NodeStatus::Good;
         }
 
-        // Check if we have request from node multiple times already without response
-        if self.refresh_requests >= MAX_REFRESH_REQUESTS {
+        // Check if the node failed to respond to our requests multiple times in a row
+        if self.failed_requests >= MAX_FAILED_REQUESTS {
             return NodeStatus::Bad;
         }
 
@@ -220,19 +293,57 @@ impl Debug for Node {
             .field("addr", &self.handle.addr)
             .field("last_request", &self.last_request)
             .field("last_response", &self.last_response)
-            .field("refresh_requests", &self.refresh_requests)
+            .field("failed_requests", &self.failed_requests)
+            .field("version", &self.version)
+            .field("rtt", &self.rtt())
             .finish()
     }
 }
 
+/// Round-trip time estimate, smoothed the same way TCP does it (RFC 6298).
+#[derive(Clone, Copy, Debug)]
+struct Rtt {
+    smoothed: Duration,
+    variation: Duration,
+}
+
+impl Rtt {
+    fn new(sample: Duration) -> Self {
+        Self {
+            smoothed: sample,
+            variation: sample / 2,
+        }
+    }
+
+    fn update(self, sample: Duration) -> Self {
+        let deviation = sample.abs_diff(self.smoothed);
+
+        Self {
+            smoothed: (self.smoothed * 7 + sample) / 8,
+            variation: (self.variation * 3 + deviation) / 4,
+        }
+    }
+
+    // Widen the variation so the timeout (`smoothed + 4 * variation`) doubles.
+    fn back_off(self) -> Self {
+        Self {
+            smoothed: self.smoothed,
+            variation: self.variation + (self.smoothed + self.variation * 4) / 4,
+        }
+    }
+}
+
 /// Node id + its socket address.
 #[derive(Copy, Clone, PartialEq, Eq, Hash)]
 pub struct NodeHandle {
+    /// Id of the node.
     pub id: NodeId,
+    /// Address the node is reachable at.
     pub addr: SocketAddr,
 }
 
 impl NodeHandle {
+    /// Create a new NodeHandle.
     pub fn new(id: NodeId, addr: SocketAddr) -> Self {
         Self { id, addr }
     }
@@ -281,6 +392,61 @@ mod tests {
         assert_eq!(node.status(), NodeStatus::Good);
     }
 
+    #[test]
+    fn positive_keep_version_on_update() {
+        let mut node = Node::as_good(test::dummy_node_id(), test::dummy_socket_addr_v4());
+        assert_eq!(node.version(), None);
+
+        node.set_version(Some(b"LT01".to_vec()));
+        assert_eq!(node.version(), Some(&b"LT01"[..]));
+
+        // Update without version keeps the known one.
+        node.update(Node::as_good(node.id(), node.addr()));
+        assert_eq!(node.version(), Some(&b"LT01"[..]));
+
+        let mut other = Node::as_good(node.id(), node.addr());
+        other.set_version(Some(b"UT02".to_vec()));
+        node.update(other);
+        assert_eq!(node.version(), Some(&b"UT02"[..]));
+    }
+
+    #[test]
+    fn positive_smoothed_rtt() {
+        let mut node = Node::as_good(test::dummy_node_id(), test::dummy_socket_addr_v4());
+        assert_eq!(node.rtt(), None);
+        assert_eq!(node.rtt_timeout(), None);
+
+        node.record_rtt(Duration::from_millis(80));
+        assert_eq!(node.rtt(), Some(Duration::from_millis(80)));
+        assert_eq!(node.rtt_timeout(), Some(Duration::from_millis(240)));
+
+        node.record_rtt(Duration::from_millis(160));
+        assert_eq!(node.rtt(), Some(Duration::from_millis(90)));
+        assert_eq!(node.rtt_timeout(), Some(Duration::from_millis(290)));
+
+        // The estimate survives updates of the node.
+        node.update(Node::as_good(node.id(), node.addr()));
+        assert_eq!(node.rtt(), Some(Duration::from_millis(90)));
+    }
+
+    #[test]
+    fn positive_rtt_timeout_backs_off() {
+        let mut node = Node::as_good(test::dummy_node_id(), test::dummy_socket_addr_v4());
+        node.record_rtt(Duration::from_millis(80));
+        assert_eq!(node.rtt_timeout(), Some(Duration::from_millis(240)));
+
+        node.request_timed_out();
+        assert_eq!(node.rtt(), Some(Duration::from_millis(80)));
+        assert_eq!(node.rtt_timeout(), Some(Duration::from_millis(480)));
+
+        node.request_timed_out();
+        assert_eq!(node.rtt_timeout(), Some(Duration::from_millis(960)));
+
+        // The next response shrinks it back.
+        node.record_rtt(Duration::from_millis(80));
+        assert_eq!(node.rtt_timeout(), Some(Duration::from_millis(740)));
+    }
+
     #[test]
     fn positive_node_idle() {
         let mut node = Node::as_good(test::dummy_node_id(), test::dummy_socket_addr_v4());
@@ -297,10 +463,15 @@ mod tests {
     fn positive_node_idle_reqeusts() {
         let mut node = Node::as_questionable(test::dummy_node_id(), test::dummy_socket_addr_v4());
 
-        for _ in 0..super::MAX_REFRESH_REQUESTS {
+        // Sending the requests is not enough, they have to time out.
+        for _ in 0..super::MAX_FAILED_REQUESTS {
             node.local_request();
         }
+        assert_eq!(node.status(), NodeStatus::Questionable);
 
+        for _ in 0..super::MAX_FAILED_REQUESTS {
+            node.request_timed_out();
+        }
         assert_eq!(node.status(), NodeStatus::Bad);
     }
 
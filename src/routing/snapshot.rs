@@ -0,0 +1,79 @@
+use super::node::NodeStatus;
+use crate::{id::NodeId, worker::IpVersion};
+use serde::{Deserialize, Serialize};
+use std::{net::SocketAddr, time::Duration};
+
+/// Contents of the routing table, returned from
+/// [`MainlineDht::export_routing_table()`](crate::MainlineDht::export_routing_table). Can be saved
+/// and passed to [`DhtBuilder::restore_routing_table()`](crate::DhtBuilder::restore_routing_table)
+/// on the next start to avoid bootstrapping from scratch.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct RoutingTableSnapshot {
+    /// Our node id.
+    pub node_id: NodeId,
+    /// The nodes in the routing table, of both address families if running dual-stack.
+    pub nodes: Vec<NodeSnapshot>,
+}
+
+/// A node in a [`RoutingTableSnapshot`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct NodeSnapshot {
+    /// Id of the node.
+    pub id: NodeId,
+    /// Address of the node.
+    pub addr: SocketAddr,
+    /// Status of the node at the time the snapshot was taken.
+    pub status: NodeStatus,
+}
+
+/// Contents of a routing table bucket by bucket, returned from
+/// [`MainlineDht::routing_table()`](crate::MainlineDht::routing_table). Meant for debugging: unlike
+/// [`RoutingTableSnapshot`], it includes the bad nodes and tells how active each node is.
+#[derive(Clone, Debug, Serialize)]
+pub struct RoutingTableInfo {
+    /// Address family of the routing table.
+    pub ip_version: IpVersion,
+    /// Our node id.
+    pub node_id: NodeId,
+    /// Maximum number of nodes in a bucket.
+    pub bucket_size: usize,
+    /// The buckets, the one farthest from our node id first.
+    pub buckets: Vec<BucketInfo>,
+}
+
+/// A bucket in a [`RoutingTableInfo`].
+#[derive(Clone, Debug, Serialize)]
+pub struct BucketInfo {
+    /// Position of the bucket in [`RoutingTableInfo::buckets`]. It is also the number of leading
+    /// bits the ids of the nodes in the bucket share with our node id, except in the last bucket
+    /// which holds all the nodes sharing at least that many.
+    pub index: usize,
+    /// The nodes in the bucket. Empty slots are left out.
+    pub nodes: Vec<NodeInfo>,
+    /// Candidates waiting for a node of the bucket to turn out dead, the most recently seen last.
+    pub replacements: Vec<NodeInfo>,
+}
+
+/// A node in a [`BucketInfo`]. The times are ages, relative to when the info was taken.
+#[derive(Clone, Debug, Serialize)]
+pub struct NodeInfo {
+    /// Id of the node.
+    pub id: NodeId,
+    /// Address of the node.
+    pub addr: SocketAddr,
+    /// Current status of the node.
+    pub status: NodeStatus,
+    /// How long ago the node last responded to us. The nodes restored from a snapshot haven't
+    /// responded yet, they start out as if they had 15 minutes ago, the age at which a node
+    /// becomes questionable.
+    pub since_last_response: Option<Duration>,
+    /// How long ago the node last sent us a request.
+    pub since_last_request: Option<Duration>,
+    /// How long ago we last sent the node a request.
+    pub since_last_local_request: Option<Duration>,
+    /// Our requests in a row the node didn't respond to in time. Two of them make the node bad,
+    /// unless it responded in the last 15 minutes.
+    pub failed_requests: usize,
+    /// Smoothed round-trip time of the node, once measured.
+    pub rtt: Option<Duration>,
+}
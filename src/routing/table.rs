@@ -1,9 +1,15 @@
 use super::{
-    bucket::{self, Bucket},
+    bucket::{AddOutcome, Bucket},
     node::{Node, NodeHandle, NodeStatus},
+    snapshot::{BucketInfo, NodeInfo, NodeSnapshot, RoutingTableSnapshot},
 };
 use crate::id::{NodeId, ID_LEN};
-use std::{cmp::Ordering, iter::Filter, slice::Iter};
+use std::{
+    cmp::Ordering,
+    iter::{Chain, Filter},
+    mem,
+    slice::Iter,
+};
 
 pub const MAX_BUCKETS: usize = ID_LEN * 8;
 
@@ -13,15 +19,44 @@ pub struct RoutingTable {
     // Important: Our node id will always fall within the range
     // of the last bucket in the buckets array.
     buckets: Vec<Bucket>,
+    bucket_size: usize,
     node_id: NodeId,
+    // Reject nodes whose ids are not valid for their IP addresses (BEP42).
+    enforce_node_id: bool,
+    // Nodes added and evicted since the last call to `take_changes`.
+    changes: Vec<TableChange>,
+    // Questionable nodes of full buckets to ping, so the dead ones can be replaced.
+    nodes_to_ping: Vec<NodeHandle>,
+}
+
+/// A change to the set of nodes in the RoutingTable.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TableChange {
+    Added(NodeHandle),
+    Evicted(NodeHandle),
 }
 
 impl RoutingTable {
-    /// Create a new RoutingTable with the given node id as our id.
-    pub fn new(node_id: NodeId) -> RoutingTable {
-        let buckets = vec![Bucket::new()];
+    /// Create a new RoutingTable with the given node id as our id, holding up to `bucket_size`
+    /// nodes in each bucket.
+    pub fn new(node_id: NodeId, bucket_size: usize) -> RoutingTable {
+        let buckets = vec![Bucket::new(bucket_size)];
+
+        RoutingTable {
+            buckets,
+            bucket_size,
+            node_id,
+            enforce_node_id: true,
+            changes: Vec::new(),
+            nodes_to_ping: Vec::new(),
+        }
+    }
 
-        RoutingTable { buckets, node_id }
+    /// Set whether nodes with ids that are not valid for their IP addresses (as per BEP42) should
+    /// be rejected. When not enforced, such nodes are still accepted, but nodes with valid ids are
+    /// preferred by `closest_nodes`. Enforced by default.
+    pub fn set_enforce_node_id(&mut self, enforce: bool) {
+        self.enforce_node_id = enforce;
     }
 
     /// Return the node id of the RoutingTable.
@@ -29,11 +64,17 @@ impl RoutingTable {
         self.node_id
     }
 
+    /// Return the maximum number of nodes in each bucket.
+    pub fn bucket_size(&self) -> usize {
+        self.bucket_size
+    }
+
     /// Iterator over the closest good nodes to the given node id.
     ///
     /// The closeness of nodes has a maximum granularity of a bucket. For most use
     /// cases this is fine since we will usually be performing lookups and aggregating
-    /// a number of results equal to the size of a bucket.
+    /// a number of results equal to the size of a bucket. Within a bucket, nodes with
+    /// BEP42 compliant ids are returned first.
     pub fn closest_nodes(&self, node_id: NodeId) -> ClosestNodes {
         ClosestNodes::new(&self.buckets, self.node_id, node_id)
     }
@@ -57,8 +98,107 @@ impl RoutingTable {
         self.buckets.iter()
     }
 
+    /// Snapshot of our node id and all the nodes in the RoutingTable, with their current status.
+    pub fn snapshot(&self) -> RoutingTableSnapshot {
+        let nodes = self
+            .buckets
+            .iter()
+            .flat_map(|bucket| bucket.pingable_nodes())
+            .map(|node| NodeSnapshot {
+                id: node.id(),
+                addr: node.addr(),
+                status: node.status(),
+            })
+            .collect();
+
+        RoutingTableSnapshot {
+            node_id: self.node_id,
+            nodes,
+        }
+    }
+
+    /// Contents of every bucket, including the bad nodes and their activity.
+    pub fn bucket_infos(&self) -> Vec<BucketInfo> {
+        self.buckets
+            .iter()
+            .enumerate()
+            .map(|(index, bucket)| BucketInfo {
+                index,
+                nodes: bucket.occupied_nodes().map(node_info).collect(),
+                replacements: bucket.replacements().map(node_info).collect(),
+            })
+            .collect()
+    }
+
+    /// Add the nodes from a snapshot. They are added as questionable because there is no telling
+    /// how long ago the snapshot was taken. Nodes that were bad at the time are skipped.
+    pub fn restore<'a, I>(&mut self, nodes: I)
+    where
+        I: IntoIterator<Item = &'a NodeSnapshot>,
+    {
+        for node in nodes {
+            if node.status != NodeStatus::Bad {
+                self.add_node(Node::as_questionable(node.id, node.addr));
+            }
+        }
+    }
+
+    /// Take the nodes added to and evicted from the RoutingTable since the last call, in order.
+    pub fn take_changes(&mut self) -> Vec<TableChange> {
+        mem::take(&mut self.changes)
+    }
+
+    /// Take the questionable nodes which need to be pinged since the last call. They are in buckets
+    /// which had no room for a new node, the ones which don't respond get replaced once
+    /// `request_timed_out` makes them bad.
+    pub fn take_nodes_to_ping(&mut self) -> Vec<NodeHandle> {
+        mem::take(&mut self.nodes_to_ping)
+    }
+
+    /// Replace the bad nodes with the candidates waiting in the replacement cache of their bucket.
+    pub fn replace_dead_nodes(&mut self) {
+        for bucket in &mut self.buckets {
+            for (node, evicted) in bucket.promote_replacements() {
+                if let Some(evicted) = evicted {
+                    self.changes.push(TableChange::Evicted(*evicted.handle()));
+                }
+
+                self.changes.push(TableChange::Added(*node.handle()));
+            }
+        }
+    }
+
+    /// Record that the node didn't respond to our request in time. While candidates wait for a slot
+    /// in its bucket, the node is pinged again until it either responds or turns bad, in which
+    /// case a candidate takes its place right away.
+    pub fn request_timed_out(&mut self, node: &NodeHandle) {
+        let bucket_index = self.bucket_index_for_node(node.id);
+        let bucket = &mut self.buckets[bucket_index];
+
+        let status = match bucket.pingable_nodes_mut().find(|n| n.handle() == node) {
+            Some(n) => {
+                n.request_timed_out();
+                n.status()
+            }
+            None => return,
+        };
+
+        if bucket.replacements().next().is_none() {
+            return;
+        }
+
+        match status {
+            NodeStatus::Bad => self.replace_dead_nodes(),
+            NodeStatus::Questionable => {
+                if !self.nodes_to_ping.contains(node) {
+                    self.nodes_to_ping.push(*node);
+                }
+            }
+            NodeStatus::Good => (),
+        }
+    }
+
     /// Find an instance of the target node in the RoutingTable, if it exists.
-    #[allow(unused)]
     pub fn find_node(&self, node: &NodeHandle) -> Option<&Node> {
         let bucket_index = self.bucket_index_for_node(node.id);
         let bucket = self.buckets.get(bucket_index)?;
@@ -95,24 +235,63 @@ impl RoutingTable {
         if node.status() == NodeStatus::Bad {
             return;
         }
+
+        if self.enforce_node_id && !node.is_secure() {
+            log::trace!("Rejecting node with non-compliant id {:?}", node.handle());
+            return;
+        }
+
         let num_same_bits = leading_bit_count(self.node_id, node.id());
 
         // Should not add a node that has the same id as us
         if num_same_bits != MAX_BUCKETS {
-            self.bucket_node(node, num_same_bits);
+            self.bucket_node(node, num_same_bits, true);
         }
     }
 
-    /// Recursively tries to place the node into some bucket.
-    fn bucket_node(&mut self, node: Node, num_same_bits: usize) {
+    /// Recursively tries to place the node into some bucket. Records the change if the node is
+    /// new to the table, as opposed to being moved by a split.
+    fn bucket_node(&mut self, node: Node, num_same_bits: usize, record: bool) {
         let bucket_index = bucket_placement(num_same_bits, self.buckets.len());
+        let handle = *node.handle();
 
         // Try to place in correct bucket
-        if !self.buckets[bucket_index].add_node(node.clone()) {
-            // Bucket was full, try to split it
-            if self.split_bucket(bucket_index) {
-                // Bucket split successfully, try to add again
-                self.bucket_node(node, num_same_bits);
+        match self.buckets[bucket_index].add_node(node.clone()) {
+            AddOutcome::Inserted { evicted } => {
+                if record {
+                    if let Some(evicted) = evicted {
+                        self.changes.push(TableChange::Evicted(*evicted.handle()));
+                    }
+
+                    self.changes.push(TableChange::Added(handle));
+                }
+            }
+            AddOutcome::Updated => (),
+            AddOutcome::Full => {
+                // Bucket was full, try to split it
+                if self.split_bucket(bucket_index) {
+                    // Bucket split successfully, try to add again
+                    self.bucket_node(node, num_same_bits, record);
+                } else {
+                    self.cache_replacement(bucket_index, node);
+                }
+            }
+        }
+    }
+
+    /// Keep the node as a candidate for the full bucket and have its questionable nodes pinged,
+    /// to find out whether some of them are dead.
+    fn cache_replacement(&mut self, bucket_index: usize, node: Node) {
+        let bucket = &mut self.buckets[bucket_index];
+        bucket.add_replacement(node);
+
+        for node in bucket
+            .pingable_nodes()
+            .filter(|node| node.status() == NodeStatus::Questionable)
+            .filter(|node| !node.recently_requested_from())
+        {
+            if !self.nodes_to_ping.contains(node.handle()) {
+                self.nodes_to_ping.push(*node.handle());
             }
         }
     }
@@ -134,17 +313,40 @@ impl RoutingTable {
         };
 
         // Push two more buckets to distribute nodes between
-        self.buckets.push(Bucket::new());
-        self.buckets.push(Bucket::new());
+        self.buckets.push(Bucket::new(self.bucket_size));
+        self.buckets.push(Bucket::new(self.bucket_size));
+
+        for node in split_bucket
+            .iter()
+            .filter(|node| node.status() != NodeStatus::Bad)
+        {
+            let num_same_bits = leading_bit_count(self.node_id, node.id());
+            self.bucket_node(node.clone(), num_same_bits, false);
+        }
 
-        for node in split_bucket.iter() {
-            self.add_node(node.clone());
+        // The candidates may find room in the new buckets. Those that do are new to the table.
+        for node in split_bucket.replacements() {
+            let num_same_bits = leading_bit_count(self.node_id, node.id());
+            self.bucket_node(node.clone(), num_same_bits, true);
         }
 
         true
     }
 }
 
+fn node_info(node: &Node) -> NodeInfo {
+    NodeInfo {
+        id: node.id(),
+        addr: node.addr(),
+        status: node.status(),
+        since_last_response: node.last_response().map(|time| time.elapsed()),
+        since_last_request: node.last_request().map(|time| time.elapsed()),
+        since_last_local_request: node.last_local_request().map(|time| time.elapsed()),
+        failed_requests: node.failed_requests(),
+        rtt: node.rtt(),
+    }
+}
+
 /// Returns true if the bucket can be split.
 fn can_split_bucket(num_buckets: usize, bucket_index: usize) -> bool {
     bucket_index == num_buckets - 1 && bucket_index != MAX_BUCKETS - 1
@@ -171,8 +373,9 @@ fn bucket_placement(num_same_bits: usize, num_buckets: usize) -> usize {
 
 // ----------------------------------------------------------------------------//
 
-// Iterator filter for only good nodes.
-type GoodNodes<'a> = Filter<Iter<'a, Node>, fn(&&Node) -> bool>;
+// Iterator filter for only good nodes, the ones with secure ids first.
+type GoodNodes<'a> =
+    Chain<Filter<Iter<'a, Node>, fn(&&Node) -> bool>, Filter<Iter<'a, Node>, fn(&&Node) -> bool>>;
 
 // So what we are going to do here is iterate over every bucket in a hypothetically filled
 // routing table (buckets slice). If the bucket we are interested in has not been created
@@ -188,7 +391,7 @@ pub struct ClosestNodes<'a> {
     // nodes as far as closest nodes are concerned, we need some way to hand the
     // assorted nodes out and keep track of which ones we have handed out.
     // (Bucket Index, Node Reference, Returned Before)
-    assorted_nodes: Option<[(usize, &'a Node, bool); bucket::MAX_BUCKET_SIZE]>,
+    assorted_nodes: Option<Vec<(usize, &'a Node, bool)>>,
 }
 
 impl<'a> ClosestNodes<'a> {
@@ -221,14 +424,17 @@ impl<'a> Iterator for ClosestNodes<'a> {
             }
         }
 
-        // Check if we have any nodes to give in the assorted bucket
+        // Check if we have any nodes to give in the assorted bucket, preferring the ones with
+        // secure ids.
         if let Some(ref mut nodes) = self.assorted_nodes {
-            let mut nodes_iter = nodes.iter_mut().filter(|tup| is_good_node(&tup.1));
+            for filter in [is_good_secure_node, is_good_insecure_node] {
+                let mut nodes_iter = nodes.iter_mut().filter(|tup| filter(&tup.1));
 
-            if let Some(node) = nodes_iter.find(|tup| tup.0 == current_index && !tup.2) {
-                node.2 = true;
-                return Some(node.1);
-            };
+                if let Some(node) = nodes_iter.find(|tup| tup.0 == current_index && !tup.2) {
+                    node.2 = true;
+                    return Some(node.1);
+                };
+            }
         }
 
         // Check if we can move to a new bucket
@@ -249,28 +455,25 @@ impl<'a> Iterator for ClosestNodes<'a> {
 fn precompute_assorted_nodes(
     buckets: &[Bucket],
     self_node_id: NodeId,
-) -> Option<[(usize, &Node, bool); bucket::MAX_BUCKET_SIZE]> {
+) -> Option<Vec<(usize, &Node, bool)>> {
     if buckets.len() == MAX_BUCKETS {
         return None;
     }
     let assorted_bucket = &buckets[buckets.len() - 1];
-    let mut assorted_iter = assorted_bucket.iter().peekable();
 
-    // So the bucket is not empty and now we have a reference to initialize our stack allocated array.
-    if let Some(&init_reference) = assorted_iter.peek() {
-        // Set all tuples to true in case our bucket is not full.
-        let mut assorted_nodes = [(0, init_reference, true); bucket::MAX_BUCKET_SIZE];
+    // Nothing to hand out if the bucket is empty.
+    assorted_bucket.iter().next()?;
 
-        for (index, node) in assorted_iter.enumerate() {
+    let assorted_nodes = assorted_bucket
+        .iter()
+        .map(|node| {
             let bucket_index = leading_bit_count(self_node_id, node.id());
 
-            assorted_nodes[index] = (bucket_index, node, false);
-        }
+            (bucket_index, node, false)
+        })
+        .collect();
 
-        Some(assorted_nodes)
-    } else {
-        None
-    }
+    Some(assorted_nodes)
 }
 
 /// Optionally returns the filter iterator for the bucket at the specified index.
@@ -284,9 +487,12 @@ fn bucket_iterator(buckets: &[Bucket], index: usize) -> Option<GoodNodes> {
     .map(|bucket| good_node_filter(bucket.iter()))
 }
 
-/// Converts the given iterator into a filter iterator to return only good nodes.
+/// Converts the given iterator into a filter iterator to return only good nodes, the ones with
+/// secure ids first.
 fn good_node_filter(iter: Iter<Node>) -> GoodNodes {
-    iter.filter(is_good_node)
+    iter.clone()
+        .filter(is_good_secure_node as fn(&&Node) -> bool)
+        .chain(iter.filter(is_good_insecure_node as fn(&&Node) -> bool))
 }
 
 /// Shakes fist at iterator making me take a double reference (could avoid it by mapping, but oh well)
@@ -296,6 +502,14 @@ fn is_good_node(node: &&Node) -> bool {
     status == NodeStatus::Good || status == NodeStatus::Questionable
 }
 
+fn is_good_secure_node(node: &&Node) -> bool {
+    is_good_node(node) && node.is_secure()
+}
+
+fn is_good_insecure_node(node: &&Node) -> bool {
+    is_good_node(node) && !node.is_secure()
+}
+
 /// Computes the next bucket index that should be visited given the number of buckets, the starting index
 /// and the current index.
 ///
@@ -362,14 +576,19 @@ fn index_is_in_bounds(length: usize, checked_index: Option<usize>) -> bool {
 mod tests {
     use crate::id::{NodeId, NODE_ID_LEN};
     use crate::routing::bucket;
-    use crate::routing::node::Node;
-    use crate::routing::table::{self, RoutingTable};
+    use crate::routing::node::{Node, NodeHandle, NodeStatus};
+    use crate::routing::snapshot::NodeSnapshot;
+    use crate::routing::table::{self, RoutingTable, TableChange};
     use crate::test;
+    use std::{
+        net::{Ipv4Addr, SocketAddr},
+        time::Duration,
+    };
 
     #[test]
     fn positive_add_node_max_recursion() {
         let table_id = [1u8; NODE_ID_LEN];
-        let mut table = RoutingTable::new(table_id.into());
+        let mut table = RoutingTable::new(table_id.into(), bucket::DEFAULT_BUCKET_SIZE);
 
         let mut node_id = table_id;
         // Modify the id so it is placed in the last bucket
@@ -377,7 +596,7 @@ mod tests {
 
         // Trigger a bucket overflow and since the ids are placed in the last bucket, all of
         // the buckets will be recursively created and inserted into the list of all buckets.
-        let block_addrs = test::dummy_block_socket_addrs((bucket::MAX_BUCKET_SIZE + 1) as u16);
+        let block_addrs = test::dummy_block_socket_addrs((bucket::DEFAULT_BUCKET_SIZE + 1) as u16);
         for block_addr in block_addrs {
             let node = Node::as_good(node_id.into(), block_addr);
 
@@ -385,10 +604,94 @@ mod tests {
         }
     }
 
+    #[test]
+    fn positive_take_changes() {
+        let table_id = [1u8; NODE_ID_LEN];
+        let mut table = RoutingTable::new(table_id.into(), bucket::DEFAULT_BUCKET_SIZE);
+
+        let mut node_id = table_id;
+        // Flip first bit so we are placed in the first bucket
+        node_id[0] |= 128;
+
+        let block_addrs = test::dummy_block_socket_addrs((bucket::DEFAULT_BUCKET_SIZE + 1) as u16);
+        let (good_addr, questionable_addrs) = block_addrs.split_last().unwrap();
+
+        for addr in questionable_addrs {
+            table.add_node(Node::as_questionable(node_id.into(), *addr));
+        }
+
+        // Moving the nodes when the bucket gets split is not a change.
+        table.add_node(Node::as_questionable(node_id.into(), *good_addr));
+
+        let changes = table.take_changes();
+        assert_eq!(changes.len(), bucket::DEFAULT_BUCKET_SIZE);
+        assert!(changes
+            .iter()
+            .all(|change| matches!(change, TableChange::Added(_))));
+
+        // The bucket is full and can't be split anymore, so a good node waits in the replacement
+        // cache until one of the questionable nodes turns out dead.
+        let good_node = Node::as_good(node_id.into(), *good_addr);
+        table.add_node(good_node.clone());
+        assert!(table.take_changes().is_empty());
+
+        // The node which doesn't respond is pinged again and replaced once that ping times out
+        // too.
+        let dead_node = table.take_nodes_to_ping()[0];
+        table.request_timed_out(&dead_node);
+        assert_eq!(table.take_nodes_to_ping(), [dead_node]);
+        assert!(table.take_changes().is_empty());
+        table.request_timed_out(&dead_node);
+
+        let changes = table.take_changes();
+        assert_eq!(
+            changes,
+            [
+                TableChange::Evicted(dead_node),
+                TableChange::Added(*good_node.handle())
+            ]
+        );
+        assert!(table.take_changes().is_empty());
+    }
+
+    #[test]
+    fn positive_ping_questionable_nodes_of_full_bucket() {
+        let table_id = [1u8; NODE_ID_LEN];
+        let mut table = RoutingTable::new(table_id.into(), bucket::DEFAULT_BUCKET_SIZE);
+
+        let mut node_id = table_id;
+        // Flip first bit so we are placed in the first bucket
+        node_id[0] |= 128;
+
+        let block_addrs = test::dummy_block_socket_addrs((bucket::DEFAULT_BUCKET_SIZE + 1) as u16);
+        let (new_addr, questionable_addrs) = block_addrs.split_last().unwrap();
+
+        for addr in questionable_addrs {
+            table.add_node(Node::as_questionable(node_id.into(), *addr));
+        }
+        assert!(table.take_nodes_to_ping().is_empty());
+
+        table.add_node(Node::as_good(node_id.into(), *new_addr));
+
+        let nodes_to_ping = table.take_nodes_to_ping();
+        assert_eq!(nodes_to_ping.len(), bucket::DEFAULT_BUCKET_SIZE);
+        assert!(nodes_to_ping.iter().all(|node| node.addr != *new_addr));
+
+        // Nothing is dead yet, so the new node keeps waiting.
+        table.replace_dead_nodes();
+        assert!(table
+            .take_changes()
+            .iter()
+            .all(|change| matches!(change, TableChange::Added(node) if node.addr != *new_addr)));
+        assert!(table
+            .find_node(&NodeHandle::new(node_id.into(), *new_addr))
+            .is_none());
+    }
+
     #[test]
     fn positive_initial_empty_buckets() {
         let table_id = [1u8; NODE_ID_LEN];
-        let table = RoutingTable::new(table_id.into());
+        let table = RoutingTable::new(table_id.into(), bucket::DEFAULT_BUCKET_SIZE);
 
         assert_eq!(table.buckets().count(), 1);
         for bucket in table.buckets() {
@@ -399,13 +702,13 @@ mod tests {
     #[test]
     fn positive_first_bucket_sorted() {
         let table_id = [1u8; NODE_ID_LEN];
-        let mut table = RoutingTable::new(table_id.into());
+        let mut table = RoutingTable::new(table_id.into(), bucket::DEFAULT_BUCKET_SIZE);
 
         let mut node_id = table_id;
         // Flip first bit so we are placed in the first bucket
         node_id[0] |= 128;
 
-        let block_addrs = test::dummy_block_socket_addrs((bucket::MAX_BUCKET_SIZE + 1) as u16);
+        let block_addrs = test::dummy_block_socket_addrs((bucket::DEFAULT_BUCKET_SIZE + 1) as u16);
         for block_addr in block_addrs {
             let node = Node::as_good(node_id.into(), block_addr);
 
@@ -415,7 +718,7 @@ mod tests {
         // First bucket should be sorted
         assert_eq!(table.buckets().take(1).count(), 1);
         for bucket in table.buckets().take(1) {
-            assert_eq!(bucket.pingable_nodes().count(), bucket::MAX_BUCKET_SIZE)
+            assert_eq!(bucket.pingable_nodes().count(), bucket::DEFAULT_BUCKET_SIZE)
         }
 
         // Assorted bucket should show up
@@ -431,13 +734,13 @@ mod tests {
     #[test]
     fn positive_last_bucket_sorted() {
         let table_id = [1u8; NODE_ID_LEN];
-        let mut table = RoutingTable::new(table_id.into());
+        let mut table = RoutingTable::new(table_id.into(), bucket::DEFAULT_BUCKET_SIZE);
 
         let mut node_id = table_id;
         // Flip last bit so we are placed in the last bucket
         node_id[NODE_ID_LEN - 1] = 0;
 
-        let block_addrs = test::dummy_block_socket_addrs((bucket::MAX_BUCKET_SIZE + 1) as u16);
+        let block_addrs = test::dummy_block_socket_addrs((bucket::DEFAULT_BUCKET_SIZE + 1) as u16);
         for block_addr in block_addrs {
             let node = Node::as_good(node_id.into(), block_addr);
 
@@ -459,16 +762,16 @@ mod tests {
             1
         );
         for bucket in table.buckets().skip(table::MAX_BUCKETS - 1).take(1) {
-            assert_eq!(bucket.pingable_nodes().count(), bucket::MAX_BUCKET_SIZE)
+            assert_eq!(bucket.pingable_nodes().count(), bucket::DEFAULT_BUCKET_SIZE)
         }
     }
 
     #[test]
     fn positive_all_sorted_buckets() {
         let table_id = NodeId::from([1u8; NODE_ID_LEN]);
-        let mut table = RoutingTable::new(table_id);
+        let mut table = RoutingTable::new(table_id, bucket::DEFAULT_BUCKET_SIZE);
 
-        let block_addrs = test::dummy_block_socket_addrs(bucket::MAX_BUCKET_SIZE as u16);
+        let block_addrs = test::dummy_block_socket_addrs(bucket::DEFAULT_BUCKET_SIZE as u16);
         for bit_flip_index in 0..table::MAX_BUCKETS {
             for block_addr in &block_addrs {
                 let bucket_node_id = table_id.flip_bit(bit_flip_index);
@@ -479,14 +782,119 @@ mod tests {
 
         assert_eq!(table.buckets().count(), table::MAX_BUCKETS);
         for bucket in table.buckets() {
-            assert_eq!(bucket.pingable_nodes().count(), bucket::MAX_BUCKET_SIZE)
+            assert_eq!(bucket.pingable_nodes().count(), bucket::DEFAULT_BUCKET_SIZE)
         }
     }
 
+    #[test]
+    fn positive_snapshot_and_restore() {
+        let table_id = [1u8; NODE_ID_LEN];
+        let mut table = RoutingTable::new(table_id.into(), bucket::DEFAULT_BUCKET_SIZE);
+        table.set_enforce_node_id(false);
+
+        let ids: Vec<NodeId> = (2..5).map(|i| [i; NODE_ID_LEN].into()).collect();
+        let addrs = test::dummy_block_socket_addrs(3);
+
+        table.add_node(Node::as_good(ids[0], addrs[0]));
+        table.add_node(Node::as_questionable(ids[1], addrs[1]));
+
+        let snapshot = table.snapshot();
+        assert_eq!(snapshot.node_id, table_id.into());
+        assert_eq!(snapshot.nodes.len(), 2);
+
+        let mut nodes = snapshot.nodes;
+        nodes.push(NodeSnapshot {
+            id: ids[2],
+            addr: addrs[2],
+            status: NodeStatus::Bad,
+        });
+
+        let mut restored = RoutingTable::new(table_id.into(), bucket::DEFAULT_BUCKET_SIZE);
+        restored.set_enforce_node_id(false);
+        restored.restore(&nodes);
+
+        // The bad node is skipped and the rest need to be verified again.
+        assert_eq!(restored.num_good_nodes(), 0);
+        assert_eq!(restored.num_questionable_nodes(), 2);
+    }
+
+    #[test]
+    fn positive_bucket_infos() {
+        let table_id = [1u8; NODE_ID_LEN];
+        let mut table = RoutingTable::new(table_id.into(), bucket::DEFAULT_BUCKET_SIZE);
+        table.set_enforce_node_id(false);
+
+        let ids: Vec<NodeId> = (2..4).map(|i| [i; NODE_ID_LEN].into()).collect();
+        let addrs = test::dummy_block_socket_addrs(2);
+
+        table.add_node(Node::as_good(ids[0], addrs[0]));
+        table.add_node(Node::as_questionable(ids[1], addrs[1]));
+        let questionable = table
+            .find_node_mut(&NodeHandle::new(ids[1], addrs[1]))
+            .unwrap();
+        questionable.local_request();
+        questionable.request_timed_out();
+
+        let buckets = table.bucket_infos();
+        assert_eq!(buckets.len(), 1);
+        assert_eq!(buckets[0].index, 0);
+
+        let good = &buckets[0].nodes[0];
+        assert_eq!(good.id, ids[0]);
+        assert_eq!(good.status, NodeStatus::Good);
+        assert!(good.since_last_response.unwrap() < Duration::from_secs(60));
+        assert_eq!(good.since_last_local_request, None);
+        assert_eq!(good.failed_requests, 0);
+
+        let questionable = &buckets[0].nodes[1];
+        assert_eq!(questionable.id, ids[1]);
+        assert_eq!(questionable.status, NodeStatus::Questionable);
+        assert!(questionable.since_last_response.unwrap() >= Duration::from_secs(15 * 60));
+        assert!(questionable.since_last_local_request.is_some());
+        assert_eq!(questionable.failed_requests, 1);
+    }
+
+    #[test]
+    fn negative_reject_insecure_node_id() {
+        let table_id = [1u8; NODE_ID_LEN];
+        let mut table = RoutingTable::new(table_id.into(), bucket::DEFAULT_BUCKET_SIZE);
+
+        let addr: SocketAddr = (Ipv4Addr::new(124, 31, 75, 21), 6881).into();
+        let secure_id = NodeId::secure(addr.ip());
+        let insecure_id = secure_id.flip_bit(0);
+
+        table.add_node(Node::as_good(insecure_id, addr));
+        assert_eq!(table.closest_nodes(table_id.into()).count(), 0);
+
+        table.add_node(Node::as_good(secure_id, addr));
+        assert_eq!(table.closest_nodes(table_id.into()).count(), 1);
+    }
+
+    #[test]
+    fn positive_prefer_secure_node_ids() {
+        let table_id = [1u8; NODE_ID_LEN];
+        let mut table = RoutingTable::new(table_id.into(), bucket::DEFAULT_BUCKET_SIZE);
+        table.set_enforce_node_id(false);
+
+        let ip = Ipv4Addr::new(124, 31, 75, 21);
+        let secure_id = NodeId::secure(ip.into());
+        // Flipping a bit of the prefix invalidates the id but keeps it in the same bucket.
+        let insecure_id = secure_id.flip_bit(20);
+
+        table.add_node(Node::as_good(insecure_id, (ip, 6881).into()));
+        table.add_node(Node::as_good(secure_id, (ip, 6882).into()));
+
+        let closest: Vec<_> = table
+            .closest_nodes(table_id.into())
+            .map(|node| node.id())
+            .collect();
+        assert_eq!(closest, [secure_id, insecure_id]);
+    }
+
     #[test]
     fn negative_node_id_equal_table_id() {
         let table_id = [1u8; NODE_ID_LEN];
-        let mut table = RoutingTable::new(table_id.into());
+        let mut table = RoutingTable::new(table_id.into(), bucket::DEFAULT_BUCKET_SIZE);
 
         assert_eq!(table.closest_nodes(table_id.into()).count(), 0);
 
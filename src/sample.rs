@@ -0,0 +1,195 @@
+//! Sampling of the info hashes stored in the DHT as per
+//! [BEP51](https://www.bittorrent.org/beps/bep_0051.html).
+
+use crate::{
+    builder::WeakMainlineDht,
+    id::{InfoHash, NodeId, NODE_ID_LEN},
+};
+use futures_util::{Stream, StreamExt};
+use rand::seq::SliceRandom;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tokio::{sync::mpsc, task, time};
+
+// How long to wait before the next pass over the keyspace if the previous one yielded nothing
+// (e.g. because all the nodes we know of asked us to wait).
+const IDLE_DELAY: Duration = Duration::from_secs(60);
+// Maximum number of info hashes remembered as already yielded. Past that, the oldest ones are
+// forgotten and may be yielded again.
+const MAX_SEEN: usize = 100_000;
+// Maximum number of nodes remembered as having asked us to wait. Past that, the ones due the
+// soonest are forgotten.
+const MAX_SCHEDULED: usize = 10_000;
+
+/// Info hashes sampled from a single node.
+#[derive(Clone, Debug)]
+pub struct InfoHashSample {
+    /// Address of the node the sample comes from.
+    pub addr: SocketAddr,
+    /// How long to wait before asking the node for another sample.
+    pub interval: Duration,
+    /// Total number of info hashes the node stores.
+    pub num: usize,
+    /// The sampled info hashes.
+    pub samples: Vec<InfoHash>,
+}
+
+/// Stream returned from [`MainlineDht::walk_infohashes()`]
+#[must_use = "streams do nothing unless polled"]
+pub struct InfoHashWalker(mpsc::UnboundedReceiver<InfoHash>);
+
+impl InfoHashWalker {
+    pub(crate) fn new(dht: WeakMainlineDht) -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        task::spawn(walk(dht, tx));
+
+        Self(rx)
+    }
+}
+
+impl Stream for InfoHashWalker {
+    type Item = InfoHash;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.0).poll_recv(cx)
+    }
+}
+
+// Repeatedly sample the whole keyspace until the receiver is dropped or the DHT shuts down.
+async fn walk(dht: WeakMainlineDht, tx: mpsc::UnboundedSender<InfoHash>) {
+    // When each node may be asked for another sample.
+    let mut schedule: HashMap<SocketAddr, Instant> = HashMap::new();
+    let mut seen = SeenInfoHashes::new(MAX_SEEN);
+
+    loop {
+        let mut sampled = false;
+
+        for target in targets() {
+            let now = Instant::now();
+            schedule.retain(|_, due| *due > now);
+
+            // Only hold on to the DHT while starting the lookup so it can still shut down.
+            let mut samples = if let Some(dht) = dht.upgrade() {
+                dht.sample_infohashes_skipping(target, schedule.keys().copied())
+            } else {
+                return;
+            };
+
+            while let Some(sample) = samples.next().await {
+                sampled = true;
+
+                if schedule.len() >= MAX_SCHEDULED && !schedule.contains_key(&sample.addr) {
+                    let soonest = schedule
+                        .iter()
+                        .min_by_key(|(_, due)| **due)
+                        .map(|(addr, _)| *addr);
+
+                    if let Some(soonest) = soonest {
+                        schedule.remove(&soonest);
+                    }
+                }
+
+                schedule.insert(sample.addr, Instant::now() + sample.interval);
+
+                for info_hash in sample.samples {
+                    if seen.insert(info_hash) && tx.send(info_hash).is_err() {
+                        return;
+                    }
+                }
+            }
+
+            if tx.is_closed() {
+                return;
+            }
+
+            match dht.upgrade() {
+                Some(dht) if dht.get_state().await.is_some() => (),
+                _ => return,
+            }
+        }
+
+        if !sampled {
+            time::sleep(IDLE_DELAY).await;
+        }
+    }
+}
+
+// Set of the most recently yielded info hashes, bounded to `capacity` of them.
+struct SeenInfoHashes {
+    set: HashSet<InfoHash>,
+    order: VecDeque<InfoHash>,
+    capacity: usize,
+}
+
+impl SeenInfoHashes {
+    fn new(capacity: usize) -> Self {
+        Self {
+            set: HashSet::new(),
+            order: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    // Returns whether the info hash wasn't seen before.
+    fn insert(&mut self, info_hash: InfoHash) -> bool {
+        if !self.set.insert(info_hash) {
+            return false;
+        }
+
+        if self.order.len() >= self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.set.remove(&oldest);
+            }
+        }
+
+        self.order.push_back(info_hash);
+        true
+    }
+}
+
+// Targets spread evenly over the keyspace: one for each possible first byte, in random order.
+fn targets() -> Vec<NodeId> {
+    let mut prefixes: Vec<u8> = (0..=u8::MAX).collect();
+    prefixes.shuffle(&mut rand::thread_rng());
+
+    prefixes
+        .into_iter()
+        .map(|prefix| {
+            let mut bytes: [u8; NODE_ID_LEN] = rand::random();
+            bytes[0] = prefix;
+            NodeId::from(bytes)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn targets_cover_keyspace() {
+        let prefixes: HashSet<_> = targets().iter().map(|target| target.as_ref()[0]).collect();
+        assert_eq!(prefixes.len(), 256);
+    }
+
+    #[test]
+    fn seen_info_hashes_bounded() {
+        let mut seen = SeenInfoHashes::new(2);
+        let info_hashes: Vec<_> = (0..3u8).map(|n| InfoHash::sha1(&[n])).collect();
+
+        assert!(seen.insert(info_hashes[0]));
+        assert!(seen.insert(info_hashes[1]));
+        assert!(!seen.insert(info_hashes[0]));
+
+        // The oldest one is forgotten to make room.
+        assert!(seen.insert(info_hashes[2]));
+        assert_eq!(seen.set.len(), 2);
+        assert!(seen.insert(info_hashes[0]));
+        assert!(!seen.insert(info_hashes[2]));
+    }
+}
@@ -3,6 +3,9 @@ use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::time::{Duration, Instant};
 
+use rand::seq::IteratorRandom;
+
+use crate::bloom::BloomFilter;
 use crate::id::InfoHash;
 
 const MAX_ITEMS_STORED: usize = 500;
@@ -23,14 +26,21 @@ impl AnnounceStorage {
     }
 
     /// Returns true if the item was added/it's existing expiration updated, false otherwise.
-    pub fn add_item(&mut self, info_hash: InfoHash, address: SocketAddr) -> bool {
-        self.add(info_hash, address, Instant::now())
+    /// `seed` tells whether the contact has the complete torrent (BEP33).
+    pub fn add_item(&mut self, info_hash: InfoHash, address: SocketAddr, seed: bool) -> bool {
+        self.add(info_hash, address, seed, Instant::now())
     }
 
-    fn add(&mut self, info_hash: InfoHash, address: SocketAddr, curr_time: Instant) -> bool {
+    fn add(
+        &mut self,
+        info_hash: InfoHash,
+        address: SocketAddr,
+        seed: bool,
+        curr_time: Instant,
+    ) -> bool {
         // Clear out any old contacts that we have stored
         self.remove_expired_items(curr_time);
-        let item = AnnounceItem::new(info_hash, address);
+        let item = AnnounceItem::new(info_hash, address, seed);
         let item_expiration = item.expiration();
 
         // Check if we already have the item and want to update it's expiration
@@ -50,6 +60,17 @@ impl AnnounceStorage {
         }
     }
 
+    /// Returns the number of contacts stored, not counting the expired ones.
+    pub fn num_items(&mut self) -> usize {
+        self.remove_expired_items(Instant::now());
+        self.expires.len()
+    }
+
+    /// Returns the maximum number of contacts which can be stored.
+    pub fn max_items(&self) -> usize {
+        MAX_ITEMS_STORED
+    }
+
     /// Returns an iterator over all contacts for the given info hash.
     pub fn find_items<'a>(
         &'a mut self,
@@ -73,15 +94,75 @@ impl AnnounceStorage {
             .map(|item| item.address())
     }
 
+    /// Returns an iterator over the contacts for the given info hash which are not seeds.
+    pub fn find_leechers<'a>(
+        &'a mut self,
+        info_hash: &'_ InfoHash,
+    ) -> impl Iterator<Item = SocketAddr> + 'a {
+        // Clear out any old contacts that we have stored
+        self.remove_expired_items(Instant::now());
+
+        self.storage
+            .get(info_hash)
+            .into_iter()
+            .flatten()
+            .filter(|item| !item.seed)
+            .map(|item| item.address())
+    }
+
+    /// Returns the bloom filters of the IP addresses of the seeds and of the leechers for the given
+    /// info hash (BEP33).
+    pub fn scrape(&mut self, info_hash: &InfoHash) -> (BloomFilter, BloomFilter) {
+        // Clear out any old contacts that we have stored
+        self.remove_expired_items(Instant::now());
+
+        let mut seeds = BloomFilter::new();
+        let mut leechers = BloomFilter::new();
+
+        for item in self.storage.get(info_hash).into_iter().flatten() {
+            if item.seed {
+                seeds.insert(item.address().ip());
+            } else {
+                leechers.insert(item.address().ip());
+            }
+        }
+
+        (seeds, leechers)
+    }
+
+    /// Returns a random sample of at most `max` of the info hashes we have contacts for, together
+    /// with the total number of such info hashes (BEP51).
+    pub fn sample_info_hashes(&mut self, max: usize) -> (Vec<InfoHash>, usize) {
+        self.sample(max, Instant::now())
+    }
+
+    fn sample(&mut self, max: usize, curr_time: Instant) -> (Vec<InfoHash>, usize) {
+        // Clear out any old contacts that we have stored
+        self.remove_expired_items(curr_time);
+
+        let samples = self
+            .storage
+            .keys()
+            .copied()
+            .choose_multiple(&mut rand::thread_rng(), max);
+
+        (samples, self.storage.len())
+    }
+
     /// Returns None if the contact could not be inserted, else, returns Some(true) if the contact was already
     /// in the table (and was replaced by the new entry) or Some(false) if the contact was not already in the
     /// table but was inserted.
     fn insert_contact(&mut self, item: AnnounceItem) -> Option<bool> {
         let item_info_hash = item.info_hash();
 
-        // Check if the contact is already in our list
+        // Check if the contact is already in our list (and update its seed status if so)
         let already_in_list = if let Some(items) = self.storage.get_mut(&item_info_hash) {
-            items.iter().any(|a| a == &item)
+            if let Some(existing) = items.iter_mut().find(|a| *a == &item) {
+                existing.seed = item.seed;
+                true
+            } else {
+                false
+            }
         } else {
             false
         };
@@ -137,15 +218,17 @@ impl AnnounceStorage {
 
 // ----------------------------------------------------------------------------//
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone)]
 struct AnnounceItem {
     expiration: ItemExpiration,
+    seed: bool,
 }
 
 impl AnnounceItem {
-    pub fn new(info_hash: InfoHash, address: SocketAddr) -> AnnounceItem {
+    pub fn new(info_hash: InfoHash, address: SocketAddr, seed: bool) -> AnnounceItem {
         AnnounceItem {
             expiration: ItemExpiration::new(info_hash, address),
+            seed,
         }
     }
 
@@ -195,6 +278,14 @@ impl ItemExpiration {
     }
 }
 
+impl PartialEq for AnnounceItem {
+    fn eq(&self, other: &AnnounceItem) -> bool {
+        self.expiration == other.expiration
+    }
+}
+
+impl Eq for AnnounceItem {}
+
 impl PartialEq for ItemExpiration {
     fn eq(&self, other: &ItemExpiration) -> bool {
         self.address() == other.address() && self.info_hash() == other.info_hash()
@@ -205,6 +296,7 @@ impl Eq for ItemExpiration {}
 
 #[cfg(test)]
 mod tests {
+    use std::net::{Ipv4Addr, SocketAddr};
     use std::time::Instant;
 
     use crate::id::INFO_HASH_LEN;
@@ -217,7 +309,7 @@ mod tests {
         let info_hash = [0u8; INFO_HASH_LEN].into();
         let sock_addr = test::dummy_socket_addr_v4();
 
-        assert!(announce_store.add_item(info_hash, sock_addr));
+        assert!(announce_store.add_item(info_hash, sock_addr, false));
 
         let items: Vec<_> = announce_store.find_items(&info_hash).collect();
         assert_eq!(items.len(), 1);
@@ -232,7 +324,7 @@ mod tests {
         let sock_addrs = test::dummy_block_socket_addrs(storage::MAX_ITEMS_STORED as u16);
 
         for sock_addr in sock_addrs.iter() {
-            assert!(announce_store.add_item(info_hash, *sock_addr));
+            assert!(announce_store.add_item(info_hash, *sock_addr, false));
         }
 
         let items: Vec<_> = announce_store.find_items(&info_hash).collect();
@@ -250,21 +342,21 @@ mod tests {
         let sock_addrs = test::dummy_block_socket_addrs((storage::MAX_ITEMS_STORED + 1) as u16);
 
         for sock_addr in sock_addrs.iter().take(storage::MAX_ITEMS_STORED) {
-            assert!(announce_store.add_item(info_hash, *sock_addr));
+            assert!(announce_store.add_item(info_hash, *sock_addr, false));
         }
 
         // Try to add a new item
         let other_info_hash = [1u8; INFO_HASH_LEN].into();
 
         // Returns false because it wasnt added
-        assert!(!announce_store.add_item(other_info_hash, sock_addrs[sock_addrs.len() - 1]));
+        assert!(!announce_store.add_item(other_info_hash, sock_addrs[sock_addrs.len() - 1], false));
         // Iterator is empty because it wasnt added
         let count = announce_store.find_items(&other_info_hash).count();
         assert_eq!(count, 0);
 
         // Try to add all of the initial nodes again (renew)
         for sock_addr in sock_addrs.iter().take(storage::MAX_ITEMS_STORED) {
-            assert!(announce_store.add_item(info_hash, *sock_addr));
+            assert!(announce_store.add_item(info_hash, *sock_addr, false));
         }
     }
 
@@ -276,14 +368,14 @@ mod tests {
 
         // Fill up the announce storage completely
         for sock_addr in sock_addrs.iter().take(storage::MAX_ITEMS_STORED) {
-            assert!(announce_store.add_item(info_hash, *sock_addr));
+            assert!(announce_store.add_item(info_hash, *sock_addr, false));
         }
 
         // Try to add a new item into the storage (under a different info hash)
         let other_info_hash = [1u8; INFO_HASH_LEN].into();
 
         // Returned false because it wasnt added
-        assert!(!announce_store.add_item(other_info_hash, sock_addrs[sock_addrs.len() - 1]));
+        assert!(!announce_store.add_item(other_info_hash, sock_addrs[sock_addrs.len() - 1], false));
         // Iterator is empty because it wasnt added
         let count = announce_store.find_items(&other_info_hash).count();
         assert_eq!(count, 0);
@@ -293,6 +385,7 @@ mod tests {
         assert!(announce_store.add(
             other_info_hash,
             sock_addrs[sock_addrs.len() - 1],
+            false,
             mock_current_time
         ));
         // Iterator is not empty because it was added
@@ -310,7 +403,7 @@ mod tests {
         // Fill up first info hash
         let num_contacts_first = storage::MAX_ITEMS_STORED / 2;
         for sock_addr in sock_addrs.iter().take(num_contacts_first) {
-            assert!(announce_store.add_item(info_hash_one, *sock_addr));
+            assert!(announce_store.add_item(info_hash_one, *sock_addr, false));
         }
 
         // Fill up second info hash
@@ -320,12 +413,12 @@ mod tests {
             .skip(num_contacts_first)
             .take(num_contacts_second)
         {
-            assert!(announce_store.add_item(info_hash_two, *sock_addr));
+            assert!(announce_store.add_item(info_hash_two, *sock_addr, false));
         }
 
         // Try to add a third info hash with a contact
         let info_hash_three = [2u8; INFO_HASH_LEN].into();
-        assert!(!announce_store.add_item(info_hash_three, sock_addrs[sock_addrs.len() - 1]));
+        assert!(!announce_store.add_item(info_hash_three, sock_addrs[sock_addrs.len() - 1], false));
         // Iterator is empty because it was not added
         let count = announce_store.find_items(&info_hash_three).count();
         assert_eq!(count, 0);
@@ -335,10 +428,66 @@ mod tests {
         assert!(announce_store.add(
             info_hash_three,
             sock_addrs[sock_addrs.len() - 1],
+            false,
             mock_current_time
         ));
         // Iterator is not empty because it was added
         let count = announce_store.find_items(&info_hash_three).count();
         assert_eq!(count, 1);
     }
+
+    #[test]
+    fn positive_sample_info_hashes() {
+        let mut announce_store = AnnounceStorage::new();
+        let info_hashes: Vec<_> = (0..5u8).map(|i| [i; INFO_HASH_LEN].into()).collect();
+        let sock_addr = test::dummy_socket_addr_v4();
+
+        let (samples, num) = announce_store.sample_info_hashes(2);
+        assert!(samples.is_empty());
+        assert_eq!(num, 0);
+
+        for info_hash in &info_hashes {
+            assert!(announce_store.add_item(*info_hash, sock_addr, false));
+        }
+
+        let (samples, num) = announce_store.sample_info_hashes(2);
+        assert_eq!(samples.len(), 2);
+        assert_ne!(samples[0], samples[1]);
+        assert!(samples.iter().all(|s| info_hashes.contains(s)));
+        assert_eq!(num, info_hashes.len());
+
+        let (samples, num) = announce_store.sample_info_hashes(10);
+        assert_eq!(samples.len(), info_hashes.len());
+        assert_eq!(num, info_hashes.len());
+
+        // Expired items are not sampled
+        let mock_current_time = Instant::now() + storage::EXPIRATION_TIME;
+        let (samples, num) = announce_store.sample(10, mock_current_time);
+        assert!(samples.is_empty());
+        assert_eq!(num, 0);
+    }
+
+    #[test]
+    fn positive_seeds_and_leechers() {
+        let mut announce_store = AnnounceStorage::new();
+        let info_hash = [0u8; INFO_HASH_LEN].into();
+        let sock_addrs: Vec<SocketAddr> = (1..=3)
+            .map(|i| (Ipv4Addr::new(192, 0, 2, i), 6881).into())
+            .collect();
+
+        assert!(announce_store.add_item(info_hash, sock_addrs[0], true));
+        assert!(announce_store.add_item(info_hash, sock_addrs[1], false));
+        assert!(announce_store.add_item(info_hash, sock_addrs[2], false));
+
+        assert_eq!(announce_store.find_items(&info_hash).count(), 3);
+        assert_eq!(announce_store.find_leechers(&info_hash).count(), 2);
+
+        let (seeds, leechers) = announce_store.scrape(&info_hash);
+        assert_eq!(seeds.estimate().round(), 1.0);
+        assert_eq!(leechers.estimate().round(), 2.0);
+
+        // Re-announcing updates the seed status
+        assert!(announce_store.add_item(info_hash, sock_addrs[1], true));
+        assert_eq!(announce_store.find_leechers(&info_hash).count(), 1);
+    }
 }
@@ -4,6 +4,7 @@ use super::{
     timer::{Timeout, Timer},
     BootstrapTimeout, IpVersion, ScheduledTaskCheck,
 };
+use crate::config::DhtConfig;
 use crate::message::{FindNodeRequest, Message, MessageBody, Request};
 use crate::routing::bucket::Bucket;
 use crate::routing::node::NodeStatus;
@@ -13,25 +14,20 @@ use crate::{id::NodeId, routing::node::NodeHandle};
 use std::{
     collections::{HashMap, HashSet},
     net::SocketAddr,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-const INITIAL_TIMEOUT: Duration = Duration::from_millis(2500);
-const NODE_TIMEOUT: Duration = Duration::from_millis(500);
-const NO_NETWORK_TIMEOUT: Duration = Duration::from_secs(5);
-const PERIODIC_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
-const GOOD_NODE_THRESHOLD: usize = 10;
-
-const PINGS_PER_BUCKET: usize = 8;
-
 pub(crate) struct TableBootstrap {
     ip_version: IpVersion,
     table_id: NodeId,
+    config: DhtConfig,
+    read_only: bool,
     routers: HashSet<String>,
     router_addresses: HashSet<SocketAddr>,
     id_generator: MIDGenerator,
     starting_nodes: HashSet<SocketAddr>,
-    active_messages: HashMap<TransactionID, Timeout>,
+    // The requests waiting for a response, with when they were sent.
+    active_messages: HashMap<TransactionID, (Timeout, Instant)>,
     curr_bootstrap_bucket: usize,
     initial_responses: HashSet<SocketAddr>,
     initial_responses_expected: usize,
@@ -53,6 +49,8 @@ impl TableBootstrap {
     pub fn new(
         ip_version: IpVersion,
         table_id: NodeId,
+        config: DhtConfig,
+        read_only: bool,
         id_generator: MIDGenerator,
         routers: HashSet<String>,
         nodes: HashSet<SocketAddr>,
@@ -60,6 +58,8 @@ impl TableBootstrap {
         TableBootstrap {
             ip_version,
             table_id,
+            config,
+            read_only,
             routers,
             router_addresses: HashSet::new(),
             id_generator,
@@ -107,19 +107,19 @@ impl TableBootstrap {
 
         // If we have no bootstrap contacts it means we are the first node in the network and
         // other would bootstrap against us. We consider this node as already bootstrapped.
-        if self.routers.is_empty() {
+        if self.routers.is_empty() && self.starting_nodes.is_empty() {
             self.bootstrap_attempt = 0;
             return self.set_state(State::Bootstrapped, line!());
         }
 
         self.router_addresses = resolve(&self.routers, socket.ip_version()).await;
 
-        if self.router_addresses.is_empty() {
+        if self.router_addresses.is_empty() && self.starting_nodes.is_empty() {
             // This doesn't need to be counted as a failed bootstrap attempt because we have not
             // yet pinged any of the routers (bootstrap nodes) and thus don't need to do the
             // exponential backoff so as to not stress them.
             self.bootstrap_attempt = 0;
-            idle_timeout_in(timer, NO_NETWORK_TIMEOUT);
+            idle_timeout_in(timer, self.config.bootstrap_retry_timeout);
             return self.set_state(State::IdleBeforeRebootstrap, line!());
         }
 
@@ -136,19 +136,22 @@ impl TableBootstrap {
         let trans_id = self.id_generator.generate();
 
         // Set a timer to begin the actual bootstrap
-        let timeout = transaction_timeout_in(timer, INITIAL_TIMEOUT, trans_id);
+        let timeout =
+            transaction_timeout_in(timer, self.config.bootstrap_initial_timeout, trans_id);
 
-        self.active_messages.insert(trans_id, timeout);
+        self.active_messages
+            .insert(trans_id, (timeout, Instant::now()));
 
         let find_node_msg = Message {
             transaction_id: trans_id.as_ref().to_vec(),
+            read_only: self.read_only,
+            version: None,
             body: MessageBody::Request(Request::FindNode(FindNodeRequest {
                 id: self.table_id,
                 target: self.table_id,
                 want: None, // we want only contacts of the same address family we have.
             })),
-        }
-        .encode();
+        };
 
         // Ping all initial routers and nodes
         self.initial_responses_expected = 0;
@@ -159,9 +162,9 @@ impl TableBootstrap {
             .iter()
             .chain(self.starting_nodes.iter())
         {
-            match socket.send(&find_node_msg, *addr).await {
+            match socket.send(find_node_msg.clone(), *addr).await {
                 Ok(()) => {
-                    if self.initial_responses_expected < PINGS_PER_BUCKET {
+                    if self.initial_responses_expected < self.config.bucket_size {
                         self.initial_responses_expected += 1
                     }
                 }
@@ -188,7 +191,7 @@ impl TableBootstrap {
             // yet pinged any of the routers (bootstrap nodes) and thus don't need to do the
             // exponential backoff so as to not stress them.
             self.bootstrap_attempt = 0;
-            idle_timeout_in(timer, NO_NETWORK_TIMEOUT);
+            idle_timeout_in(timer, self.config.bootstrap_retry_timeout);
             self.set_state(State::IdleBeforeRebootstrap, line!())
         }
     }
@@ -208,15 +211,20 @@ impl TableBootstrap {
     /// Return true if the bootstrap state has changed.
     pub async fn recv_response(
         &mut self,
-        addr: SocketAddr,
+        node: &NodeHandle,
         trans_id: &TransactionID,
         table: &mut RoutingTable,
         socket: &Socket,
         timer: &mut Timer<ScheduledTaskCheck>,
     ) -> bool {
         // Process the message transaction id
-        let timeout = if let Some(t) = self.active_messages.get(trans_id) {
-            *t
+        let timeout = if let Some((timeout, sent)) = self.active_messages.get(trans_id) {
+            // The initial round is sent to everyone at once, so its responses are timed too.
+            if let Some(node) = table.find_node_mut(node) {
+                node.record_rtt(sent.elapsed());
+            }
+
+            *timeout
         } else {
             log::debug!(
                 "{}: Received expired/unsolicited node response for an active table bootstrap",
@@ -230,7 +238,7 @@ impl TableBootstrap {
         // we receive sufficient number of unique response. After the initial round, every message
         // has its own transaction id so clear it immediately.
         if self.curr_bootstrap_bucket == 0 {
-            self.initial_responses.insert(addr);
+            self.initial_responses.insert(node.addr);
 
             if self.initial_responses.len() >= self.initial_responses_expected {
                 timer.cancel(timeout);
@@ -302,10 +310,10 @@ impl TableBootstrap {
     ) -> bool {
         match self.state {
             State::Bootstrapped => {
-                if table.num_good_nodes() < GOOD_NODE_THRESHOLD {
+                if table.num_good_nodes() < self.config.good_node_threshold {
                     self.start(socket, timer).await
                 } else {
-                    idle_timeout_in(timer, PERIODIC_CHECK_TIMEOUT);
+                    idle_timeout_in(timer, self.config.bootstrap_check_interval);
                     false
                 }
             }
@@ -328,9 +336,9 @@ impl TableBootstrap {
         );
         loop {
             if self.curr_bootstrap_bucket >= table::MAX_BUCKETS {
-                if table.num_good_nodes() >= GOOD_NODE_THRESHOLD {
+                if table.num_good_nodes() >= self.config.good_node_threshold {
                     self.bootstrap_attempt = 0;
-                    idle_timeout_in(timer, PERIODIC_CHECK_TIMEOUT);
+                    idle_timeout_in(timer, self.config.bootstrap_check_interval);
                     return self.set_state(State::Bootstrapped, line!());
                 } else {
                     idle_timeout_in(timer, self.calculate_retry_duration());
@@ -346,12 +354,12 @@ impl TableBootstrap {
                     table
                         .closest_nodes(target_id)
                         .filter(|n| n.status() == NodeStatus::Questionable)
-                        .take(PINGS_PER_BUCKET)
+                        .take(self.config.bucket_size)
                         .map(|node| *node.handle())
                         .collect()
                 } else {
                     let mut buckets = table.buckets().skip(self.curr_bootstrap_bucket - 2);
-                    let dummy_bucket = Bucket::new();
+                    let dummy_bucket = Bucket::new(0);
 
                     // Sloppy probabilities of our target node residing at the node
                     let percent_25_bucket = if let Some(bucket) = buckets.next() {
@@ -378,7 +386,7 @@ impl TableBootstrap {
                         .chain(percent_50_bucket)
                         .chain(percent_100_bucket)
                         .filter(|n| n.status() == NodeStatus::Questionable)
-                        .take(PINGS_PER_BUCKET)
+                        .take(self.config.bucket_size)
                         .map(|node| *node.handle())
                         .collect()
                 };
@@ -414,19 +422,21 @@ impl TableBootstrap {
 
             let find_node_msg = Message {
                 transaction_id: trans_id.as_ref().to_vec(),
+                read_only: self.read_only,
+                version: None,
                 body: MessageBody::Request(Request::FindNode(FindNodeRequest {
                     id: table.node_id(),
                     target: target_id,
                     want: None,
                 })),
-            }
-            .encode();
+            };
 
             // Add a timeout for the node
-            let timeout = transaction_timeout_in(timer, NODE_TIMEOUT, trans_id);
+            let timeout =
+                transaction_timeout_in(timer, self.config.bootstrap_node_timeout, trans_id);
 
             // Send the message to the node
-            if let Err(error) = socket.send(&find_node_msg, node.addr).await {
+            if let Err(error) = socket.send(find_node_msg, node.addr).await {
                 log::error!(
                     "{}: Could not send a bootstrap message: {}",
                     self.ip_version,
@@ -441,7 +451,8 @@ impl TableBootstrap {
             }
 
             // Create an entry for the timeout in the map
-            self.active_messages.insert(trans_id, timeout);
+            self.active_messages
+                .insert(trans_id, (timeout, Instant::now()));
 
             messages_sent += 1;
         }
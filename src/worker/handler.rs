@@ -1,25 +1,39 @@
 use super::{
-    bootstrap::TableBootstrap, lookup::TableLookup, refresh::TableRefresh, socket::Socket,
-    timer::Timer, ActionStatus, BootstrapTimeout, IpVersion, OneshotTask, ScheduledTaskCheck,
-    StartLookup, State, WorkerError,
+    bootstrap::TableBootstrap,
+    lookup::TableLookup,
+    refresh::TableRefresh,
+    socket::Socket,
+    stats::Method,
+    timer::{Timeout, Timer},
+    ActionStatus, BootstrapTimeout, DhtEvent, EventSubscribers, IpVersion, LookupEvent, LookupKind,
+    OneshotTask, PingError, PingResponse, ScheduledTaskCheck, Sibling, StartAnnounce, StartLookup,
+    State, Stats, WorkerError,
 };
 use crate::{
+    announce::AnnounceOptions,
+    config::DhtConfig,
     id::InfoHash,
-    message::{error_code, Error, Message, MessageBody, Request, Response, Want},
+    ip_voter::IpVoter,
+    item::Item,
+    item_storage::ItemStorage,
+    message::{error_code, Error, Message, MessageBody, PingRequest, Request, Response, Want},
     routing::{
         node::{Node, NodeHandle},
-        table::RoutingTable,
+        snapshot::{RoutingTableInfo, RoutingTableSnapshot},
+        table::{RoutingTable, TableChange},
     },
     storage::AnnounceStorage,
     token::{Token, TokenStore},
-    transaction::{AIDGenerator, ActionID, TransactionID},
+    transaction::{AIDGenerator, ActionID, MIDGenerator, TransactionID},
 };
-use futures_util::StreamExt;
+use futures_util::{future::BoxFuture, stream::FuturesUnordered, StreamExt};
 use std::{
+    any::Any,
     collections::{HashMap, HashSet},
     convert::AsRef,
-    net::SocketAddr,
-    time::Duration,
+    future, mem,
+    net::{IpAddr, SocketAddr},
+    time::{Duration, Instant},
 };
 use tokio::{
     select,
@@ -29,8 +43,12 @@ use tokio::{
 /// Storage for our EventLoop to invoke actions upon.
 pub(crate) struct DhtHandler {
     running: bool,
+    // Shutting down, only waiting for the lookups to get the responses to their announces and
+    // puts.
+    stopping: bool,
     command_rx: mpsc::UnboundedReceiver<OneshotTask>,
     timer: Timer<ScheduledTaskCheck>,
+    config: DhtConfig,
     read_only: bool,
     announce_port: Option<u16>,
     socket: Socket,
@@ -38,6 +56,7 @@ pub(crate) struct DhtHandler {
     aid_generator: AIDGenerator,
     routing_table: RoutingTable,
     active_stores: AnnounceStorage,
+    item_store: ItemStorage,
     bootstrap: TableBootstrap,
 
     next_bootstrap_txs_id: u64,
@@ -47,12 +66,43 @@ pub(crate) struct DhtHandler {
     refresh: TableRefresh,
     // Ongoing TableLookups.
     lookups: HashMap<ActionID, TableLookup>,
+    // Resolve to the id of a lookup whose results are no longer awaited, or to `None` once the
+    // lookup is dropped.
+    abandoned_lookups: FuturesUnordered<BoxFuture<'static, Option<ActionID>>>,
+    // Announces repeated periodically, by id.
+    next_announce_id: u64,
+    announces: HashMap<u64, PeriodicAnnounce>,
+    // Pings requested by the user, waiting for the response.
+    ping_id_generator: MIDGenerator,
+    pings: HashMap<TransactionID, PendingPing>,
+
+    // Nodes which sent us read-only requests. They don't respond to requests, so we keep them out
+    // of the routing table.
+    read_only_nodes: ReadOnlyNodes,
+
+    // Nodes we recently sent info hash samples to (BEP51).
+    sample_requesters: SampleRequesters,
+
+    // Tally of the external addresses reported by the remote nodes.
+    ip_voter: IpVoter,
+    external_addr_txs: Vec<mpsc::UnboundedSender<SocketAddr>>,
+
+    // Subscribers to our events.
+    events: EventSubscribers,
+
+    // The handler of the other address family, when running dual-stack.
+    sibling: Option<Sibling>,
+
+    // Where to send the socket and the routing table once the handler stops, if asked to.
+    shutdown_tx: Option<oneshot::Sender<(Box<dyn Any + Send>, RoutingTableSnapshot)>>,
 }
 
 impl DhtHandler {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         table: RoutingTable,
-        socket: Socket,
+        mut socket: Socket,
+        config: DhtConfig,
         read_only: bool,
         routers: HashSet<String>,
         nodes: HashSet<SocketAddr>,
@@ -63,23 +113,33 @@ impl DhtHandler {
 
         // The refresh task to execute after the bootstrap
         let mid_generator = aid_generator.generate();
-        let table_refresh = TableRefresh::new(mid_generator);
+        let ping_mid_generator = aid_generator.generate();
+        let table_refresh = TableRefresh::new(mid_generator, ping_mid_generator, read_only, config);
 
         let mid_generator = aid_generator.generate();
         let bootstrap = TableBootstrap::new(
             socket.ip_version(),
             table.node_id(),
+            config,
+            read_only,
             mid_generator,
             routers,
             nodes,
         );
 
+        let ping_id_generator = aid_generator.generate();
+
         let timer = Timer::new();
 
+        let events = EventSubscribers::default();
+        socket.set_event_subscribers(events.clone());
+
         Self {
             running: true,
+            stopping: false,
             command_rx,
             timer,
+            config,
             read_only,
             announce_port,
             socket,
@@ -87,21 +147,49 @@ impl DhtHandler {
             aid_generator,
             routing_table: table,
             active_stores: AnnounceStorage::new(),
+            item_store: ItemStorage::new(),
             bootstrap,
             next_bootstrap_txs_id: 0,
             bootstrap_txs: HashMap::new(),
             refresh: table_refresh,
             lookups: HashMap::new(),
+            abandoned_lookups: FuturesUnordered::new(),
+            next_announce_id: 0,
+            announces: HashMap::new(),
+            ping_id_generator,
+            pings: HashMap::new(),
+            read_only_nodes: ReadOnlyNodes::new(),
+            sample_requesters: SampleRequesters::new(),
+            ip_voter: IpVoter::new(),
+            external_addr_txs: Vec::new(),
+            events,
+            sibling: None,
+            shutdown_tx: None,
         }
     }
 
+    /// Link this handler with the one of the other address family, making them exchange the nodes
+    /// they learn of and ask for the nodes of both families in lookups.
+    pub fn set_sibling(&mut self, sibling: Sibling) {
+        self.sibling = Some(sibling);
+    }
+
     fn ip_version(&self) -> IpVersion {
         self.socket.ip_version()
     }
 
     pub async fn run(mut self) {
         while self.running {
-            self.run_once().await
+            self.run_once().await;
+
+            if self.stopping && self.lookups.is_empty() {
+                self.shutdown();
+            }
+        }
+
+        if let Some(tx) = self.shutdown_tx.take() {
+            let snapshot = self.routing_table.snapshot();
+            tx.send((self.socket.into_inner(), snapshot)).unwrap_or(())
         }
     }
 
@@ -113,7 +201,7 @@ impl DhtHandler {
                 let token = token.unwrap();
                 self.handle_timeout(token).await
             }
-            command = self.command_rx.recv() => {
+            command = self.command_rx.recv(), if !self.stopping => {
                 if let Some(command) = command {
                     self.handle_command(command).await
                 } else {
@@ -128,7 +216,18 @@ impl DhtHandler {
                     Err(error) => log::warn!("{}: Failed to receive incoming message: {}", self.ip_version(), error),
                 }
             }
+            nodes = recv_from_sibling(&mut self.sibling) => {
+                self.handle_sibling_nodes(nodes)
+            }
+            Some(action_id) = self.abandoned_lookups.next(), if !self.abandoned_lookups.is_empty() => {
+                if let Some(action_id) = action_id {
+                    self.handle_abandoned_lookup(action_id)
+                }
+            }
         }
+
+        self.ping_questionable_nodes().await;
+        self.publish_table_changes();
     }
 
     async fn handle_command(&mut self, task: OneshotTask) {
@@ -142,8 +241,31 @@ impl DhtHandler {
             OneshotTask::StartLookup(lookup) => {
                 self.handle_start_lookup(lookup).await;
             }
+            OneshotTask::StartAnnounce(announce) => {
+                self.handle_start_announce(announce).await;
+            }
             OneshotTask::GetLocalAddr(tx) => self.handle_get_local_addr(tx),
             OneshotTask::GetState(tx) => self.handle_get_state(tx),
+            OneshotTask::GetStats(tx) => self.handle_get_stats(tx),
+            OneshotTask::GetExternalAddr(tx) => self.handle_get_external_addr(tx),
+            OneshotTask::WatchExternalAddr(tx) => self.external_addr_txs.push(tx),
+            OneshotTask::WatchEvents(tx) => tx.send(self.events.subscribe()).unwrap_or(()),
+            OneshotTask::Ping(addr, tx) => self.handle_start_ping(addr, tx).await,
+            OneshotTask::GetClientCensus(tx) => self.handle_get_client_census(tx),
+            OneshotTask::ExportRoutingTable(tx) => {
+                tx.send(self.routing_table.snapshot()).unwrap_or(())
+            }
+            OneshotTask::InspectRoutingTable(tx) => tx
+                .send(RoutingTableInfo {
+                    ip_version: self.ip_version(),
+                    node_id: self.routing_table.node_id(),
+                    bucket_size: self.routing_table.bucket_size(),
+                    buckets: self.routing_table.bucket_infos(),
+                })
+                .unwrap_or(()),
+            OneshotTask::Shutdown { finish_lookups, tx } => {
+                self.handle_shutdown(finish_lookups, tx).await
+            }
         }
     }
 
@@ -153,17 +275,36 @@ impl DhtHandler {
                 self.handle_check_table_refresh().await;
             }
             ScheduledTaskCheck::BootstrapTimeout(timeout) => {
+                if let BootstrapTimeout::Transaction(_) = timeout {
+                    self.socket.stats().timeouts += 1;
+                }
+
                 self.handle_check_bootstrap_timeout(timeout).await;
             }
             ScheduledTaskCheck::UserBootstrappedTimeout(entry) => {
                 self.handle_check_user_bootstrapped_timeout(entry).await;
             }
             ScheduledTaskCheck::LookupTimeout(trans_id) => {
+                self.socket.stats().timeouts += 1;
                 self.handle_check_lookup_timeout(trans_id).await;
             }
+            ScheduledTaskCheck::RefreshTimeout(trans_id) => {
+                self.socket.stats().timeouts += 1;
+                self.refresh
+                    .recv_timeout(&trans_id, &mut self.routing_table);
+            }
             ScheduledTaskCheck::LookupEndGame(trans_id) => {
                 self.handle_check_lookup_endgame(trans_id).await;
             }
+            ScheduledTaskCheck::PingTimeout(trans_id) => {
+                if let Some(ping) = self.pings.remove(&trans_id) {
+                    self.socket.stats().timeouts += 1;
+                    ping.tx.send(Err(PingError::Timeout)).unwrap_or(())
+                }
+            }
+            ScheduledTaskCheck::AnnounceRound(id) => {
+                self.start_announce_round(id).await;
+            }
         }
     }
 
@@ -172,81 +313,116 @@ impl DhtHandler {
         buffer: &[u8],
         addr: SocketAddr,
     ) -> Result<(), WorkerError> {
-        let message = Message::decode(buffer).map_err(WorkerError::InvalidBencode)?;
+        let message = match Message::decode(buffer) {
+            Ok(message) => message,
+            Err(error) => {
+                self.socket.stats().decode_failures += 1;
+                return Err(WorkerError::InvalidBencode(error));
+            }
+        };
+
+        self.socket.stats().record_received(&message.body);
+
+        let read_only = message.read_only;
+        if read_only {
+            self.read_only_nodes.insert(addr);
+        }
 
         // Do not process requests if we are read only
-        // TODO: Add read only flags to messages we send it we are read only!
-        // Also, check for read only flags on responses we get before adding nodes
-        // to our RoutingTable.
         if self.read_only && matches!(message.body, MessageBody::Request(_)) {
             return Ok(());
         }
 
         log::trace!("{}: Received {:?}", self.ip_version(), message);
 
+        let version = message.version;
+
+        // Unknown requests with a target are answered like find_node (Mainline DHT extension).
+        let body = match message.body {
+            MessageBody::Request(Request::Unknown(request)) => match request.into_find_node() {
+                Ok(request) => MessageBody::Request(Request::FindNode(request)),
+                Err(request) => MessageBody::Request(Request::Unknown(request)),
+            },
+            body => body,
+        };
+
         // Process the given message
-        match message.body {
+        match body {
             MessageBody::Request(Request::Ping(p)) => {
                 let node = NodeHandle::new(p.id, addr);
 
-                // Node requested from us, mark it in the Routingtable
-                if let Some(n) = self.routing_table.find_node_mut(&node) {
-                    n.remote_request()
+                // Node requested from us, mark it in the Routingtable (unless it's read only)
+                if !read_only {
+                    if let Some(n) = self.routing_table.find_node_mut(&node) {
+                        n.remote_request();
+                        n.set_version(version.clone());
+                    }
                 }
 
                 let ping_rsp = Response {
-                    id: self.routing_table.node_id(),
-                    values: vec![],
-                    nodes_v4: vec![],
-                    nodes_v6: vec![],
-                    token: None,
+                    ip: Some(addr),
+                    ..Response::new(self.routing_table.node_id())
                 };
                 let ping_msg = Message {
                     transaction_id: message.transaction_id,
+                    read_only: false,
+                    version: None,
                     body: MessageBody::Response(ping_rsp),
                 };
-                let ping_msg = ping_msg.encode();
 
-                self.socket.send(&ping_msg, addr).await?
+                self.socket.send_reply(ping_msg, addr, Method::Ping).await?
             }
             MessageBody::Request(Request::FindNode(f)) => {
                 let node = NodeHandle::new(f.id, addr);
 
-                // Node requested from us, mark it in the Routingtable
-                if let Some(n) = self.routing_table.find_node_mut(&node) {
-                    n.remote_request()
+                // Node requested from us, mark it in the Routingtable (unless it's read only)
+                if !read_only {
+                    if let Some(n) = self.routing_table.find_node_mut(&node) {
+                        n.remote_request();
+                        n.set_version(version.clone());
+                    }
                 }
 
                 let (nodes_v4, nodes_v6) = self.find_closest_nodes(f.target, f.want)?;
 
                 let find_node_rsp = Response {
-                    id: self.routing_table.node_id(),
-                    values: vec![],
                     nodes_v4,
                     nodes_v6,
-                    token: None,
+                    ip: Some(addr),
+                    ..Response::new(self.routing_table.node_id())
                 };
                 let find_node_msg = Message {
                     transaction_id: message.transaction_id,
+                    read_only: false,
+                    version: None,
                     body: MessageBody::Response(find_node_rsp),
                 };
-                let find_node_msg = find_node_msg.encode();
 
-                self.socket.send(&find_node_msg, addr).await?
+                self.socket
+                    .send_reply(find_node_msg, addr, Method::FindNode)
+                    .await?
             }
             MessageBody::Request(Request::GetPeers(g)) => {
                 let node = NodeHandle::new(g.id, addr);
 
-                // Node requested from us, mark it in the Routingtable
-                if let Some(n) = self.routing_table.find_node_mut(&node) {
-                    n.remote_request()
+                // Node requested from us, mark it in the Routingtable (unless it's read only)
+                if !read_only {
+                    if let Some(n) = self.routing_table.find_node_mut(&node) {
+                        n.remote_request();
+                        n.set_version(version.clone());
+                    }
                 }
 
                 // TODO: Check what the maximum number of values we can give without overflowing a udp packet
                 // Also, if we arent going to give all of the contacts, we may want to shuffle which ones we give
-                let values: Vec<_> = self
-                    .active_stores
-                    .find_items(&g.info_hash)
+                // Seeds are left out if the requester asked so (BEP33)
+                let values: Vec<_> = if g.noseed {
+                    self.active_stores.find_leechers(&g.info_hash).collect()
+                } else {
+                    self.active_stores.find_items(&g.info_hash).collect()
+                };
+                let values: Vec<_> = values
+                    .into_iter()
                     .filter(|value_addr| {
                         // According to the spec (BEP32), `values` should contain only addresses of the
                         // same family as the address the request came from. The `want` field affects only
@@ -260,31 +436,47 @@ impl DhtHandler {
                     })
                     .collect();
 
+                let (bf_seeds, bf_peers) = if g.scrape {
+                    let (seeds, peers) = self.active_stores.scrape(&g.info_hash);
+                    (Some(seeds), Some(peers))
+                } else {
+                    (None, None)
+                };
+
                 // Grab the closest nodes
                 let (nodes_v4, nodes_v6) = self.find_closest_nodes(g.info_hash, g.want)?;
                 let token = self.token_store.checkout(addr.ip());
 
                 let get_peers_rsp = Response {
-                    id: self.routing_table.node_id(),
                     values,
                     nodes_v4,
                     nodes_v6,
                     token: Some(token.as_ref().to_vec()),
+                    bf_seeds,
+                    bf_peers,
+                    ip: Some(addr),
+                    ..Response::new(self.routing_table.node_id())
                 };
                 let get_peers_msg = Message {
                     transaction_id: message.transaction_id,
+                    read_only: false,
+                    version: None,
                     body: MessageBody::Response(get_peers_rsp),
                 };
-                let get_peers_msg = get_peers_msg.encode();
 
-                self.socket.send(&get_peers_msg, addr).await?
+                self.socket
+                    .send_reply(get_peers_msg, addr, Method::GetPeers)
+                    .await?
             }
             MessageBody::Request(Request::AnnouncePeer(a)) => {
                 let node = NodeHandle::new(a.id, addr);
 
-                // Node requested from us, mark it in the Routingtable
-                if let Some(n) = self.routing_table.find_node_mut(&node) {
-                    n.remote_request()
+                // Node requested from us, mark it in the Routingtable (unless it's read only)
+                if !read_only {
+                    if let Some(n) = self.routing_table.find_node_mut(&node) {
+                        n.remote_request();
+                        n.set_version(version.clone());
+                    }
                 }
 
                 // Validate the token
@@ -310,52 +502,265 @@ impl DhtHandler {
                         "{}: Remote node sent us an invalid token for an AnnounceRequest",
                         self.ip_version()
                     );
+                    self.events.emit(DhtEvent::InvalidToken { addr });
+
                     Message {
                         transaction_id: message.transaction_id,
+                        read_only: false,
+                        version: None,
                         body: MessageBody::Error(Error {
                             code: error_code::PROTOCOL_ERROR,
                             message: "received an invalid token".to_owned(),
                         }),
                     }
-                    .encode()
-                } else if self.active_stores.add_item(a.info_hash, connect_addr) {
+                } else if self
+                    .active_stores
+                    .add_item(a.info_hash, connect_addr, a.seed)
+                {
                     // Node successfully stored the value with us, send an announce response
+                    self.events.emit(DhtEvent::AnnounceStored {
+                        info_hash: a.info_hash,
+                        peer: connect_addr,
+                    });
+
                     Message {
                         transaction_id: message.transaction_id,
+                        read_only: false,
+                        version: None,
                         body: MessageBody::Response(Response {
-                            id: self.routing_table.node_id(),
-                            values: vec![],
-                            nodes_v4: vec![],
-                            nodes_v6: vec![],
-                            token: None,
+                            ip: Some(addr),
+                            ..Response::new(self.routing_table.node_id())
                         }),
                     }
-                    .encode()
                 } else {
                     // Node unsuccessfully stored the value with us, send them an error message
                     // TODO: Spec doesnt actually say what error message to send, or even if we should send one...
                     log::warn!(
                         "{}: AnnounceStorage failed to store contact information because it is full", self.ip_version()
                     );
+                    self.events.emit(DhtEvent::AnnounceRejected {
+                        info_hash: a.info_hash,
+                        addr,
+                    });
 
                     Message {
                         transaction_id: message.transaction_id,
+                        read_only: false,
+                        version: None,
                         body: MessageBody::Error(Error {
                             code: error_code::SERVER_ERROR,
                             message: "announce storage is full".to_owned(),
                         }),
                     }
-                    .encode()
                 };
 
-                self.socket.send(&response_msg, addr).await?
+                self.socket
+                    .send_reply(response_msg, addr, Method::AnnouncePeer)
+                    .await?
+            }
+            MessageBody::Request(Request::Get(g)) => {
+                let node = NodeHandle::new(g.id, addr);
+
+                // Node requested from us, mark it in the Routingtable (unless it's read only)
+                if !read_only {
+                    if let Some(n) = self.routing_table.find_node_mut(&node) {
+                        n.remote_request();
+                        n.set_version(version.clone());
+                    }
+                }
+
+                let (nodes_v4, nodes_v6) = self.find_closest_nodes(g.target, None)?;
+                let token = self.token_store.checkout(addr.ip());
+
+                let mut get_rsp = Response {
+                    nodes_v4,
+                    nodes_v6,
+                    token: Some(token.as_ref().to_vec()),
+                    ip: Some(addr),
+                    ..Response::new(self.routing_table.node_id())
+                };
+
+                match self.item_store.get(&g.target) {
+                    Some(Item::Immutable(value)) => {
+                        get_rsp.v = Some(value.clone());
+                    }
+                    // Send mutable items only if they are newer than what the requester has.
+                    Some(Item::Mutable(item))
+                        if g.seq.map(|seq| item.seq() > seq).unwrap_or(true) =>
+                    {
+                        get_rsp.v = Some(item.value().clone());
+                        get_rsp.k = Some(item.key().as_bytes().to_vec());
+                        get_rsp.sig = Some(item.signature().to_bytes().to_vec());
+                        get_rsp.seq = Some(item.seq());
+                    }
+                    Some(Item::Mutable(_)) | None => (),
+                }
+                let get_msg = Message {
+                    transaction_id: message.transaction_id,
+                    read_only: false,
+                    version: None,
+                    body: MessageBody::Response(get_rsp),
+                };
+
+                self.socket.send_reply(get_msg, addr, Method::Get).await?
+            }
+            MessageBody::Request(Request::Put(p)) => {
+                let node = NodeHandle::new(p.id, addr);
+
+                // Node requested from us, mark it in the Routingtable (unless it's read only)
+                if !read_only {
+                    if let Some(n) = self.routing_table.find_node_mut(&node) {
+                        n.remote_request();
+                        n.set_version(version.clone());
+                    }
+                }
+
+                // Validate the token
+                let is_valid = match Token::new(&p.token) {
+                    Ok(t) => self.token_store.checkin(addr.ip(), t),
+                    Err(_) => false,
+                };
+
+                let body = if !is_valid {
+                    log::debug!(
+                        "{}: Remote node sent us an invalid token for a PutRequest",
+                        self.ip_version()
+                    );
+                    self.events.emit(DhtEvent::InvalidToken { addr });
+
+                    MessageBody::Error(Error {
+                        code: error_code::PROTOCOL_ERROR,
+                        message: "received an invalid token".to_owned(),
+                    })
+                } else {
+                    match self.item_store.put(p) {
+                        Ok(()) => MessageBody::Response(Response {
+                            ip: Some(addr),
+                            ..Response::new(self.routing_table.node_id())
+                        }),
+                        Err(error) => {
+                            log::debug!("{}: Rejected a PutRequest: {}", self.ip_version(), error);
+
+                            MessageBody::Error(Error {
+                                code: error.code(),
+                                message: error.to_string(),
+                            })
+                        }
+                    }
+                };
+
+                let response_msg = Message {
+                    transaction_id: message.transaction_id,
+                    read_only: false,
+                    version: None,
+                    body,
+                };
+
+                self.socket
+                    .send_reply(response_msg, addr, Method::Put)
+                    .await?
+            }
+            MessageBody::Request(Request::SampleInfohashes(r)) => {
+                let node = NodeHandle::new(r.id, addr);
+
+                // Node requested from us, mark it in the Routingtable (unless it's read only)
+                if !read_only {
+                    if let Some(n) = self.routing_table.find_node_mut(&node) {
+                        n.remote_request();
+                        n.set_version(version.clone());
+                    }
+                }
+
+                let (nodes_v4, nodes_v6) = self.find_closest_nodes(r.target, r.want)?;
+
+                // Nodes asking again before the interval we told them elapsed get no samples, so
+                // don't bother drawing them.
+                let (max_samples, interval) = match self.sample_requesters.check(addr.ip()) {
+                    None => (MAX_SAMPLES, SAMPLE_INTERVAL),
+                    Some(remaining) => (0, remaining),
+                };
+                let (samples, num) = self.active_stores.sample_info_hashes(max_samples);
+
+                let sample_rsp = Response {
+                    nodes_v4,
+                    nodes_v6,
+                    interval: Some(interval.as_secs() as i64),
+                    num: Some(num as i64),
+                    samples: Some(samples),
+                    ip: Some(addr),
+                    ..Response::new(self.routing_table.node_id())
+                };
+                let sample_msg = Message {
+                    transaction_id: message.transaction_id,
+                    read_only: false,
+                    version: None,
+                    body: MessageBody::Response(sample_rsp),
+                };
+
+                self.socket
+                    .send_reply(sample_msg, addr, Method::SampleInfohashes)
+                    .await?
+            }
+            MessageBody::Request(Request::Unknown(request)) => {
+                log::debug!(
+                    "{}: Received request with unknown method {:?}",
+                    self.ip_version(),
+                    String::from_utf8_lossy(&request.method)
+                );
+
+                let error_msg = Message {
+                    transaction_id: message.transaction_id,
+                    read_only: false,
+                    version: None,
+                    body: MessageBody::Error(Error {
+                        code: error_code::METHOD_UNKNOWN,
+                        message: "Method Unknown".to_owned(),
+                    }),
+                };
+
+                self.socket
+                    .send_reply(error_msg, addr, Method::Unknown)
+                    .await?
+            }
+            MessageBody::Request(Request::Malformed(request)) => {
+                log::debug!(
+                    "{}: Received malformed {:?} request",
+                    self.ip_version(),
+                    String::from_utf8_lossy(&request.method)
+                );
+
+                let error_msg = Message {
+                    transaction_id: message.transaction_id,
+                    read_only: false,
+                    version: None,
+                    body: MessageBody::Error(Error {
+                        code: error_code::PROTOCOL_ERROR,
+                        message: "Protocol Error".to_owned(),
+                    }),
+                };
+
+                self.socket
+                    .send_reply(error_msg, addr, Method::Malformed)
+                    .await?
             }
             MessageBody::Response(rsp) => {
                 let trans_id = TransactionID::from_bytes(&message.transaction_id)
                     .ok_or(WorkerError::InvalidTransactionId)?;
-                self.handle_incoming_response(trans_id, addr, rsp).await?;
+                let result = self
+                    .handle_incoming_response(trans_id, addr, version, rsp)
+                    .await;
+
+                if let Err(WorkerError::UnsolicitedResponse) = result {
+                    self.socket.stats().unsolicited_responses += 1;
+                }
+
+                result?;
+            }
+            MessageBody::Error(error) => {
+                let trans_id = TransactionID::from_bytes(&message.transaction_id)
+                    .ok_or(WorkerError::InvalidTransactionId)?;
+                self.handle_incoming_error(trans_id, addr, error).await;
             }
-            MessageBody::Error(_) => (),
         }
 
         Ok(())
@@ -365,27 +770,41 @@ impl DhtHandler {
         &mut self,
         trans_id: TransactionID,
         addr: SocketAddr,
+        version: Option<Vec<u8>>,
         rsp: Response,
     ) -> Result<(), WorkerError> {
-        let node = Node::as_good(rsp.id, addr);
+        let mut node = Node::as_good(rsp.id, addr);
+        node.set_version(version);
+        let reported_addr = rsp.ip;
 
-        let nodes = match self.socket.ip_version() {
-            IpVersion::V4 => &rsp.nodes_v4,
-            IpVersion::V6 => &rsp.nodes_v6,
+        let (nodes, other_nodes) = match self.socket.ip_version() {
+            IpVersion::V4 => (&rsp.nodes_v4, &rsp.nodes_v6),
+            IpVersion::V6 => (&rsp.nodes_v6, &rsp.nodes_v4),
+        };
+        // Nodes of the other address family go to the routing table of the other handler.
+        let other_nodes = match &self.sibling {
+            Some(_) if !other_nodes.is_empty() => Some(other_nodes.clone()),
+            _ => None,
         };
 
         if self.bootstrap.action_id() == trans_id.action_id() {
+            self.socket
+                .stats()
+                .responses_received
+                .record_response(Method::FindNode);
+
             add_nodes(
                 &mut self.routing_table,
                 &node,
                 nodes,
                 self.bootstrap.router_addresses(),
+                &mut self.read_only_nodes,
             );
 
             let state_changed = self
                 .bootstrap
                 .recv_response(
-                    addr,
+                    node.handle(),
                     &trans_id,
                     &mut self.routing_table,
                     &self.socket,
@@ -398,11 +817,20 @@ impl DhtHandler {
                     .await;
             }
         } else if let Some(lookup) = self.lookups.get_mut(&trans_id.action_id()) {
+            let method = lookup
+                .request_method(&trans_id)
+                .ok_or(WorkerError::UnsolicitedResponse)?;
+            self.socket
+                .stats()
+                .responses_received
+                .record_response(method);
+
             add_nodes(
                 &mut self.routing_table,
                 &node,
                 nodes,
                 self.bootstrap.router_addresses(),
+                &mut self.read_only_nodes,
             );
 
             match lookup
@@ -419,20 +847,146 @@ impl DhtHandler {
                 ActionStatus::Ongoing => (),
                 ActionStatus::Completed => self.handle_lookup_completed(trans_id).await,
             }
-        } else if self.refresh.action_id() == trans_id.action_id() {
+        } else if let Some(method) =
+            self.refresh
+                .recv_response(&trans_id, &mut self.routing_table, &mut self.timer)
+        {
+            self.socket
+                .stats()
+                .responses_received
+                .record_response(method);
+
             add_nodes(
                 &mut self.routing_table,
                 &node,
                 nodes,
                 self.bootstrap.router_addresses(),
+                &mut self.read_only_nodes,
             );
+        } else if self.ping_id_generator.action_id() == trans_id.action_id() {
+            let ping = match self.pings.remove(&trans_id) {
+                Some(ping) if ping.addr == addr => ping,
+                Some(ping) => {
+                    self.pings.insert(trans_id, ping);
+                    return Err(WorkerError::UnsolicitedResponse);
+                }
+                None => return Err(WorkerError::UnsolicitedResponse),
+            };
+
+            self.timer.cancel(ping.timeout);
+            self.socket
+                .stats()
+                .responses_received
+                .record_response(Method::Ping);
+
+            add_nodes(
+                &mut self.routing_table,
+                &node,
+                &[],
+                self.bootstrap.router_addresses(),
+                &mut self.read_only_nodes,
+            );
+
+            let rtt = ping.sent.elapsed();
+
+            if let Some(node) = self.routing_table.find_node_mut(node.handle()) {
+                node.record_rtt(rtt);
+            }
+
+            ping.tx
+                .send(Ok(PingResponse { id: node.id(), rtt }))
+                .unwrap_or(())
         } else {
             return Err(WorkerError::UnsolicitedResponse);
         }
 
+        if let (Some(sibling), Some(other_nodes)) = (&self.sibling, other_nodes) {
+            sibling.send(other_nodes);
+        }
+
+        if let Some(reported_addr) = reported_addr {
+            self.handle_external_addr_vote(addr, reported_addr);
+        }
+
         Ok(())
     }
 
+    fn handle_external_addr_vote(&mut self, voter: SocketAddr, reported_addr: SocketAddr) {
+        if self.ip_voter.add_vote(voter.ip(), reported_addr) {
+            self.report_external_addr();
+        }
+    }
+
+    fn report_external_addr(&mut self) {
+        let addr = if let Some(addr) = self.ip_voter.external_addr() {
+            addr
+        } else {
+            return;
+        };
+
+        log::debug!(
+            "{}: External address changed to {}",
+            self.ip_version(),
+            addr
+        );
+
+        self.external_addr_txs.retain(|tx| tx.send(addr).is_ok());
+    }
+
+    async fn handle_incoming_error(
+        &mut self,
+        trans_id: TransactionID,
+        addr: SocketAddr,
+        error: Error,
+    ) {
+        log::debug!(
+            "{}: Received error {} ({})",
+            self.ip_version(),
+            error.message,
+            error.code
+        );
+
+        match self.pings.remove(&trans_id) {
+            Some(ping) if ping.addr == addr => {
+                self.timer.cancel(ping.timeout);
+                ping.tx
+                    .send(Err(PingError::Rejected {
+                        code: error.code,
+                        message: error.message,
+                    }))
+                    .unwrap_or(());
+                return;
+            }
+            // Someone else guessing the transaction id must not fail the ping.
+            Some(ping) => {
+                self.pings.insert(trans_id, ping);
+                return;
+            }
+            None => (),
+        }
+
+        // Only lookups and pings care about errors, everyone else treats them as timeouts.
+        let lookup = if let Some(lookup) = self.lookups.get_mut(&trans_id.action_id()) {
+            lookup
+        } else {
+            return;
+        };
+
+        match lookup
+            .recv_error(
+                &trans_id,
+                error,
+                &mut self.routing_table,
+                &self.socket,
+                &mut self.timer,
+            )
+            .await
+        {
+            ActionStatus::Ongoing => (),
+            ActionStatus::Completed => self.handle_lookup_completed(trans_id).await,
+        }
+    }
+
     async fn handle_start_bootstrap(&mut self) {
         if self.bootstrap.start(&self.socket, &mut self.timer).await {
             self.handle_bootstrap_change(self.bootstrap.is_bootstrapped())
@@ -479,6 +1033,11 @@ impl DhtHandler {
     }
 
     async fn handle_bootstrap_change(&mut self, bootstrapped: bool) {
+        self.events.emit(DhtEvent::BootstrapChanged {
+            ip_version: self.ip_version(),
+            bootstrapped,
+        });
+
         if bootstrapped {
             self.handle_bootstrap_success().await
         } else {
@@ -504,15 +1063,26 @@ impl DhtHandler {
         }
     }
 
-    async fn handle_start_lookup(&mut self, lookup: StartLookup) {
+    async fn handle_start_lookup(&mut self, lookup: StartLookup) -> Option<ActionID> {
+        // Don't send anything if the results are no longer awaited.
+        if lookup.kind.is_abandoned() {
+            return None;
+        }
+
         // Start the lookup right now if not bootstrapping
         let mid_generator = self.aid_generator.generate();
         let action_id = mid_generator.action_id();
 
+        self.events.emit(DhtEvent::LookupStarted {
+            ip_version: self.ip_version(),
+            target: lookup.target,
+        });
+
         let mut lookup = TableLookup::new(
-            lookup.info_hash,
-            lookup.announce,
-            lookup.tx,
+            lookup,
+            self.config,
+            self.read_only,
+            self.sibling.as_ref().map(|_| Want::Both),
             mid_generator,
             &mut self.routing_table,
             &self.socket,
@@ -520,15 +1090,147 @@ impl DhtHandler {
         )
         .await;
 
-        if lookup.completed() {
+        let status = if lookup.completed() {
             lookup
-                .recv_finished(self.announce_port, &mut self.routing_table, &self.socket)
-                .await;
+                .recv_finished(
+                    self.announce_port,
+                    &mut self.routing_table,
+                    &self.socket,
+                    &mut self.timer,
+                )
+                .await
         } else {
+            ActionStatus::Ongoing
+        };
+
+        if status == ActionStatus::Ongoing {
+            let abandoned = lookup.abandoned();
+            self.abandoned_lookups.push(Box::pin(
+                async move { abandoned.await.then_some(action_id) },
+            ));
             self.lookups.insert(action_id, lookup);
+            Some(action_id)
+        } else {
+            self.publish_lookup_finished(&lookup);
+            None
+        }
+    }
+
+    async fn handle_start_announce(&mut self, announce: StartAnnounce) {
+        let id = self.next_announce_id;
+        self.next_announce_id = self.next_announce_id.wrapping_add(1);
+
+        self.announces.insert(
+            id,
+            PeriodicAnnounce {
+                info_hash: announce.info_hash,
+                options: announce.options,
+                tx: announce.tx,
+                round: None,
+            },
+        );
+
+        self.start_announce_round(id).await;
+    }
+
+    async fn start_announce_round(&mut self, id: u64) {
+        if self.stopping {
+            return;
+        }
+
+        let announce = match self.announces.get(&id) {
+            Some(announce) if !announce.tx.is_closed() => announce,
+            _ => {
+                self.announces.remove(&id);
+                return;
+            }
+        };
+
+        let lookup = StartLookup {
+            target: announce.info_hash,
+            kind: LookupKind::GetPeers {
+                announce: announce.options.mode(),
+                tx: announce.tx.clone(),
+            },
+        };
+
+        match self.handle_start_lookup(lookup).await {
+            Some(action_id) => {
+                if let Some(announce) = self.announces.get_mut(&id) {
+                    announce.round = Some(action_id);
+                }
+            }
+            None => self.schedule_announce_round(id),
+        }
+    }
+
+    // Schedule the next round of the periodic announce whose round was the given lookup, if any.
+    fn handle_announce_round_finished(&mut self, action_id: ActionID) {
+        let id = self
+            .announces
+            .iter()
+            .find(|(_, announce)| announce.round == Some(action_id))
+            .map(|(id, _)| *id);
+
+        if let Some(id) = id {
+            self.schedule_announce_round(id);
         }
     }
 
+    fn schedule_announce_round(&mut self, id: u64) {
+        let announce = match self.announces.get_mut(&id) {
+            Some(announce) if !announce.tx.is_closed() => announce,
+            _ => {
+                self.announces.remove(&id);
+                return;
+            }
+        };
+
+        announce.round = None;
+        self.timer.schedule_in(
+            announce.options.next_delay(),
+            ScheduledTaskCheck::AnnounceRound(id),
+        );
+    }
+
+    async fn handle_start_ping(
+        &mut self,
+        addr: SocketAddr,
+        tx: oneshot::Sender<Result<PingResponse, PingError>>,
+    ) {
+        let trans_id = self.ping_id_generator.generate();
+        let ping_msg = Message {
+            transaction_id: trans_id.as_ref().to_vec(),
+            read_only: self.read_only,
+            version: None,
+            body: MessageBody::Request(Request::Ping(PingRequest {
+                id: self.routing_table.node_id(),
+            })),
+        };
+
+        let sent = Instant::now();
+
+        if let Err(error) = self.socket.send(ping_msg, addr).await {
+            tx.send(Err(PingError::Socket(error))).unwrap_or(());
+            return;
+        }
+
+        let timeout = self.timer.schedule_in(
+            self.config.ping_timeout,
+            ScheduledTaskCheck::PingTimeout(trans_id),
+        );
+
+        self.pings.insert(
+            trans_id,
+            PendingPing {
+                addr,
+                sent,
+                timeout,
+                tx,
+            },
+        );
+    }
+
     fn handle_get_state(&self, tx: oneshot::Sender<State>) {
         tx.send(State {
             is_running: self.running,
@@ -540,10 +1242,53 @@ impl DhtHandler {
         .unwrap_or(())
     }
 
+    fn handle_get_stats(&mut self, tx: oneshot::Sender<Stats>) {
+        let mut stats = self.socket.stats().clone();
+        stats.active_lookups = self.lookups.len();
+        stats.scheduled_timeouts = self.timer.len();
+        stats.stored_peers = self.active_stores.num_items();
+        stats.max_stored_peers = self.active_stores.max_items();
+
+        tx.send(stats).unwrap_or(())
+    }
+
+    fn handle_get_client_census(&self, tx: oneshot::Sender<HashMap<Option<Vec<u8>>, usize>>) {
+        let mut census = HashMap::new();
+
+        for node in self
+            .routing_table
+            .buckets()
+            .flat_map(|bucket| bucket.pingable_nodes())
+        {
+            *census
+                .entry(node.version().map(<[u8]>::to_vec))
+                .or_insert(0) += 1;
+        }
+
+        tx.send(census).unwrap_or(())
+    }
+
+    fn handle_sibling_nodes(&mut self, nodes: Vec<NodeHandle>) {
+        add_questionable_nodes(
+            &mut self.routing_table,
+            &nodes,
+            self.bootstrap.router_addresses(),
+            &mut self.read_only_nodes,
+        );
+    }
+
     fn handle_get_local_addr(&self, tx: oneshot::Sender<SocketAddr>) {
         tx.send(self.socket.local_addr()).unwrap_or(())
     }
 
+    fn handle_get_external_addr(&mut self, tx: oneshot::Sender<Option<SocketAddr>>) {
+        if self.ip_voter.remove_expired_votes() {
+            self.report_external_addr();
+        }
+
+        tx.send(self.ip_voter.external_addr()).unwrap_or(())
+    }
+
     async fn handle_check_lookup_timeout(&mut self, trans_id: TransactionID) {
         let lookup = if let Some(lookup) = self.lookups.get_mut(&trans_id.action_id()) {
             lookup
@@ -582,17 +1327,127 @@ impl DhtHandler {
             return;
         };
 
-        lookup
-            .recv_finished(self.announce_port, &mut self.routing_table, &self.socket)
+        let status = lookup
+            .recv_finished(
+                self.announce_port,
+                &mut self.routing_table,
+                &self.socket,
+                &mut self.timer,
+            )
             .await;
+
+        // Keep the lookup around until it receives the responses to its put requests.
+        if status == ActionStatus::Ongoing {
+            self.lookups.insert(trans_id.action_id(), lookup);
+        } else {
+            self.publish_lookup_finished(&lookup);
+            self.handle_announce_round_finished(trans_id.action_id());
+        }
+    }
+
+    // Stop the lookup as soon as its results are no longer awaited, rather than when its next
+    // response or timeout arrives.
+    fn handle_abandoned_lookup(&mut self, action_id: ActionID) {
+        if let Some(mut lookup) = self.lookups.remove(&action_id) {
+            lookup.abort(&mut self.timer);
+            self.publish_lookup_finished(&lookup);
+            self.handle_announce_round_finished(action_id);
+        }
+    }
+
+    fn publish_lookup_finished(&self, lookup: &TableLookup) {
+        self.events.emit(DhtEvent::LookupFinished {
+            ip_version: self.ip_version(),
+            target: lookup.target(),
+        });
+    }
+
+    fn publish_table_changes(&mut self) {
+        let changes = self.routing_table.take_changes();
+
+        if !changes.is_empty() {
+            self.share_nodes_with_sibling();
+        }
+
+        for change in changes {
+            self.events.emit(match change {
+                TableChange::Added(node) => DhtEvent::NodeAdded(node),
+                TableChange::Evicted(node) => DhtEvent::NodeEvicted(node),
+            });
+        }
+    }
+
+    fn share_nodes_with_sibling(&self) {
+        if let Some(sibling) = &self.sibling {
+            sibling.share_nodes(
+                self.routing_table
+                    .closest_nodes(self.routing_table.node_id())
+                    .map(|node| *node.handle())
+                    .collect(),
+            );
+        }
+    }
+
+    async fn ping_questionable_nodes(&mut self) {
+        let nodes = self.routing_table.take_nodes_to_ping();
+
+        if !nodes.is_empty() {
+            self.refresh
+                .ping_nodes(
+                    &mut self.routing_table,
+                    &self.socket,
+                    &mut self.timer,
+                    nodes,
+                )
+                .await
+        }
     }
 
     async fn handle_check_table_refresh(&mut self) {
+        self.routing_table.replace_dead_nodes();
+        // Also catches the nodes which went bad without the table changing.
+        self.share_nodes_with_sibling();
         self.refresh
             .continue_refresh(&mut self.routing_table, &self.socket, &mut self.timer)
             .await
     }
 
+    async fn handle_shutdown(
+        &mut self,
+        finish_lookups: bool,
+        tx: oneshot::Sender<(Box<dyn Any + Send>, RoutingTableSnapshot)>,
+    ) {
+        // Send out the final announces and puts and deliver the results found so far. The lookups
+        // waiting for the responses to them are kept until they get them or time out, the others
+        // are dropped, which closes their channels.
+        for (action_id, mut lookup) in mem::take(&mut self.lookups) {
+            let status = if finish_lookups {
+                lookup
+                    .recv_finished(
+                        self.announce_port,
+                        &mut self.routing_table,
+                        &self.socket,
+                        &mut self.timer,
+                    )
+                    .await
+            } else {
+                ActionStatus::Completed
+            };
+
+            if status == ActionStatus::Ongoing {
+                self.lookups.insert(action_id, lookup);
+            } else {
+                self.publish_lookup_finished(&lookup);
+            }
+        }
+
+        // No more lookups or announces from now on.
+        self.announces.clear();
+        self.command_rx.close();
+        self.shutdown_tx = Some(tx);
+        self.stopping = true;
+    }
+
     fn shutdown(&mut self) {
         self.running = false;
     }
@@ -610,24 +1465,30 @@ impl DhtHandler {
             },
         };
 
+        // Our routing table has only the nodes of our address family, the nodes of the other one
+        // come from the routing table of the sibling handler, if we run dual-stack.
+        let closest_nodes = |ip_version| {
+            if ip_version == self.socket.ip_version() {
+                self.routing_table
+                    .closest_nodes(target)
+                    .take(self.config.bucket_size)
+                    .map(|node| *node.handle())
+                    .collect()
+            } else if let Some(sibling) = &self.sibling {
+                sibling.closest_nodes(target, self.config.bucket_size)
+            } else {
+                vec![]
+            }
+        };
+
         let nodes_v4 = if matches!(want, Want::V4 | Want::Both) {
-            self.routing_table
-                .closest_nodes(target)
-                .filter(|node| node.addr().is_ipv4())
-                .take(8)
-                .map(|node| *node.handle())
-                .collect()
+            closest_nodes(IpVersion::V4)
         } else {
             vec![]
         };
 
         let nodes_v6 = if matches!(want, Want::V6 | Want::Both) {
-            self.routing_table
-                .closest_nodes(target)
-                .filter(|node| node.addr().is_ipv6())
-                .take(8)
-                .map(|node| *node.handle())
-                .collect()
+            closest_nodes(IpVersion::V6)
         } else {
             vec![]
         };
@@ -643,15 +1504,293 @@ fn add_nodes(
     node: &Node,
     nodes: &[NodeHandle],
     routers: &HashSet<SocketAddr>,
+    read_only_nodes: &mut ReadOnlyNodes,
 ) {
-    if !routers.contains(&node.addr()) {
+    if !routers.contains(&node.addr()) && !read_only_nodes.contains(&node.addr()) {
         table.add_node(node.clone());
     }
 
     // Add the payload nodes as questionable
+    add_questionable_nodes(table, nodes, routers, read_only_nodes);
+}
+
+fn add_questionable_nodes(
+    table: &mut RoutingTable,
+    nodes: &[NodeHandle],
+    routers: &HashSet<SocketAddr>,
+    read_only_nodes: &mut ReadOnlyNodes,
+) {
     for node in nodes {
-        if !routers.contains(&node.addr) {
+        if !routers.contains(&node.addr) && !read_only_nodes.contains(&node.addr) {
             table.add_node(Node::as_questionable(node.id, node.addr));
         }
     }
 }
+
+async fn recv_from_sibling(sibling: &mut Option<Sibling>) -> Vec<NodeHandle> {
+    match sibling {
+        Some(sibling) => sibling.recv().await,
+        None => future::pending().await,
+    }
+}
+
+struct PeriodicAnnounce {
+    info_hash: InfoHash,
+    options: AnnounceOptions,
+    tx: mpsc::UnboundedSender<LookupEvent>,
+    // The lookup of the ongoing round.
+    round: Option<ActionID>,
+}
+
+struct PendingPing {
+    addr: SocketAddr,
+    sent: Instant,
+    timeout: Timeout,
+    tx: oneshot::Sender<Result<PingResponse, PingError>>,
+}
+
+const MAX_READ_ONLY_NODES: usize = 1000;
+const READ_ONLY_NODE_EXPIRATION: Duration = Duration::from_secs(15 * 60);
+
+/// Bounded set of addresses of nodes that recently sent us read-only requests (BEP43).
+struct ReadOnlyNodes {
+    nodes: HashMap<SocketAddr, Instant>,
+}
+
+impl ReadOnlyNodes {
+    fn new() -> Self {
+        Self {
+            nodes: HashMap::new(),
+        }
+    }
+
+    fn insert(&mut self, addr: SocketAddr) {
+        let now = Instant::now();
+
+        if self.nodes.len() >= MAX_READ_ONLY_NODES && !self.nodes.contains_key(&addr) {
+            self.nodes
+                .retain(|_, time| now - *time < READ_ONLY_NODE_EXPIRATION);
+        }
+
+        // Still full, forget the least recently seen one.
+        if self.nodes.len() >= MAX_READ_ONLY_NODES && !self.nodes.contains_key(&addr) {
+            let oldest = self
+                .nodes
+                .iter()
+                .min_by_key(|(_, time)| **time)
+                .map(|(addr, _)| *addr);
+
+            if let Some(oldest) = oldest {
+                self.nodes.remove(&oldest);
+            }
+        }
+
+        self.nodes.insert(addr, now);
+    }
+
+    fn contains(&mut self, addr: &SocketAddr) -> bool {
+        match self.nodes.get(addr) {
+            Some(time) if time.elapsed() < READ_ONLY_NODE_EXPIRATION => true,
+            Some(_) => {
+                self.nodes.remove(addr);
+                false
+            }
+            None => false,
+        }
+    }
+}
+
+// Maximum number of info hashes in a sample_infohashes response. Keeps the response, together
+// with the closest nodes, well within a single UDP packet.
+const MAX_SAMPLES: usize = 20;
+// How long the requesters should wait before asking us for another sample (BEP51 caps this at six
+// hours).
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+const MAX_SAMPLE_REQUESTERS: usize = 1000;
+
+/// Bounded set of addresses of nodes we recently sent info hash samples to, used to rate limit
+/// sample_infohashes requests (BEP51).
+struct SampleRequesters {
+    requesters: HashMap<IpAddr, Instant>,
+}
+
+impl SampleRequesters {
+    fn new() -> Self {
+        Self {
+            requesters: HashMap::new(),
+        }
+    }
+
+    /// Returns `None` if the given requester may get a sample now (and records it), or the time
+    /// it still has to wait otherwise.
+    fn check(&mut self, ip: IpAddr) -> Option<Duration> {
+        self.check_at(ip, Instant::now())
+    }
+
+    fn check_at(&mut self, ip: IpAddr, now: Instant) -> Option<Duration> {
+        if let Some(time) = self.requesters.get(&ip) {
+            let elapsed = now.saturating_duration_since(*time);
+
+            if elapsed < SAMPLE_INTERVAL {
+                return Some(SAMPLE_INTERVAL - elapsed);
+            }
+        }
+
+        if self.requesters.len() >= MAX_SAMPLE_REQUESTERS && !self.requesters.contains_key(&ip) {
+            self.requesters
+                .retain(|_, time| now.saturating_duration_since(*time) < SAMPLE_INTERVAL);
+        }
+
+        // Still full, forget the least recent one.
+        if self.requesters.len() >= MAX_SAMPLE_REQUESTERS && !self.requesters.contains_key(&ip) {
+            let oldest = self
+                .requesters
+                .iter()
+                .min_by_key(|(_, time)| **time)
+                .map(|(ip, _)| *ip);
+
+            if let Some(oldest) = oldest {
+                self.requesters.remove(&oldest);
+            }
+        }
+
+        self.requesters.insert(ip, now);
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        id::NodeId,
+        routing::node::{Node, NodeStatus},
+        test,
+    };
+    use std::net::Ipv4Addr;
+    use tokio::net::UdpSocket;
+
+    #[test]
+    fn positive_sample_requesters_rate_limit() {
+        let mut requesters = SampleRequesters::new();
+        let ip_a = IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4));
+        let ip_b = IpAddr::V4(Ipv4Addr::new(5, 6, 7, 8));
+        let time = Instant::now();
+
+        assert_eq!(requesters.check_at(ip_a, time), None);
+        assert_eq!(requesters.check_at(ip_b, time), None);
+
+        let later = time + Duration::from_secs(60);
+        assert_eq!(
+            requesters.check_at(ip_a, later),
+            Some(SAMPLE_INTERVAL - Duration::from_secs(60))
+        );
+
+        assert_eq!(requesters.check_at(ip_a, time + SAMPLE_INTERVAL), None);
+    }
+
+    #[tokio::test]
+    async fn positive_pinged_questionable_node_is_promoted() {
+        let (mut handler, peer, questionable, candidate) = handler_with_full_bucket().await;
+
+        // The first ping times out, so the node is pinged again.
+        handler.ping_questionable_nodes().await;
+        let trans_id = recv_ping(&peer).await;
+        handler
+            .handle_timeout(ScheduledTaskCheck::RefreshTimeout(trans_id))
+            .await;
+        assert_eq!(
+            handler
+                .routing_table
+                .find_node(&questionable)
+                .unwrap()
+                .status(),
+            NodeStatus::Questionable
+        );
+
+        handler.ping_questionable_nodes().await;
+        let trans_id = recv_ping(&peer).await;
+        let response = Message {
+            transaction_id: trans_id.as_ref().to_vec(),
+            read_only: false,
+            version: None,
+            body: MessageBody::Response(Response::new(questionable.id)),
+        };
+        handler
+            .handle_incoming(&response.encode(), questionable.addr)
+            .await
+            .unwrap();
+
+        // The response makes the node good, the candidate keeps waiting.
+        let node = handler.routing_table.find_node(&questionable).unwrap();
+        assert_eq!(node.status(), NodeStatus::Good);
+        assert_eq!(node.failed_requests(), 0);
+        assert!(node.rtt().is_some());
+        assert!(handler.routing_table.find_node(&candidate).is_none());
+        assert_eq!(handler.socket.stats().responses_received.ping, 1);
+    }
+
+    #[tokio::test]
+    async fn positive_dead_questionable_node_is_replaced() {
+        let (mut handler, peer, questionable, candidate) = handler_with_full_bucket().await;
+
+        for _ in 0..2 {
+            handler.ping_questionable_nodes().await;
+            let trans_id = recv_ping(&peer).await;
+            handler
+                .handle_timeout(ScheduledTaskCheck::RefreshTimeout(trans_id))
+                .await;
+        }
+
+        // The candidate takes the place of the node right after its second ping timed out.
+        assert!(handler.routing_table.find_node(&questionable).is_none());
+        assert!(handler.routing_table.find_node(&candidate).is_some());
+        assert_eq!(handler.socket.stats().timeouts, 2);
+    }
+
+    // A handler whose routing table has a full bucket, which can't be split, of a single
+    // questionable node and a candidate waiting for it to turn out dead. The node is played by
+    // the returned socket.
+    async fn handler_with_full_bucket() -> (DhtHandler, UdpSocket, NodeHandle, NodeHandle) {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let peer = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let addrs = test::dummy_block_socket_addrs(2);
+
+        let mut table = RoutingTable::new(NodeId::from([0; 20]), 1);
+        table.set_enforce_node_id(false);
+
+        let questionable = NodeHandle::new(NodeId::from([0x80; 20]), peer.local_addr().unwrap());
+        let candidate = NodeHandle::new(NodeId::from([0xc0; 20]), addrs[0]);
+        table.add_node(Node::as_questionable(questionable.id, questionable.addr));
+        // Splits the bucket, the far one can't be split anymore.
+        table.add_node(Node::as_good(NodeId::from([0x01; 20]), addrs[1]));
+        table.add_node(Node::as_good(candidate.id, candidate.addr));
+
+        let (_, command_rx) = mpsc::unbounded_channel();
+        let handler = DhtHandler::new(
+            table,
+            Socket::new(socket, None).unwrap(),
+            DhtConfig::new(),
+            false,
+            HashSet::new(),
+            HashSet::new(),
+            None,
+            command_rx,
+        );
+
+        (handler, peer, questionable, candidate)
+    }
+
+    async fn recv_ping(peer: &UdpSocket) -> TransactionID {
+        let mut buffer = vec![0; 1500];
+        let (size, _) = peer.recv_from(&mut buffer).await.unwrap();
+        let message = Message::decode(&buffer[..size]).unwrap();
+        assert!(matches!(
+            message.body,
+            MessageBody::Request(Request::Ping(_))
+        ));
+
+        TransactionID::from_bytes(&message.transaction_id).unwrap()
+    }
+}
@@ -1,36 +1,40 @@
 use super::{
     socket::Socket,
+    stats::Method,
     timer::{Timeout, Timer},
-    ActionStatus, IpVersion, ScheduledTaskCheck,
+    ActionStatus, IpVersion, LookupEvent, LookupKind, LookupSummary, ScheduledTaskCheck,
+    StartLookup,
 };
-use crate::id::{Id, InfoHash, NODE_ID_LEN};
+use crate::announce::AnnounceMode;
+use crate::bloom::{BloomFilter, ScrapeResult};
+use crate::config::DhtConfig;
+use crate::id::{Id, InfoHash, NodeId};
+use crate::item::{self, Item, MutableItem, PutError};
 use crate::message::{
-    AnnouncePeerRequest, GetPeersRequest, Message, MessageBody, Request, Response,
+    error_code, AnnouncePeerRequest, Error, FindNodeRequest, GetPeersRequest, GetRequest, Message,
+    MessageBody, PutRequest, Request, Response, SampleInfohashesRequest, Want,
 };
-use crate::routing::bucket;
 use crate::routing::node::{Node, NodeHandle, NodeStatus};
 use crate::routing::table::RoutingTable;
+use crate::sample::InfoHashSample;
 use crate::transaction::{MIDGenerator, TransactionID};
+use serde_bencode::value::Value;
 use std::{
     collections::{HashMap, HashSet},
-    net::{Ipv4Addr, SocketAddr},
-    time::Duration,
+    future::Future,
+    mem,
+    net::SocketAddr,
+    time::{Duration, Instant},
 };
-use tokio::sync::mpsc;
-
-const LOOKUP_TIMEOUT: Duration = Duration::from_millis(1500);
-const ENDGAME_TIMEOUT: Duration = Duration::from_millis(1500);
+use tokio::{select, sync::oneshot};
 
 // Currently using the aggressive variant of the standard lookup procedure.
 // https://people.kth.se/~rauljc/p2p11/jimenez2011subsecond.pdf
+// The number of nodes picked in each round (alpha and beta) and the timeouts are in `DhtConfig`.
 
 // TODO: Handle case where a request round fails, should we fail the whole lookup (clear acvite lookups?)
 // TODO: Clean up the code in this module.
 
-const INITIAL_PICK_NUM: usize = 4; // Alpha
-const ITERATIVE_PICK_NUM: usize = 3; // Beta
-const ANNOUNCE_PICK_NUM: usize = 8; // # Announces
-
 type Distance = Id;
 type DistanceToBeat = Id;
 
@@ -38,70 +42,105 @@ pub(crate) struct TableLookup {
     ip_version: IpVersion,
     target_id: InfoHash,
     in_endgame: bool,
+    // The timeout of the endgame round, shared by all its requests.
+    endgame_timer: Option<Timeout>,
     // If we have received any values in the lookup.
     recv_values: bool,
     id_generator: MIDGenerator,
-    will_announce: bool,
+    kind: LookupKind,
+    config: DhtConfig,
+    // Whether to mark our requests as read-only (BEP43).
+    read_only: bool,
+    // Which address families of nodes to ask for (BEP32).
+    want: Option<Want>,
+    // Set once the search is over and we announced/put to the closest nodes.
+    finished: bool,
+    // Set once the summary of a get_peers lookup has been sent.
+    summary_sent: bool,
+    started: Instant,
     // DistanceToBeat is the distance that the responses of the current lookup needs to beat,
     // interestingly enough (and super important), this distance may not be eqaul to the
-    // requested node's distance
-    active_lookups: HashMap<TransactionID, (DistanceToBeat, Timeout)>,
+    // requested node's distance. The instant is when the request was sent, to measure the
+    // round-trip time of the node.
+    active_lookups: HashMap<TransactionID, (NodeHandle, DistanceToBeat, Timeout, Instant)>,
     announce_tokens: HashMap<NodeHandle, Vec<u8>>,
     requested_nodes: HashSet<NodeHandle>,
+    responded_nodes: HashSet<NodeHandle>,
     // Storing whether or not it has ever been pinged so that we
     // can perform the brute force lookup if the lookup failed
     all_sorted_nodes: Vec<(Distance, NodeHandle, bool)>,
-    // Send the found peers through this channel.
-    tx: mpsc::UnboundedSender<SocketAddr>,
+    // Dropped along with the lookup, to stop the future watching whether it was abandoned.
+    _dropped_tx: Option<oneshot::Sender<()>>,
+    // Announce or put requests we are still waiting the response for.
+    store_requests: HashMap<TransactionID, (NodeHandle, Timeout, Instant)>,
+    // Bloom filters of the seeds and the peers received from each node (BEP33).
+    scrapes: HashMap<NodeHandle, (BloomFilter, BloomFilter)>,
 }
 
 // Gather nodes
 
 impl TableLookup {
+    #[allow(clippy::too_many_arguments)]
     pub async fn new(
-        target_id: InfoHash,
-        will_announce: bool,
-        tx: mpsc::UnboundedSender<SocketAddr>,
+        lookup: StartLookup,
+        config: DhtConfig,
+        read_only: bool,
+        want: Option<Want>,
         id_generator: MIDGenerator,
         table: &mut RoutingTable,
         socket: &Socket,
         timer: &mut Timer<ScheduledTaskCheck>,
     ) -> TableLookup {
+        let StartLookup {
+            target: target_id,
+            kind,
+        } = lookup;
+
         // Pick a buckets worth of nodes and put them into the all_sorted_nodes list
-        let mut all_sorted_nodes = Vec::with_capacity(bucket::MAX_BUCKET_SIZE);
+        let mut all_sorted_nodes = Vec::with_capacity(table.bucket_size());
         for node in table
             .closest_nodes(target_id)
             .filter(|n| n.status() == NodeStatus::Good)
-            .take(bucket::MAX_BUCKET_SIZE)
+            .take(table.bucket_size())
         {
             insert_sorted_node(&mut all_sorted_nodes, target_id, *node.handle(), false);
         }
 
         // Call pick_initial_nodes with the all_sorted_nodes list as an iterator
-        let initial_pick_nodes = pick_initial_nodes(all_sorted_nodes.iter_mut());
-        let initial_pick_nodes_filtered =
-            initial_pick_nodes
-                .iter()
-                .filter(|(_, good)| *good)
-                .map(|(node, _)| {
-                    let distance_to_beat = node.id ^ target_id;
+        let initial_pick_nodes = pick_initial_nodes(
+            all_sorted_nodes.iter_mut(),
+            config.lookup_initial_pick,
+            |node| node_rtt(table, node),
+        );
+        let initial_pick_nodes_filtered = initial_pick_nodes.iter().map(|node| {
+            let distance_to_beat = node.id ^ target_id;
 
-                    (node, distance_to_beat)
-                });
+            (node, distance_to_beat)
+        });
 
         // Construct the lookup table structure
         let mut table_lookup = TableLookup {
             ip_version: socket.ip_version(),
             target_id,
             in_endgame: false,
+            endgame_timer: None,
             recv_values: false,
             id_generator,
-            will_announce,
+            kind,
+            config,
+            read_only,
+            want,
+            finished: false,
+            summary_sent: false,
+            started: Instant::now(),
             all_sorted_nodes,
             announce_tokens: HashMap::new(),
             requested_nodes: HashSet::new(),
-            active_lookups: HashMap::with_capacity(INITIAL_PICK_NUM),
-            tx,
+            responded_nodes: HashSet::new(),
+            active_lookups: HashMap::with_capacity(config.lookup_initial_pick),
+            store_requests: HashMap::new(),
+            scrapes: HashMap::new(),
+            _dropped_tx: None,
         };
 
         // Call start_request_round with the list of initial_nodes (return even if the search completed...for now :D)
@@ -112,10 +151,44 @@ impl TableLookup {
         table_lookup
     }
 
+    pub fn target(&self) -> InfoHash {
+        self.target_id
+    }
+
+    /// Resolves to `true` once nobody is waiting for the results of the lookup anymore, or to
+    /// `false` once the lookup is dropped. The future doesn't borrow the lookup, so it can be
+    /// registered once and polled alongside it.
+    pub fn abandoned(&mut self) -> impl Future<Output = bool> + Send + 'static {
+        let kind = self.kind.clone();
+        let (dropped_tx, dropped_rx) = oneshot::channel();
+        self._dropped_tx = Some(dropped_tx);
+
+        async move {
+            select! {
+                _ = kind.abandoned() => true,
+                _ = dropped_rx => false,
+            }
+        }
+    }
+
     pub fn completed(&self) -> bool {
         self.active_lookups.is_empty()
     }
 
+    /// The method of our request with the given transaction id, if the lookup is still waiting
+    /// for the response to it.
+    pub fn request_method(&self, trans_id: &TransactionID) -> Option<Method> {
+        if self.store_requests.contains_key(trans_id) {
+            return Some(match self.kind {
+                LookupKind::PutItem { .. } => Method::Put,
+                _ => Method::AnnouncePeer,
+            });
+        }
+
+        let (node, _, _, _) = self.active_lookups.get(trans_id)?;
+        Some(lookup_method(&self.kind, node))
+    }
+
     pub async fn recv_response(
         &mut self,
         node: Node,
@@ -125,33 +198,73 @@ impl TableLookup {
         socket: &Socket,
         timer: &mut Timer<ScheduledTaskCheck>,
     ) -> ActionStatus {
-        // Process the message transaction id
-        let (dist_to_beat, timeout) = if let Some(lookup) = self.active_lookups.remove(trans_id) {
-            lookup
-        } else {
-            log::debug!(
-                "{}: Received expired/unsolicited node response for an active table lookup",
-                self.ip_version
-            );
+        if self.kind.is_abandoned() {
+            return self.abort(timer);
+        }
+
+        if let Some((node, timeout, sent)) = self.store_requests.remove(trans_id) {
+            timer.cancel(timeout);
+            record_rtt(table, &node, sent);
+            self.send_put_result(Ok(()));
+            self.send_event(LookupEvent::AnnounceAccepted(node));
+
             return self.current_lookup_status();
-        };
+        }
 
-        // Cancel the timeout (if this is not an endgame response)
-        if !self.in_endgame {
+        // Process the message transaction id
+        let (_, dist_to_beat, timeout, sent) =
+            if let Some(lookup) = self.active_lookups.remove(trans_id) {
+                lookup
+            } else {
+                log::debug!(
+                    "{}: Received expired/unsolicited node response for an active table lookup",
+                    self.ip_version
+                );
+                return self.current_lookup_status();
+            };
+
+        // Cancel the timeout, unless it is the one shared by the whole endgame round
+        if Some(timeout) != self.endgame_timer {
             timer.cancel(timeout);
         }
 
+        record_rtt(table, node.handle(), sent);
+        self.responded_nodes.insert(*node.handle());
+        self.send_event(LookupEvent::NodeResponded(*node.handle()));
+
         if let Some(token) = msg.token {
             // Add the announce token to our list of tokens
             self.announce_tokens.insert(*node.handle(), token);
         }
 
+        if matches!(self.kind, LookupKind::Scrape(_))
+            && (msg.bf_seeds.is_some() || msg.bf_peers.is_some())
+        {
+            self.scrapes.insert(
+                *node.handle(),
+                (
+                    msg.bf_seeds.unwrap_or_else(BloomFilter::new),
+                    msg.bf_peers.unwrap_or_else(BloomFilter::new),
+                ),
+            );
+        }
+
         let nodes = match socket.ip_version() {
             IpVersion::V4 => msg.nodes_v4,
             IpVersion::V6 => msg.nodes_v6,
         };
 
-        let values = msg.values;
+        self.recv_values(
+            *node.handle(),
+            msg.values,
+            ItemResponse {
+                v: msg.v,
+                k: msg.k,
+                sig: msg.sig,
+                seq: msg.seq,
+            },
+        );
+        self.recv_samples(node.addr(), msg.interval, msg.num, msg.samples);
 
         // Check if we beat the distance, get the next distance to beat
         let (iterate_nodes, next_dist_to_beat) = if !nodes.is_empty() {
@@ -179,11 +292,13 @@ impl TableLookup {
                         .filter(|node| !requested_nodes.contains(node))
                         .copied(),
                     self.target_id,
+                    self.config.lookup_iterative_pick,
+                    |node| node_rtt(table, node),
                 );
 
                 // Push nodes into the all nodes list
                 for node in nodes {
-                    let will_ping = iterate_nodes.iter().any(|(n, _)| n == &node);
+                    let will_ping = iterate_nodes.contains(&node);
 
                     insert_sorted_node(&mut self.all_sorted_nodes, self.target_id, node, will_ping);
                 }
@@ -207,10 +322,7 @@ impl TableLookup {
         if !self.in_endgame {
             // If the node gave us a closer id than its own to the target id, continue the search
             if let Some(nodes) = iterate_nodes {
-                let filtered_nodes = nodes
-                    .iter()
-                    .filter(|(_, good)| *good)
-                    .map(|(n, _)| (n, next_dist_to_beat));
+                let filtered_nodes = nodes.iter().map(|n| (n, next_dist_to_beat));
                 self.start_request_round(filtered_nodes, table, socket, timer)
                     .await;
             }
@@ -221,11 +333,51 @@ impl TableLookup {
             }
         }
 
-        for value in values {
-            self.tx.send(value).unwrap_or(())
+        self.current_lookup_status()
+    }
+
+    pub async fn recv_error(
+        &mut self,
+        trans_id: &TransactionID,
+        error: Error,
+        table: &mut RoutingTable,
+        socket: &Socket,
+        timer: &mut Timer<ScheduledTaskCheck>,
+    ) -> ActionStatus {
+        if self.kind.is_abandoned() {
+            return self.abort(timer);
         }
 
-        self.current_lookup_status()
+        if let Some((node, timeout, _)) = self.store_requests.remove(trans_id) {
+            timer.cancel(timeout);
+
+            match &self.kind {
+                LookupKind::PutItem { .. } => self.send_put_result(Err(match error.code {
+                    error_code::CAS_MISMATCH => PutError::CasMismatch,
+                    error_code::SEQ_TOO_LOW => PutError::SeqTooLow,
+                    _ => PutError::Rejected {
+                        code: error.code,
+                        message: error.message,
+                    },
+                })),
+                _ => self.send_event(LookupEvent::AnnounceRejected {
+                    node,
+                    code: error.code,
+                    message: error.message,
+                }),
+            }
+
+            return self.current_lookup_status();
+        }
+
+        // The node won't send us anything else, so treat it the same as if it timed out.
+        if let Some((_, _, timeout, _)) = self.active_lookups.get(trans_id) {
+            if Some(*timeout) != self.endgame_timer {
+                timer.cancel(*timeout);
+            }
+        }
+
+        self.give_up_request(trans_id, table, socket, timer).await
     }
 
     pub async fn recv_timeout(
@@ -235,7 +387,41 @@ impl TableLookup {
         socket: &Socket,
         timer: &mut Timer<ScheduledTaskCheck>,
     ) -> ActionStatus {
-        if self.active_lookups.remove(trans_id).is_none() {
+        if self.kind.is_abandoned() {
+            return self.abort(timer);
+        }
+
+        let node = match (
+            self.store_requests.get(trans_id),
+            self.active_lookups.get(trans_id),
+        ) {
+            (Some((node, _, _)), _) | (None, Some((node, _, _, _))) => Some(*node),
+            (None, None) => None,
+        };
+
+        if let Some(node) = node {
+            table.request_timed_out(&node);
+        }
+
+        self.give_up_request(trans_id, table, socket, timer).await
+    }
+
+    // Stop waiting for the response to the request and report its node as timed out.
+    async fn give_up_request(
+        &mut self,
+        trans_id: &TransactionID,
+        table: &mut RoutingTable,
+        socket: &Socket,
+        timer: &mut Timer<ScheduledTaskCheck>,
+    ) -> ActionStatus {
+        if let Some((node, _, _)) = self.store_requests.remove(trans_id) {
+            self.send_event(LookupEvent::NodeTimedOut(node));
+            return self.current_lookup_status();
+        }
+
+        if let Some((node, _, _, _)) = self.active_lookups.remove(trans_id) {
+            self.send_event(LookupEvent::NodeTimedOut(node));
+        } else {
             log::warn!(
                 "{}: Received expired/unsolicited node timeout for an active table lookup",
                 self.ip_version
@@ -258,64 +444,338 @@ impl TableLookup {
         port: Option<u16>,
         table: &mut RoutingTable,
         socket: &Socket,
+        timer: &mut Timer<ScheduledTaskCheck>,
+    ) -> ActionStatus {
+        if self.kind.is_abandoned() {
+            return self.abort(timer);
+        }
+
+        if self.finished {
+            return self.current_lookup_status();
+        }
+
+        self.finished = true;
+
+        if let Some(timeout) = self.endgame_timer.take() {
+            timer.cancel(timeout);
+        }
+
+        // The nodes which haven't responded by now, most likely the endgame ones, are out of time.
+        socket.stats().timeouts += self.active_lookups.len() as u64;
+        self.time_out_active_lookups(timer);
+        self.in_endgame = false;
+
+        match &self.kind {
+            LookupKind::FindNode(tx) => {
+                for node in self.closest_responded_nodes() {
+                    tx.send(node).unwrap_or(())
+                }
+            }
+            // Announce if we were told to
+            LookupKind::GetPeers { announce, .. } if *announce != AnnounceMode::Disabled => {
+                let seed = *announce == AnnounceMode::Seed;
+                self.send_announces(port, seed, table, socket, timer).await
+            }
+            LookupKind::PutItem { item, cas, .. } => {
+                let (item, cas) = (item.clone(), *cas);
+                self.send_puts(&item, cas, table, socket, timer).await
+            }
+            LookupKind::Scrape(tx) => {
+                if let Some(result) = self.merge_closest_scrapes() {
+                    tx.send(result).unwrap_or(())
+                }
+            }
+            LookupKind::GetPeers { .. }
+            | LookupKind::GetImmutable(_)
+            | LookupKind::GetMutable { .. }
+            | LookupKind::SampleInfohashes { .. } => (),
+        }
+
+        self.current_lookup_status()
+    }
+
+    async fn send_announces(
+        &mut self,
+        port: Option<u16>,
+        seed: bool,
+        table: &mut RoutingTable,
+        socket: &Socket,
+        timer: &mut Timer<ScheduledTaskCheck>,
     ) {
-        // Announce if we were told to
-        if self.will_announce {
-            // Partial borrow so the filter function doesnt capture all of self
-            let announce_tokens = &self.announce_tokens;
+        for (node, token) in self.closest_nodes_with_tokens() {
+            let trans_id = self.id_generator.generate();
 
-            for (_, node, _) in self
-                .all_sorted_nodes
-                .iter()
-                .filter(|(_, node, _)| announce_tokens.contains_key(node))
-                .take(ANNOUNCE_PICK_NUM)
-            {
-                let trans_id = self.id_generator.generate();
-                let token = announce_tokens.get(node).unwrap();
+            let announce_peer_req = AnnouncePeerRequest {
+                id: table.node_id(),
+                info_hash: self.target_id,
+                token,
+                port,
+                seed,
+            };
+            let announce_peer_msg = Message {
+                transaction_id: trans_id.as_ref().to_vec(),
+                read_only: self.read_only,
+                version: None,
+                body: MessageBody::Request(Request::AnnouncePeer(announce_peer_req)),
+            };
 
-                let announce_peer_req = AnnouncePeerRequest {
-                    id: table.node_id(),
-                    info_hash: self.target_id,
-                    token: token.clone(),
-                    port,
-                };
-                let announce_peer_msg = Message {
-                    transaction_id: trans_id.as_ref().to_vec(),
-                    body: MessageBody::Request(Request::AnnouncePeer(announce_peer_req)),
-                };
-                let announce_peer_msg = announce_peer_msg.encode();
+            if let Err(error) = socket.send(announce_peer_msg, node.addr).await {
+                log::error!(
+                    "{}: TableLookup announce request failed to send: {}",
+                    self.ip_version,
+                    error
+                );
+                continue;
+            }
 
-                match socket.send(&announce_peer_msg, node.addr).await {
-                    Ok(()) => {
-                        // We requested from the node, marke it down if the node is in our routing table
-                        if let Some(n) = table.find_node_mut(node) {
-                            n.local_request()
-                        }
+            let timeout = timer.schedule_in(
+                self.request_timeout(table, &node),
+                ScheduledTaskCheck::LookupTimeout(trans_id),
+            );
+            self.store_requests
+                .insert(trans_id, (node, timeout, Instant::now()));
+
+            // We requested from the node, marke it down if the node is in our routing table
+            if let Some(n) = table.find_node_mut(&node) {
+                n.local_request()
+            }
+        }
+    }
+
+    async fn send_puts(
+        &mut self,
+        item: &Item,
+        cas: Option<i64>,
+        table: &mut RoutingTable,
+        socket: &Socket,
+        timer: &mut Timer<ScheduledTaskCheck>,
+    ) {
+        for (node, token) in self.closest_nodes_with_tokens() {
+            let trans_id = self.id_generator.generate();
+
+            let put_msg = Message {
+                transaction_id: trans_id.as_ref().to_vec(),
+                read_only: self.read_only,
+                version: None,
+                body: MessageBody::Request(Request::Put(put_request(
+                    table.node_id(),
+                    token,
+                    item,
+                    cas,
+                ))),
+            };
+
+            if let Err(error) = socket.send(put_msg, node.addr).await {
+                log::error!(
+                    "{}: TableLookup put request failed to send: {}",
+                    self.ip_version,
+                    error
+                );
+                continue;
+            }
+
+            let timeout = timer.schedule_in(
+                self.request_timeout(table, &node),
+                ScheduledTaskCheck::LookupTimeout(trans_id),
+            );
+            self.store_requests
+                .insert(trans_id, (node, timeout, Instant::now()));
+
+            if let Some(n) = table.find_node_mut(&node) {
+                n.local_request()
+            }
+        }
+    }
+
+    // The closest nodes that gave us a token, to announce/put to.
+    fn closest_nodes_with_tokens(&self) -> Vec<(NodeHandle, Vec<u8>)> {
+        self.all_sorted_nodes
+            .iter()
+            .filter_map(|(_, node, _)| {
+                self.announce_tokens
+                    .get(node)
+                    .map(|token| (*node, token.clone()))
+            })
+            .take(self.config.lookup_store_pick)
+            .collect()
+    }
+
+    // A find_node lookup yields a full bucket of nodes, the others only as many as they store to.
+    fn closest_responded_nodes(&self) -> Vec<NodeHandle> {
+        let count = match self.kind {
+            LookupKind::FindNode(_) => self.config.bucket_size,
+            _ => self.config.lookup_store_pick,
+        };
+
+        // The sorted list may contain duplicates.
+        let mut seen = HashSet::new();
+
+        self.all_sorted_nodes
+            .iter()
+            .map(|(_, node, _)| *node)
+            .filter(|node| self.responded_nodes.contains(node) && seen.insert(*node))
+            .take(count)
+            .collect()
+    }
+
+    // Merge the bloom filters from the closest nodes which sent them. The farther nodes are likely
+    // to have only a stale subset of the peers, so they are ignored.
+    fn merge_closest_scrapes(&self) -> Option<ScrapeResult> {
+        let mut filters = self
+            .all_sorted_nodes
+            .iter()
+            .filter_map(|(_, node, _)| self.scrapes.get(node))
+            .take(self.config.lookup_store_pick);
+
+        let (mut seeds, mut peers) = filters.next()?.clone();
+
+        for (other_seeds, other_peers) in filters {
+            seeds.merge(other_seeds);
+            peers.merge(other_peers);
+        }
+
+        Some(ScrapeResult {
+            seeds: seeds.estimate().round() as usize,
+            leechers: peers.estimate().round() as usize,
+        })
+    }
+
+    fn recv_values(&mut self, source: NodeHandle, values: Vec<SocketAddr>, msg: ItemResponse) {
+        match &self.kind {
+            LookupKind::GetPeers { tx, .. } => {
+                for peer in values {
+                    tx.send(LookupEvent::PeerFound { peer, source })
+                        .unwrap_or(())
+                }
+            }
+            LookupKind::GetImmutable(tx) => {
+                if let Some(v) = msg.v {
+                    // Make sure the node isn't lying to us.
+                    if item::immutable_target(&v) == self.target_id {
+                        self.recv_values = true;
+                        tx.send(v).unwrap_or(())
+                    } else {
+                        log::debug!(
+                            "{}: Received immutable item not matching its target",
+                            self.ip_version
+                        );
                     }
-                    Err(error) => {
-                        log::error!(
-                            "{}: TableLookup announce request failed to send: {}",
-                            self.ip_version,
-                            error
-                        )
+                }
+            }
+            LookupKind::GetMutable { salt, tx } => {
+                if let (Some(v), Some(k), Some(sig), Some(seq)) = (msg.v, msg.k, msg.sig, msg.seq) {
+                    // Make sure the node isn't lying to us.
+                    match MutableItem::verify(&k, &sig, salt.clone(), seq, v) {
+                        Some(item) if item.target() == self.target_id => {
+                            self.recv_values = true;
+                            tx.send(item).unwrap_or(())
+                        }
+                        _ => log::debug!(
+                            "{}: Received mutable item with invalid signature or target",
+                            self.ip_version
+                        ),
                     }
                 }
             }
+            LookupKind::FindNode(_)
+            | LookupKind::PutItem { .. }
+            | LookupKind::Scrape(_)
+            | LookupKind::SampleInfohashes { .. } => (),
+        }
+    }
+
+    fn recv_samples(
+        &self,
+        addr: SocketAddr,
+        interval: Option<i64>,
+        num: Option<i64>,
+        samples: Option<Vec<InfoHash>>,
+    ) {
+        if let LookupKind::SampleInfohashes { tx, .. } = &self.kind {
+            // Responses to find_node (sent to the nodes we skip) carry no interval.
+            if let Some(interval) = interval {
+                tx.send(InfoHashSample {
+                    addr,
+                    interval: Duration::from_secs(interval.max(0) as u64),
+                    num: num.unwrap_or(0).max(0) as usize,
+                    samples: samples.unwrap_or_default(),
+                })
+                .unwrap_or(())
+            }
+        }
+    }
+
+    fn send_put_result(&self, result: Result<(), PutError>) {
+        if let LookupKind::PutItem { tx, .. } = &self.kind {
+            tx.send(result).unwrap_or(())
+        }
+    }
+
+    // Stop the lookup because nobody is waiting for its results anymore. Its pending timeouts are
+    // cancelled and the responses still to come are going to be treated as unsolicited.
+    pub fn abort(&mut self, timer: &mut Timer<ScheduledTaskCheck>) -> ActionStatus {
+        log::debug!(
+            "{}: Aborting abandoned table lookup for {:?}",
+            self.ip_version,
+            self.target_id
+        );
+
+        if let Some(timeout) = self.endgame_timer.take() {
+            timer.cancel(timeout);
+        }
+
+        // The endgame requests share its timeout, cancelling it again is harmless.
+        for (_, (_, _, timeout, _)) in self.active_lookups.drain() {
+            timer.cancel(timeout);
+        }
+
+        for (_, (_, timeout, _)) in self.store_requests.drain() {
+            timer.cancel(timeout);
         }
 
-        // This may not be cleared since we didnt set a timeout for each node, any nodes that didnt respond would still be in here.
-        self.active_lookups.clear();
         self.in_endgame = false;
+        self.finished = true;
+        // Nobody to send it to.
+        self.summary_sent = true;
+
+        ActionStatus::Completed
     }
 
-    fn current_lookup_status(&self) -> ActionStatus {
-        if self.in_endgame || !self.active_lookups.is_empty() {
-            ActionStatus::Ongoing
-        } else {
-            ActionStatus::Completed
+    // How long to wait for the node to respond: adapted to its round-trip time if we know it.
+    fn request_timeout(&self, table: &RoutingTable, node: &NodeHandle) -> Duration {
+        match table.find_node(node).and_then(Node::rtt_timeout) {
+            Some(timeout) => timeout
+                .max(self.config.min_lookup_timeout)
+                .min(self.config.lookup_timeout),
+            None => self.config.lookup_timeout,
         }
     }
 
+    fn send_event(&self, event: LookupEvent) {
+        if let LookupKind::GetPeers { tx, .. } = &self.kind {
+            tx.send(event).unwrap_or(())
+        }
+    }
+
+    fn current_lookup_status(&mut self) -> ActionStatus {
+        if self.in_endgame || !self.active_lookups.is_empty() || !self.store_requests.is_empty() {
+            return ActionStatus::Ongoing;
+        }
+
+        // Nothing else is going to be sent for this lookup, wrap it up.
+        if self.finished && !self.summary_sent {
+            self.summary_sent = true;
+            self.send_event(LookupEvent::Finished(LookupSummary {
+                ip_version: self.ip_version,
+                closest_nodes: self.closest_responded_nodes(),
+                queried: self.requested_nodes.len(),
+                duration: self.started.elapsed(),
+            }));
+        }
+
+        ActionStatus::Completed
+    }
+
     async fn start_request_round<'a, I>(
         &mut self,
         nodes: I,
@@ -332,25 +792,30 @@ impl TableLookup {
             let trans_id = self.id_generator.generate();
 
             // Try to start a timeout for the node
-            let timeout =
-                timer.schedule_in(LOOKUP_TIMEOUT, ScheduledTaskCheck::LookupTimeout(trans_id));
+            let timeout = timer.schedule_in(
+                self.request_timeout(table, node),
+                ScheduledTaskCheck::LookupTimeout(trans_id),
+            );
 
             // Associate the transaction id with the distance the returned nodes must beat and the timeout token
             self.active_lookups
-                .insert(trans_id, (dist_to_beat, timeout));
+                .insert(trans_id, (*node, dist_to_beat, timeout, Instant::now()));
 
             // Send the message to the node
-            let get_peers_msg = Message {
+            let lookup_msg = Message {
                 transaction_id: trans_id.as_ref().to_vec(),
-                body: MessageBody::Request(Request::GetPeers(GetPeersRequest {
-                    id: table.node_id(),
-                    info_hash: self.target_id,
-                    want: None,
-                })),
-            }
-            .encode();
+                read_only: self.read_only,
+                version: None,
+                body: MessageBody::Request(lookup_request(
+                    &self.kind,
+                    self.target_id,
+                    table.node_id(),
+                    node,
+                    self.want,
+                )),
+            };
 
-            if let Err(error) = socket.send(&get_peers_msg, node.addr).await {
+            if let Err(error) = socket.send(lookup_msg, node.addr).await {
                 log::error!(
                     "{}: Could not send a lookup message: {}",
                     self.ip_version,
@@ -371,7 +836,16 @@ impl TableLookup {
         }
 
         if messages_sent == 0 {
-            self.active_lookups.clear();
+            self.time_out_active_lookups(timer);
+        }
+    }
+
+    // Stop waiting for the responses to the lookup requests still pending and report their nodes
+    // as timed out.
+    fn time_out_active_lookups(&mut self, timer: &mut Timer<ScheduledTaskCheck>) {
+        for (_, (node, _, timeout, _)) in mem::take(&mut self.active_lookups) {
+            timer.cancel(timeout);
+            self.send_event(LookupEvent::NodeTimedOut(node));
         }
     }
 
@@ -386,9 +860,10 @@ impl TableLookup {
 
         // Try to start a global message timeout for the endgame
         let timeout = timer.schedule_in(
-            ENDGAME_TIMEOUT,
+            self.config.endgame_timeout,
             ScheduledTaskCheck::LookupEndGame(self.id_generator.generate()),
         );
+        self.endgame_timer = Some(timeout);
 
         // Request all unpinged nodes if we didnt receive any values
         if !self.recv_values {
@@ -401,20 +876,24 @@ impl TableLookup {
                 // Associate the transaction id with this node's distance and its timeout token
                 // We dont actually need to keep track of this information, but we do still need to
                 // filter out unsolicited responses by using the active_lookups map!!!
-                self.active_lookups.insert(trans_id, (*node_dist, timeout));
+                self.active_lookups
+                    .insert(trans_id, (*node, *node_dist, timeout, Instant::now()));
 
                 // Send the message to the node
-                let get_peers_msg = Message {
+                let lookup_msg = Message {
                     transaction_id: trans_id.as_ref().to_vec(),
-                    body: MessageBody::Request(Request::GetPeers(GetPeersRequest {
-                        id: table.node_id(),
-                        info_hash: self.target_id,
-                        want: None,
-                    })),
-                }
-                .encode();
+                    read_only: self.read_only,
+                    version: None,
+                    body: MessageBody::Request(lookup_request(
+                        &self.kind,
+                        self.target_id,
+                        table.node_id(),
+                        node,
+                        self.want,
+                    )),
+                };
 
-                if let Err(error) = socket.send(&get_peers_msg, node.addr).await {
+                if let Err(error) = socket.send(lookup_msg, node.addr).await {
                     log::error!(
                         "{}: Could not send an endgame message: {}",
                         self.ip_version,
@@ -429,6 +908,7 @@ impl TableLookup {
                 }
 
                 // Mark that we requested from the node
+                self.requested_nodes.insert(*node);
                 *req = true;
             }
         }
@@ -437,76 +917,155 @@ impl TableLookup {
     }
 }
 
-/// Picks a number of nodes from the sorted distance iterator to ping on the first round.
-fn pick_initial_nodes<'a, I>(sorted_nodes: I) -> [(NodeHandle, bool); INITIAL_PICK_NUM]
-where
-    I: Iterator<Item = &'a mut (Distance, NodeHandle, bool)>,
-{
-    let dummy_id = [0u8; NODE_ID_LEN].into();
-    let default = (
-        NodeHandle::new(dummy_id, SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))),
-        false,
-    );
+// The BEP44 item fields of a response.
+struct ItemResponse {
+    v: Option<Value>,
+    k: Option<Vec<u8>>,
+    sig: Option<Vec<u8>>,
+    seq: Option<i64>,
+}
 
-    let mut pick_nodes = [default; INITIAL_PICK_NUM];
-    for (src, dst) in sorted_nodes.zip(pick_nodes.iter_mut()) {
-        dst.0 = src.1;
-        dst.1 = true;
+// The request to send to the given node during the search phase of the lookup.
+fn lookup_request(
+    kind: &LookupKind,
+    target: InfoHash,
+    id: NodeId,
+    node: &NodeHandle,
+    want: Option<Want>,
+) -> Request {
+    match kind {
+        LookupKind::FindNode(_) => Request::FindNode(FindNodeRequest { id, target, want }),
+        LookupKind::GetPeers { .. } => Request::GetPeers(GetPeersRequest {
+            id,
+            info_hash: target,
+            want,
+            scrape: false,
+            noseed: false,
+        }),
+        LookupKind::Scrape(_) => Request::GetPeers(GetPeersRequest {
+            id,
+            info_hash: target,
+            want,
+            scrape: true,
+            noseed: false,
+        }),
+        LookupKind::GetImmutable(_)
+        | LookupKind::GetMutable { .. }
+        | LookupKind::PutItem { .. } => Request::Get(GetRequest {
+            id,
+            target,
+            seq: None,
+        }),
+        LookupKind::SampleInfohashes { skip, .. } if skip.contains(&node.addr) => {
+            Request::FindNode(FindNodeRequest { id, target, want })
+        }
+        LookupKind::SampleInfohashes { .. } => {
+            Request::SampleInfohashes(SampleInfohashesRequest { id, target, want })
+        }
+    }
+}
 
-        // Mark that the node has been requested from
-        src.2 = true;
+// The method of `lookup_request`.
+fn lookup_method(kind: &LookupKind, node: &NodeHandle) -> Method {
+    match kind {
+        LookupKind::FindNode(_) => Method::FindNode,
+        LookupKind::GetPeers { .. } | LookupKind::Scrape(_) => Method::GetPeers,
+        LookupKind::GetImmutable(_)
+        | LookupKind::GetMutable { .. }
+        | LookupKind::PutItem { .. } => Method::Get,
+        LookupKind::SampleInfohashes { skip, .. } if skip.contains(&node.addr) => Method::FindNode,
+        LookupKind::SampleInfohashes { .. } => Method::SampleInfohashes,
     }
+}
+
+fn put_request(id: NodeId, token: Vec<u8>, item: &Item, cas: Option<i64>) -> PutRequest {
+    let (k, sig, seq, salt) = match item {
+        Item::Immutable(_) => (None, None, None, None),
+        Item::Mutable(item) => (
+            Some(item.key().as_bytes().to_vec()),
+            Some(item.signature().to_bytes().to_vec()),
+            Some(item.seq()),
+            Some(item.salt().to_vec()).filter(|salt| !salt.is_empty()),
+        ),
+    };
 
-    pick_nodes
+    PutRequest {
+        id,
+        token,
+        v: item.value().clone(),
+        k,
+        sig,
+        seq,
+        salt,
+        cas,
+    }
 }
 
-/// Picks a number of nodes from the unsorted distance iterator to ping on iterative rounds.
-fn pick_iterate_nodes<I>(
-    unsorted_nodes: I,
-    target_id: InfoHash,
-) -> [(NodeHandle, bool); ITERATIVE_PICK_NUM]
-where
-    I: Iterator<Item = NodeHandle>,
-{
-    let dummy_id = [0u8; NODE_ID_LEN].into();
-    let default = (
-        NodeHandle::new(dummy_id, SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))),
-        false,
-    );
+fn node_rtt(table: &RoutingTable, node: &NodeHandle) -> Option<Duration> {
+    table.find_node(node).and_then(Node::rtt)
+}
 
-    let mut pick_nodes = [default; ITERATIVE_PICK_NUM];
-    for node in unsorted_nodes {
-        insert_closest_nodes(&mut pick_nodes, target_id, node);
+// Update the round-trip time of the node, if it's in the routing table, with its response to the
+// request sent at the given instant.
+fn record_rtt(table: &mut RoutingTable, node: &NodeHandle, sent: Instant) {
+    if let Some(node) = table.find_node_mut(node) {
+        node.record_rtt(sent.elapsed())
     }
+}
 
-    pick_nodes
+/// Number of bits after the leading one bit which the distances of two nodes must share for
+/// their round-trip times to decide between them. The nodes tied this way are within about 6% of
+/// each other's distance.
+const RTT_TIE_BITS: u32 = 4;
+
+/// Order in which to request the nodes: by distance, except that among the nodes about as close
+/// as each other the ones known to respond fast come first, then the ones whose round-trip time
+/// is unknown.
+fn pick_order(distance: Distance, rtt: Option<Duration>) -> (Distance, Duration, Distance) {
+    let tie_bits = distance.leading_zeros() + 1 + RTT_TIE_BITS;
+
+    (
+        distance.truncate(tie_bits as usize),
+        rtt.unwrap_or(Duration::MAX),
+        distance,
+    )
 }
 
-/// Inserts the node into the slice if a slot in the slice is unused or a node
-/// in the slice is further from the target id than the node being inserted.
-fn insert_closest_nodes(
-    nodes: &mut [(NodeHandle, bool)],
-    target_id: InfoHash,
-    new_node: NodeHandle,
-) {
-    let new_distance = target_id ^ new_node.id;
-
-    for &mut (ref mut old_node, ref mut used) in nodes.iter_mut() {
-        if !*used {
-            // Slot was not in use, go ahead and place the node
-            *old_node = new_node;
-            *used = true;
-            return;
-        } else {
-            // Slot is in use, see if our node is closer to the target
-            let old_distance = target_id ^ old_node.id;
+/// Picks a number of nodes from the sorted distance iterator to ping on the first round.
+fn pick_initial_nodes<'a, I, F>(sorted_nodes: I, num: usize, rtt: F) -> Vec<NodeHandle>
+where
+    I: Iterator<Item = &'a mut (Distance, NodeHandle, bool)>,
+    F: Fn(&NodeHandle) -> Option<Duration>,
+{
+    let mut candidates: Vec<_> = sorted_nodes.collect();
+    candidates.sort_by_key(|(distance, node, _)| pick_order(*distance, rtt(node)));
+
+    candidates
+        .into_iter()
+        .take(num)
+        .map(|(_, node, requested)| {
+            // Mark that the node has been requested from
+            *requested = true;
+            *node
+        })
+        .collect()
+}
 
-            if new_distance < old_distance {
-                *old_node = new_node;
-                return;
-            }
-        }
-    }
+/// Picks a number of nodes from the unsorted distance iterator to ping on iterative rounds.
+fn pick_iterate_nodes<I, F>(
+    unsorted_nodes: I,
+    target_id: InfoHash,
+    num: usize,
+    rtt: F,
+) -> Vec<NodeHandle>
+where
+    I: Iterator<Item = NodeHandle>,
+    F: Fn(&NodeHandle) -> Option<Duration>,
+{
+    let mut candidates: Vec<_> = unsorted_nodes.collect();
+    candidates.sort_by_key(|node| pick_order(target_id ^ node.id, rtt(node)));
+    candidates.truncate(num);
+    candidates
 }
 
 /// Inserts the Node into the list of nodes based on its distance from the target node.
@@ -536,3 +1095,263 @@ fn insert_sorted_node(
         Err(ins_index) => nodes.insert(ins_index, (node_dist, node, pinged)),
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{id::ID_LEN, test, transaction::AIDGenerator};
+    use std::net::Ipv4Addr;
+    use tokio::{net::UdpSocket, sync::mpsc};
+
+    #[tokio::test]
+    async fn positive_finished_times_out_pending_requests() {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let socket = Socket::new(socket, None).unwrap();
+        let mut timer = Timer::new();
+
+        let target = InfoHash::from([0; 20]);
+        let mut table = RoutingTable::new(NodeId::from([0x80; 20]), 8);
+        let near = NodeHandle::new(NodeId::from([1; 20]), addr);
+        let far = NodeHandle::new(NodeId::from([0xff; 20]), addr);
+        table.add_node(Node::as_good(near.id, near.addr));
+        table.add_node(Node::as_good(far.id, far.addr));
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let lookup = StartLookup {
+            target,
+            kind: LookupKind::GetPeers {
+                announce: AnnounceMode::Disabled,
+                tx,
+            },
+        };
+        let mut lookup = TableLookup::new(
+            lookup,
+            DhtConfig::new().set_lookup_initial_pick(1),
+            false,
+            None,
+            AIDGenerator::new().generate(),
+            &mut table,
+            &socket,
+            &mut timer,
+        )
+        .await;
+
+        // The near node responds, which leaves the far one to the endgame.
+        let trans_id = *lookup.active_lookups.keys().next().unwrap();
+        assert_eq!(lookup.request_method(&trans_id), Some(Method::GetPeers));
+        let status = lookup
+            .recv_response(
+                Node::as_good(near.id, near.addr),
+                &trans_id,
+                Response::new(near.id),
+                &mut table,
+                &socket,
+                &mut timer,
+            )
+            .await;
+        assert_eq!(status, ActionStatus::Ongoing);
+        assert!(lookup.in_endgame);
+
+        // The endgame ends without the far node responding.
+        let status = lookup
+            .recv_finished(None, &mut table, &socket, &mut timer)
+            .await;
+        assert_eq!(status, ActionStatus::Completed);
+        assert_eq!(lookup.request_method(&trans_id), None);
+        assert_eq!(socket.stats().timeouts, 1);
+        assert!(timer.is_empty());
+        drop(lookup);
+
+        assert_eq!(rx.recv().await, Some(LookupEvent::NodeResponded(near)));
+        assert_eq!(rx.recv().await, Some(LookupEvent::NodeTimedOut(far)));
+
+        match rx.recv().await {
+            Some(LookupEvent::Finished(summary)) => {
+                assert_eq!(summary.ip_version, IpVersion::V4);
+                assert_eq!(summary.closest_nodes, vec![near]);
+                assert_eq!(summary.queried, 2);
+            }
+            event => panic!("unexpected {:?}", event),
+        }
+
+        assert_eq!(rx.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn positive_find_node_yields_a_full_bucket() {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let socket = Socket::new(socket, None).unwrap();
+        let mut timer = Timer::new();
+
+        let mut table = RoutingTable::new(NodeId::from([0x80; 20]), 4);
+        let nodes: Vec<_> = test::dummy_block_socket_addrs(4)
+            .into_iter()
+            .enumerate()
+            .map(|(index, addr)| NodeHandle::new(NodeId::from([index as u8 + 1; 20]), addr))
+            .collect();
+        for node in &nodes {
+            table.add_node(Node::as_good(node.id, node.addr));
+        }
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let lookup = StartLookup {
+            target: InfoHash::from([0; 20]),
+            kind: LookupKind::FindNode(tx),
+        };
+        let mut lookup = TableLookup::new(
+            lookup,
+            DhtConfig::new()
+                .set_bucket_size(4)
+                .set_lookup_initial_pick(4)
+                .set_lookup_store_pick(2),
+            false,
+            None,
+            AIDGenerator::new().generate(),
+            &mut table,
+            &socket,
+            &mut timer,
+        )
+        .await;
+
+        let requests: Vec<_> = lookup
+            .active_lookups
+            .iter()
+            .map(|(trans_id, (node, _, _, _))| (*trans_id, *node))
+            .collect();
+        for (trans_id, node) in requests {
+            lookup
+                .recv_response(
+                    Node::as_good(node.id, node.addr),
+                    &trans_id,
+                    Response::new(node.id),
+                    &mut table,
+                    &socket,
+                    &mut timer,
+                )
+                .await;
+        }
+
+        let status = lookup
+            .recv_finished(None, &mut table, &socket, &mut timer)
+            .await;
+        assert_eq!(status, ActionStatus::Completed);
+        drop(lookup);
+
+        // All the responders are reported, not just the number the other lookups store to.
+        let mut found = Vec::new();
+        while let Some(node) = rx.recv().await {
+            found.push(node);
+        }
+        assert_eq!(found, nodes);
+    }
+
+    #[tokio::test]
+    async fn positive_abandoned_watch_ends_with_the_lookup() {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let socket = Socket::new(socket, None).unwrap();
+        let mut timer = Timer::new();
+        let mut table = RoutingTable::new(NodeId::from([0x80; 20]), 8);
+
+        let start = |tx| StartLookup {
+            target: InfoHash::from([0; 20]),
+            kind: LookupKind::FindNode(tx),
+        };
+
+        // Dropping the receiver resolves the watch.
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut lookup = TableLookup::new(
+            start(tx),
+            DhtConfig::new(),
+            false,
+            None,
+            AIDGenerator::new().generate(),
+            &mut table,
+            &socket,
+            &mut timer,
+        )
+        .await;
+        let abandoned = lookup.abandoned();
+        drop(rx);
+        assert!(abandoned.await);
+
+        // Dropping the lookup resolves it too, and lets go of the sender so the receiver ends.
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut lookup = TableLookup::new(
+            start(tx),
+            DhtConfig::new(),
+            false,
+            None,
+            AIDGenerator::new().generate(),
+            &mut table,
+            &socket,
+            &mut timer,
+        )
+        .await;
+        let abandoned = lookup.abandoned();
+        drop(lookup);
+        assert!(!abandoned.await);
+        assert_eq!(rx.recv().await, None);
+    }
+
+    #[test]
+    fn positive_pick_initial_nodes_by_distance_then_rtt() {
+        let (nodes, rtt) = pick_candidates();
+        let mut sorted_nodes: Vec<_> = nodes.iter().map(|node| (node.id, *node, false)).collect();
+        sorted_nodes.sort_by_key(|(distance, _, _)| *distance);
+
+        let picked = pick_initial_nodes(sorted_nodes.iter_mut(), 3, &rtt);
+
+        // Closest first. The next two are about as close, the one known to be fast goes first.
+        // The fastest node is too far to be picked.
+        assert_eq!(picked, [nodes[0], nodes[2], nodes[1]]);
+        assert!(sorted_nodes
+            .iter()
+            .all(|(_, node, requested)| *requested == picked.contains(node)));
+    }
+
+    #[test]
+    fn positive_pick_iterate_nodes_by_distance_then_rtt() {
+        let (nodes, rtt) = pick_candidates();
+        let target = InfoHash::from([0; ID_LEN]);
+
+        let picked = pick_iterate_nodes(nodes.iter().rev().copied(), target, 4, &rtt);
+        assert_eq!(picked, [nodes[0], nodes[2], nodes[1], nodes[3]]);
+
+        let picked = pick_iterate_nodes(nodes.iter().copied(), target, 2, &rtt);
+        assert_eq!(picked, [nodes[0], nodes[2]]);
+    }
+
+    // Nodes in order of their distance to the zero target, with a function telling their
+    // round-trip times.
+    fn pick_candidates() -> (Vec<NodeHandle>, impl Fn(&NodeHandle) -> Option<Duration>) {
+        let addrs = test::dummy_block_socket_addrs(4);
+        let ids: Vec<NodeId> = [[0x08, 0x00], [0x10, 0x00], [0x10, 0x01], [0x11, 0x00]]
+            .iter()
+            .map(|prefix| {
+                let mut id = [0; ID_LEN];
+                id[..2].copy_from_slice(prefix);
+                NodeId::from(id)
+            })
+            .collect();
+        let nodes: Vec<_> = ids
+            .into_iter()
+            .zip(addrs)
+            .map(|(id, addr)| NodeHandle::new(id, addr))
+            .collect();
+
+        let fast = nodes[2];
+        let fastest = nodes[3];
+        let rtt = move |node: &NodeHandle| {
+            if *node == fast {
+                Some(Duration::from_millis(10))
+            } else if *node == fastest {
+                Some(Duration::from_millis(1))
+            } else {
+                None
+            }
+        };
+
+        (nodes, rtt)
+    }
+}
@@ -1,14 +1,36 @@
+pub use self::stats::{MessageCounts, Stats};
 pub(crate) use self::{handler::DhtHandler, socket::Socket};
-use crate::{id::InfoHash, transaction::TransactionID};
-use std::{collections::HashSet, fmt, io, net::SocketAddr, time::Duration};
+use crate::{
+    announce::{AnnounceMode, AnnounceOptions},
+    bloom::ScrapeResult,
+    id::{InfoHash, NodeId},
+    item::{Item, MutableItem, PutError},
+    routing::{
+        node::NodeHandle,
+        snapshot::{RoutingTableInfo, RoutingTableSnapshot},
+    },
+    sample::InfoHashSample,
+    transaction::TransactionID,
+};
+use serde::Serialize;
+use serde_bencode::value::Value;
+use std::{
+    any::Any,
+    collections::{HashMap, HashSet},
+    fmt, future, io,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 use thiserror::Error;
-use tokio::sync::{mpsc, oneshot};
+use tokio::sync::{broadcast, mpsc, oneshot};
 
 mod bootstrap;
 mod handler;
 mod lookup;
 mod refresh;
 mod socket;
+mod stats;
 mod timer;
 
 #[derive(Copy, Clone, Debug)]
@@ -20,7 +42,119 @@ pub struct State {
     pub bucket_count: usize,
 }
 
-#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+/// Event yielded from [`MainlineDht::events()`](crate::MainlineDht::events).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DhtEvent {
+    /// The routing table of the given address family became bootstrapped, or stopped being so.
+    BootstrapChanged {
+        ip_version: IpVersion,
+        bootstrapped: bool,
+    },
+    /// A node was added to the routing table.
+    NodeAdded(NodeHandle),
+    /// A node was removed from the routing table after it stopped responding, to make room for a
+    /// node from the replacement cache of its bucket.
+    NodeEvicted(NodeHandle),
+    /// We stored a peer announced by a remote node.
+    AnnounceStored {
+        info_hash: InfoHash,
+        peer: SocketAddr,
+    },
+    /// We refused to store a peer announced by a remote node because our storage is full.
+    AnnounceRejected {
+        info_hash: InfoHash,
+        addr: SocketAddr,
+    },
+    /// A remote node sent an `announce_peer` or `put` request with a token we didn't give it, or
+    /// which expired.
+    InvalidToken { addr: SocketAddr },
+    /// Sending or receiving on the socket of the given address family failed.
+    SocketError {
+        ip_version: IpVersion,
+        kind: io::ErrorKind,
+        message: String,
+    },
+    /// A lookup for the target started on the given address family.
+    LookupStarted {
+        ip_version: IpVersion,
+        target: InfoHash,
+    },
+    /// A lookup for the target finished, or was aborted, on the given address family.
+    LookupFinished {
+        ip_version: IpVersion,
+        target: InfoHash,
+    },
+    /// The stream was not polled fast enough and missed the given number of events of the given
+    /// address family.
+    Lagged { ip_version: IpVersion, missed: u64 },
+}
+
+/// Progress of a lookup started with [`MainlineDht::lookup()`](crate::MainlineDht::lookup).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum LookupEvent {
+    /// A node sent us the address of a peer.
+    PeerFound {
+        peer: SocketAddr,
+        source: NodeHandle,
+    },
+    /// A node responded to our request.
+    NodeResponded(NodeHandle),
+    /// A node didn't respond to our request in time, or responded with an error.
+    NodeTimedOut(NodeHandle),
+    /// A node accepted our announce.
+    AnnounceAccepted(NodeHandle),
+    /// A node responded to our announce with an error.
+    AnnounceRejected {
+        node: NodeHandle,
+        code: u16,
+        message: String,
+    },
+    /// The lookup is over. This is the last event, except when running dual-stack where each
+    /// address family finishes separately with its own summary. The lookup is over once both of
+    /// them have finished.
+    Finished(LookupSummary),
+}
+
+/// Summary of a finished lookup, over a single address family.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LookupSummary {
+    /// Address family the lookup was performed over.
+    pub ip_version: IpVersion,
+    /// The nodes closest to the target which responded, closest first.
+    pub closest_nodes: Vec<NodeHandle>,
+    /// Number of nodes we sent a request to.
+    pub queried: usize,
+    /// Time from the start of the lookup until it finished.
+    pub duration: Duration,
+}
+
+/// Response to [`MainlineDht::ping()`](crate::MainlineDht::ping).
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct PingResponse {
+    /// Id of the node which responded.
+    pub id: NodeId,
+    /// Time it took the node to respond.
+    pub rtt: Duration,
+}
+
+/// Error returned from [`MainlineDht::ping()`](crate::MainlineDht::ping).
+#[derive(Debug, Error)]
+pub enum PingError {
+    /// The node didn't respond in time.
+    #[error("ping timed out")]
+    Timeout,
+    /// The node responded with an error.
+    #[error("ping rejected: {message} ({code})")]
+    Rejected { code: u16, message: String },
+    /// The ping could not be sent.
+    #[error("failed to send ping")]
+    Socket(#[source] io::Error),
+    /// The DHT has shut down.
+    #[error("DhtHandler has shut down")]
+    Shutdown,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Serialize)]
 pub enum IpVersion {
     V4,
     V6,
@@ -42,18 +176,205 @@ pub(crate) enum OneshotTask {
     /// Check bootstrap status. The given sender will be notified when the bootstrap completed,
     /// with an optional timeout.
     CheckBootstrap(oneshot::Sender<bool>, Option<Duration>),
-    /// Start a lookup for the given InfoHash.
+    /// Start a lookup for the given target.
     StartLookup(StartLookup),
+    /// Start announcing periodically.
+    StartAnnounce(StartAnnounce),
     /// Get the local address the socket is bound to.
     GetLocalAddr(oneshot::Sender<SocketAddr>),
     /// Retrieve debug information.
     GetState(oneshot::Sender<State>),
+    /// Retrieve the traffic and health statistics.
+    GetStats(oneshot::Sender<Stats>),
+    /// Get our external address as reported by the remote nodes.
+    GetExternalAddr(oneshot::Sender<Option<SocketAddr>>),
+    /// Subscribe to changes of our external address.
+    WatchExternalAddr(mpsc::UnboundedSender<SocketAddr>),
+    /// Ping the node at the given address.
+    Ping(SocketAddr, oneshot::Sender<Result<PingResponse, PingError>>),
+    /// Count the nodes in the routing table by their client identification.
+    GetClientCensus(oneshot::Sender<HashMap<Option<Vec<u8>>, usize>>),
+    /// Take a snapshot of the routing table.
+    ExportRoutingTable(oneshot::Sender<RoutingTableSnapshot>),
+    /// Describe the routing table bucket by bucket.
+    InspectRoutingTable(oneshot::Sender<RoutingTableInfo>),
+    /// Subscribe to the events of the handler.
+    WatchEvents(oneshot::Sender<broadcast::Receiver<DhtEvent>>),
+    /// Stop the handler, either finishing the ongoing lookups with the nodes found so far or
+    /// dropping them. The socket and a snapshot of the routing table are sent back once stopped.
+    Shutdown {
+        finish_lookups: bool,
+        tx: oneshot::Sender<(Box<dyn Any + Send>, RoutingTableSnapshot)>,
+    },
 }
 
 pub(crate) struct StartLookup {
+    pub target: InfoHash,
+    pub kind: LookupKind,
+}
+
+pub(crate) struct StartAnnounce {
     pub info_hash: InfoHash,
-    pub announce: bool,
-    pub tx: mpsc::UnboundedSender<SocketAddr>,
+    pub options: AnnounceOptions,
+    pub tx: mpsc::UnboundedSender<LookupEvent>,
+}
+
+/// What a lookup is looking for and what to do once it finds the nodes closest to the target.
+#[derive(Clone)]
+pub(crate) enum LookupKind {
+    /// Find the nodes closest to the target. The closest ones which responded are sent through the
+    /// channel once the lookup finishes.
+    FindNode(mpsc::UnboundedSender<NodeHandle>),
+    /// Find peers for the target InfoHash and optionally announce ourselves to the closest nodes.
+    GetPeers {
+        announce: AnnounceMode,
+        tx: mpsc::UnboundedSender<LookupEvent>,
+    },
+    /// Find the immutable item (BEP44) stored under the target.
+    GetImmutable(mpsc::UnboundedSender<Value>),
+    /// Find the mutable item (BEP44) stored under the target. The salt is needed to verify the
+    /// signatures. Every valid item found is sent through the channel, regardless of its sequence
+    /// number.
+    GetMutable {
+        salt: Vec<u8>,
+        tx: mpsc::UnboundedSender<MutableItem>,
+    },
+    /// Store the item (BEP44) on the closest nodes. The outcome of each put request is sent
+    /// through the channel.
+    PutItem {
+        item: Item,
+        cas: Option<i64>,
+        tx: mpsc::UnboundedSender<Result<(), PutError>>,
+    },
+    /// Estimate the number of seeds and leechers of the target torrent from the bloom filters
+    /// (BEP33) of the closest nodes. The estimate is sent through the channel once the lookup
+    /// finishes, unless none of the nodes sent the filters.
+    Scrape(mpsc::UnboundedSender<ScrapeResult>),
+    /// Sample the info hashes stored by the nodes closest to the target (BEP51). The nodes in
+    /// `skip` are not asked for samples, only for other nodes.
+    SampleInfohashes {
+        skip: HashSet<SocketAddr>,
+        tx: mpsc::UnboundedSender<InfoHashSample>,
+    },
+}
+
+impl LookupKind {
+    /// Whether the receiver of the results has been dropped, so there is no point in continuing
+    /// the lookup.
+    pub fn is_abandoned(&self) -> bool {
+        match self {
+            Self::FindNode(tx) => tx.is_closed(),
+            Self::GetPeers { tx, .. } => tx.is_closed(),
+            Self::GetImmutable(tx) => tx.is_closed(),
+            Self::GetMutable { tx, .. } => tx.is_closed(),
+            Self::PutItem { tx, .. } => tx.is_closed(),
+            Self::Scrape(tx) => tx.is_closed(),
+            Self::SampleInfohashes { tx, .. } => tx.is_closed(),
+        }
+    }
+
+    /// Wait until the receiver of the results is dropped.
+    pub async fn abandoned(&self) {
+        match self {
+            Self::FindNode(tx) => tx.closed().await,
+            Self::GetPeers { tx, .. } => tx.closed().await,
+            Self::GetImmutable(tx) => tx.closed().await,
+            Self::GetMutable { tx, .. } => tx.closed().await,
+            Self::PutItem { tx, .. } => tx.closed().await,
+            Self::Scrape(tx) => tx.closed().await,
+            Self::SampleInfohashes { tx, .. } => tx.closed().await,
+        }
+    }
+}
+
+/// Number of events kept for a subscriber which hasn't received them yet. Beyond that the oldest
+/// ones are dropped and the subscriber is told how many it missed.
+const EVENT_CAPACITY: usize = 256;
+
+/// Subscribers to the events of a handler, shared with its socket.
+#[derive(Clone)]
+pub(crate) struct EventSubscribers(broadcast::Sender<DhtEvent>);
+
+impl EventSubscribers {
+    pub fn new() -> Self {
+        Self(broadcast::channel(EVENT_CAPACITY).0)
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<DhtEvent> {
+        self.0.subscribe()
+    }
+
+    /// Send the event to all the subscribers, if there are any.
+    pub fn emit(&self, event: DhtEvent) {
+        self.0.send(event).unwrap_or(0);
+    }
+}
+
+impl Default for EventSubscribers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Link between the handlers of the two address families of a dual-stack DHT. Each handler passes
+/// the other one the nodes of its family it learns of, and shares the nodes of its routing table so
+/// the other one can include them in its responses (BEP32).
+pub(crate) struct Sibling {
+    tx: mpsc::UnboundedSender<Vec<NodeHandle>>,
+    rx: mpsc::UnboundedReceiver<Vec<NodeHandle>>,
+    our_nodes: Arc<Mutex<Vec<NodeHandle>>>,
+    their_nodes: Arc<Mutex<Vec<NodeHandle>>>,
+}
+
+impl Sibling {
+    /// Create the two ends of the link.
+    pub fn pair() -> (Self, Self) {
+        let (tx_a, rx_a) = mpsc::unbounded_channel();
+        let (tx_b, rx_b) = mpsc::unbounded_channel();
+        let nodes_a = Arc::new(Mutex::new(Vec::new()));
+        let nodes_b = Arc::new(Mutex::new(Vec::new()));
+
+        (
+            Self {
+                tx: tx_a,
+                rx: rx_b,
+                our_nodes: nodes_a.clone(),
+                their_nodes: nodes_b.clone(),
+            },
+            Self {
+                tx: tx_b,
+                rx: rx_a,
+                our_nodes: nodes_b,
+                their_nodes: nodes_a,
+            },
+        )
+    }
+
+    /// Replace the nodes shared with the other handler by the current ones of our routing table.
+    pub fn share_nodes(&self, nodes: Vec<NodeHandle>) {
+        *self.our_nodes.lock().unwrap() = nodes;
+    }
+
+    /// The `count` nodes shared by the other handler which are the closest to the target.
+    pub fn closest_nodes(&self, target: NodeId, count: usize) -> Vec<NodeHandle> {
+        let mut nodes = self.their_nodes.lock().unwrap().clone();
+        nodes.sort_by_key(|node| node.id ^ target);
+        nodes.truncate(count);
+        nodes
+    }
+
+    /// Pass the nodes to the other handler, unless it has shut down.
+    pub fn send(&self, nodes: Vec<NodeHandle>) {
+        self.tx.send(nodes).unwrap_or(())
+    }
+
+    /// Receive the nodes passed by the other handler. Never completes once it has shut down.
+    pub async fn recv(&mut self) -> Vec<NodeHandle> {
+        match self.rx.recv().await {
+            Some(nodes) => nodes,
+            None => future::pending().await,
+        }
+    }
 }
 
 /// Signifies what has timed out in the TableBootstrap class.
@@ -68,6 +389,8 @@ pub(crate) enum BootstrapTimeout {
 pub(crate) enum ScheduledTaskCheck {
     /// Check the progress of the bucket refresh.
     TableRefresh,
+    /// Timeout for a request of the table refresh.
+    RefreshTimeout(TransactionID),
     /// Check the progress of the current bootstrap.
     BootstrapTimeout(BootstrapTimeout),
     /// Timeout for user waiting to get bootstrapped.
@@ -76,6 +399,10 @@ pub(crate) enum ScheduledTaskCheck {
     LookupTimeout(TransactionID),
     /// Check the progress of the lookup endgame.
     LookupEndGame(TransactionID),
+    /// Timeout for a ping requested by the user.
+    PingTimeout(TransactionID),
+    /// Start the next round of a periodic announce.
+    AnnounceRound(u64),
 }
 
 #[derive(Error, Debug)]
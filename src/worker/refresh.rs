@@ -1,28 +1,73 @@
-use super::{socket::Socket, timer::Timer, ScheduledTaskCheck};
-use crate::message::{FindNodeRequest, Message, MessageBody, Request};
-use crate::routing::node::NodeStatus;
+use super::{
+    socket::Socket,
+    stats::Method,
+    timer::{Timeout, Timer},
+    ScheduledTaskCheck,
+};
+use crate::config::DhtConfig;
+use crate::message::{FindNodeRequest, Message, MessageBody, PingRequest, Request};
+use crate::routing::node::{NodeHandle, NodeStatus};
 use crate::routing::table::{self, RoutingTable};
-use crate::transaction::{ActionID, MIDGenerator};
-use std::time::Duration;
-
-const REFRESH_INTERVAL_TIMEOUT: Duration = Duration::from_millis(6000);
-const REFRESH_CONCURRENCY: usize = 4;
+use crate::transaction::{MIDGenerator, TransactionID};
+use std::{collections::HashMap, time::Instant};
 
 pub(crate) struct TableRefresh {
     id_generator: MIDGenerator,
+    // For the pings, to tell their responses from the find_node ones.
+    ping_id_generator: MIDGenerator,
+    read_only: bool,
+    config: DhtConfig,
     curr_refresh_bucket: usize,
+    // The requests waiting for a response, to tell the table about the nodes which don't respond
+    // and the round-trip times of those which do.
+    active_requests: HashMap<TransactionID, (NodeHandle, Timeout, Instant)>,
 }
 
 impl TableRefresh {
-    pub fn new(id_generator: MIDGenerator) -> TableRefresh {
+    pub fn new(
+        id_generator: MIDGenerator,
+        ping_id_generator: MIDGenerator,
+        read_only: bool,
+        config: DhtConfig,
+    ) -> TableRefresh {
         TableRefresh {
             id_generator,
+            ping_id_generator,
+            read_only,
+            config,
             curr_refresh_bucket: 0,
+            active_requests: HashMap::new(),
         }
     }
 
-    pub fn action_id(&self) -> ActionID {
-        self.id_generator.action_id()
+    /// Stop waiting for the response to the request with the given transaction id and record the
+    /// round-trip time of its node. Returns its method, or `None` if it is not a request of the
+    /// refresh waiting for a response.
+    pub fn recv_response(
+        &mut self,
+        trans_id: &TransactionID,
+        table: &mut RoutingTable,
+        timer: &mut Timer<ScheduledTaskCheck>,
+    ) -> Option<Method> {
+        let (node, timeout, sent) = self.active_requests.remove(trans_id)?;
+        timer.cancel(timeout);
+
+        if let Some(node) = table.find_node_mut(&node) {
+            node.record_rtt(sent.elapsed());
+        }
+
+        if trans_id.action_id() == self.ping_id_generator.action_id() {
+            Some(Method::Ping)
+        } else {
+            Some(Method::FindNode)
+        }
+    }
+
+    /// The node didn't respond to the request with the given transaction id in time.
+    pub fn recv_timeout(&mut self, trans_id: &TransactionID, table: &mut RoutingTable) {
+        if let Some((node, _, _)) = self.active_requests.remove(trans_id) {
+            table.request_timed_out(&node);
+        }
     }
 
     pub async fn continue_refresh(
@@ -45,7 +90,7 @@ impl TableRefresh {
             .closest_nodes(target_id)
             .filter(|n| n.status() == NodeStatus::Questionable)
             .filter(|n| !n.recently_requested_from())
-            .take(REFRESH_CONCURRENCY)
+            .take(self.config.refresh_pick)
             .map(|node| *node.handle())
             .collect::<Vec<_>>();
 
@@ -62,15 +107,19 @@ impl TableRefresh {
             };
             let find_node_msg = Message {
                 transaction_id: trans_id.as_ref().to_vec(),
+                read_only: self.read_only,
+                version: None,
                 body: MessageBody::Request(Request::FindNode(find_node_req)),
             };
-            let find_node_msg = find_node_msg.encode();
 
             // Send the message
-            if let Err(error) = socket.send(&find_node_msg, node.addr).await {
+            if let Err(error) = socket.send(find_node_msg, node.addr).await {
                 log::error!("TableRefresh failed to send a refresh message: {}", error);
+                continue;
             }
 
+            self.start_timeout(trans_id, node, timer);
+
             // Mark that we requested from the node
             if let Some(node) = table.find_node_mut(&node) {
                 node.local_request();
@@ -78,8 +127,59 @@ impl TableRefresh {
         }
 
         // Start a timer for the next refresh
-        timer.schedule_in(REFRESH_INTERVAL_TIMEOUT, ScheduledTaskCheck::TableRefresh);
+        timer.schedule_in(
+            self.config.refresh_interval,
+            ScheduledTaskCheck::TableRefresh,
+        );
 
         self.curr_refresh_bucket += 1;
     }
+
+    /// Ping the given nodes, to find out whether they are still alive. The responses are handled
+    /// like the refresh ones, the nodes which don't respond are reported to the table.
+    pub async fn ping_nodes(
+        &mut self,
+        table: &mut RoutingTable,
+        socket: &Socket,
+        timer: &mut Timer<ScheduledTaskCheck>,
+        nodes: Vec<NodeHandle>,
+    ) {
+        for node in nodes {
+            let trans_id = self.ping_id_generator.generate();
+
+            let ping_msg = Message {
+                transaction_id: trans_id.as_ref().to_vec(),
+                read_only: self.read_only,
+                version: None,
+                body: MessageBody::Request(Request::Ping(PingRequest {
+                    id: table.node_id(),
+                })),
+            };
+
+            if let Err(error) = socket.send(ping_msg, node.addr).await {
+                log::error!("TableRefresh failed to send a ping message: {}", error);
+                continue;
+            }
+
+            self.start_timeout(trans_id, node, timer);
+
+            if let Some(node) = table.find_node_mut(&node) {
+                node.local_request();
+            }
+        }
+    }
+
+    fn start_timeout(
+        &mut self,
+        trans_id: TransactionID,
+        node: NodeHandle,
+        timer: &mut Timer<ScheduledTaskCheck>,
+    ) {
+        let timeout = timer.schedule_in(
+            self.config.lookup_timeout,
+            ScheduledTaskCheck::RefreshTimeout(trans_id),
+        );
+        self.active_requests
+            .insert(trans_id, (node, timeout, Instant::now()));
+    }
 }
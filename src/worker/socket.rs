@@ -1,36 +1,105 @@
 //! Helpers to simplify work with UdpSocket.
 
-use super::IpVersion;
-use crate::SocketTrait;
+use super::{stats::Method, DhtEvent, EventSubscribers, IpVersion, Stats};
+use crate::{
+    message::{Message, MessageBody},
+    SocketTrait,
+};
 use async_trait::async_trait;
-use std::{io, net::SocketAddr};
+use std::{
+    any::Any,
+    io,
+    net::SocketAddr,
+    sync::{Mutex, MutexGuard},
+};
 use tokio::net::UdpSocket;
 
-pub struct Socket(Box<dyn SocketTrait + Send + Sync + 'static>, SocketAddr);
+pub struct Socket(
+    Box<dyn AnySocket>,
+    SocketAddr,
+    // Client identification to put into the outgoing messages.
+    Option<Vec<u8>>,
+    // Told about the errors.
+    EventSubscribers,
+    // Traffic counters. Behind a mutex because sending only borrows the socket immutably.
+    Mutex<Stats>,
+);
 
 impl Socket {
-    pub fn new<S: SocketTrait + Send + Sync + 'static>(inner: S) -> io::Result<Self> {
+    pub fn new<S: SocketTrait + Send + Sync + 'static>(
+        inner: S,
+        version: Option<Vec<u8>>,
+    ) -> io::Result<Self> {
         let inner = Box::new(inner);
         let local_addr = inner.local_addr()?;
-        Ok(Self(inner, local_addr))
+        Ok(Self(
+            inner,
+            local_addr,
+            version,
+            EventSubscribers::default(),
+            Mutex::new(Stats::default()),
+        ))
     }
 
-    pub(crate) async fn send(&self, bytes: &[u8], addr: SocketAddr) -> io::Result<()> {
+    /// Report the send and receive errors to the given subscribers.
+    pub(crate) fn set_event_subscribers(&mut self, subscribers: EventSubscribers) {
+        self.3 = subscribers;
+    }
+
+    /// Encode the message, with our client identification, and send it to the given address.
+    pub(crate) async fn send(&self, mut message: Message, addr: SocketAddr) -> io::Result<()> {
+        message.version = self.2.clone();
+        let bytes = message.encode();
+
         // Note: if the socket fails to send the entire buffer, then there is no point in trying to
         // send the rest (no node will attempt to reassemble two or more datagrams into a
         // meaningful message).
-        self.0.send_to(bytes, &addr).await?;
+        self.0
+            .send_to(&bytes, &addr)
+            .await
+            .map_err(|error| self.report(error))?;
+        self.stats().record_sent(&message.body, bytes.len());
+
+        Ok(())
+    }
+
+    /// Send our reply to a request of the given method. A response is counted by that method, an
+    /// error by its code like any other.
+    pub(crate) async fn send_reply(
+        &self,
+        message: Message,
+        addr: SocketAddr,
+        method: Method,
+    ) -> io::Result<()> {
+        let is_response = matches!(message.body, MessageBody::Response(_));
+        self.send(message, addr).await?;
+
+        if is_response {
+            self.stats().responses_sent.record_response(method);
+        }
+
         Ok(())
     }
 
     /// This function is cancel safe: https://docs.rs/tokio/1.12.0/tokio/net/struct.UdpSocket.html#cancel-safety-6
     pub(crate) async fn recv(&mut self) -> io::Result<(Vec<u8>, SocketAddr)> {
         let mut buffer = vec![0u8; 1500];
-        let (size, addr) = self.0.recv_from(&mut buffer).await?;
+        let (size, addr) = self
+            .0
+            .recv_from(&mut buffer)
+            .await
+            .map_err(|error| self.report(error))?;
         buffer.truncate(size);
+        self.stats().bytes_received += size as u64;
         Ok((buffer, addr))
     }
 
+    /// Counters of the traffic through this socket. The handler records the incoming messages
+    /// itself as only it decodes them.
+    pub(crate) fn stats(&self) -> MutexGuard<'_, Stats> {
+        self.4.lock().unwrap()
+    }
+
     pub fn local_addr(&self) -> SocketAddr {
         self.1
     }
@@ -41,6 +110,32 @@ impl Socket {
             SocketAddr::V6(_) => IpVersion::V6,
         }
     }
+
+    fn report(&self, error: io::Error) -> io::Error {
+        self.3.emit(DhtEvent::SocketError {
+            ip_version: self.ip_version(),
+            kind: error.kind(),
+            message: error.to_string(),
+        });
+
+        error
+    }
+
+    /// Give back the socket this was created from, to be downcast to its original type.
+    pub fn into_inner(self) -> Box<dyn Any + Send> {
+        self.0.into_any()
+    }
+}
+
+// Lets the socket be handed back to the user with its original type.
+trait AnySocket: SocketTrait + Send + Sync {
+    fn into_any(self: Box<Self>) -> Box<dyn Any + Send>;
+}
+
+impl<S: SocketTrait + Send + Sync + 'static> AnySocket for S {
+    fn into_any(self: Box<Self>) -> Box<dyn Any + Send> {
+        self
+    }
 }
 
 #[async_trait]
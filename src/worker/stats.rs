@@ -0,0 +1,223 @@
+use crate::message::{MessageBody, Request};
+use std::collections::BTreeMap;
+
+/// Traffic and health statistics, returned from
+/// [`MainlineDht::stats()`](crate::MainlineDht::stats). The counters are totals since the DHT
+/// started. When running dual-stack, they are those of both address families combined.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Stats {
+    /// Requests we sent, by method.
+    pub requests_sent: MessageCounts,
+    /// Requests we received, by method.
+    pub requests_received: MessageCounts,
+    /// Responses we sent, by the method of the request they answer.
+    pub responses_sent: MessageCounts,
+    /// Responses we received, by the method of the request of ours they answer. The unsolicited
+    /// ones are only counted in `unsolicited_responses`.
+    pub responses_received: MessageCounts,
+    /// Error replies we sent, by error code.
+    pub errors_sent: BTreeMap<u16, u64>,
+    /// Error replies we received, by error code.
+    pub errors_received: BTreeMap<u16, u64>,
+    /// Requests of ours which got no reply in time.
+    pub timeouts: u64,
+    /// Bytes of the messages we sent.
+    pub bytes_sent: u64,
+    /// Bytes of the datagrams we received.
+    pub bytes_received: u64,
+    /// Received datagrams which could not be decoded.
+    pub decode_failures: u64,
+    /// Received responses which matched none of our requests.
+    pub unsolicited_responses: u64,
+    /// Lookups in progress.
+    pub active_lookups: usize,
+    /// Timeouts scheduled by the handler, including the ones of the requests waiting for a reply.
+    pub scheduled_timeouts: usize,
+    /// Peers stored on behalf of the remote nodes announcing to us.
+    pub stored_peers: usize,
+    /// Maximum number of peers we store on behalf of the remote nodes.
+    pub max_stored_peers: usize,
+}
+
+impl Stats {
+    /// Count an outgoing message of the given size.
+    pub(crate) fn record_sent(&mut self, body: &MessageBody, size: usize) {
+        match body {
+            MessageBody::Request(request) => self.requests_sent.record(request),
+            // Counted by the method of the request they answer, see `Socket::send_reply`.
+            MessageBody::Response(_) => (),
+            MessageBody::Error(error) => *self.errors_sent.entry(error.code).or_default() += 1,
+        }
+
+        self.bytes_sent += size as u64;
+    }
+
+    /// Count an incoming message, decoded successfully.
+    pub(crate) fn record_received(&mut self, body: &MessageBody) {
+        match body {
+            MessageBody::Request(request) => self.requests_received.record(request),
+            // Counted once matched to the request of ours they answer.
+            MessageBody::Response(_) => (),
+            MessageBody::Error(error) => *self.errors_received.entry(error.code).or_default() += 1,
+        }
+    }
+
+    /// Add the counters of the other address family.
+    pub(crate) fn merge(&mut self, other: Stats) {
+        self.requests_sent.merge(&other.requests_sent);
+        self.requests_received.merge(&other.requests_received);
+        self.responses_sent.merge(&other.responses_sent);
+        self.responses_received.merge(&other.responses_received);
+
+        for (code, count) in other.errors_sent {
+            *self.errors_sent.entry(code).or_default() += count;
+        }
+
+        for (code, count) in other.errors_received {
+            *self.errors_received.entry(code).or_default() += count;
+        }
+
+        self.timeouts += other.timeouts;
+        self.bytes_sent += other.bytes_sent;
+        self.bytes_received += other.bytes_received;
+        self.decode_failures += other.decode_failures;
+        self.unsolicited_responses += other.unsolicited_responses;
+        self.active_lookups += other.active_lookups;
+        self.scheduled_timeouts += other.scheduled_timeouts;
+        self.stored_peers += other.stored_peers;
+        self.max_stored_peers += other.max_stored_peers;
+    }
+}
+
+/// Number of messages of each method, part of [`Stats`]. Responses count under the method of the
+/// request they answer.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct MessageCounts {
+    pub ping: u64,
+    pub find_node: u64,
+    pub get_peers: u64,
+    pub announce_peer: u64,
+    pub get: u64,
+    pub put: u64,
+    pub sample_infohashes: u64,
+    /// Requests of a method we don't understand. Always zero for the responses.
+    pub unknown: u64,
+    /// Requests of a known method with missing or invalid arguments. Always zero for the
+    /// responses.
+    pub malformed: u64,
+}
+
+impl MessageCounts {
+    /// Total of all the methods.
+    pub fn total(&self) -> u64 {
+        self.ping
+            + self.find_node
+            + self.get_peers
+            + self.announce_peer
+            + self.get
+            + self.put
+            + self.sample_infohashes
+            + self.unknown
+            + self.malformed
+    }
+
+    /// Count a response to a request of the given method.
+    pub(crate) fn record_response(&mut self, method: Method) {
+        let count = match method {
+            Method::Ping => &mut self.ping,
+            Method::FindNode => &mut self.find_node,
+            Method::GetPeers => &mut self.get_peers,
+            Method::AnnouncePeer => &mut self.announce_peer,
+            Method::Get => &mut self.get,
+            Method::Put => &mut self.put,
+            Method::SampleInfohashes => &mut self.sample_infohashes,
+            Method::Unknown => &mut self.unknown,
+            Method::Malformed => &mut self.malformed,
+        };
+
+        *count += 1;
+    }
+
+    fn record(&mut self, request: &Request) {
+        let count = match request {
+            Request::Ping(_) => &mut self.ping,
+            Request::FindNode(_) => &mut self.find_node,
+            Request::GetPeers(_) => &mut self.get_peers,
+            Request::AnnouncePeer(_) => &mut self.announce_peer,
+            Request::Get(_) => &mut self.get,
+            Request::Put(_) => &mut self.put,
+            Request::SampleInfohashes(_) => &mut self.sample_infohashes,
+            Request::Unknown(_) => &mut self.unknown,
+            Request::Malformed(_) => &mut self.malformed,
+        };
+
+        *count += 1;
+    }
+
+    fn merge(&mut self, other: &MessageCounts) {
+        self.ping += other.ping;
+        self.find_node += other.find_node;
+        self.get_peers += other.get_peers;
+        self.announce_peer += other.announce_peer;
+        self.get += other.get;
+        self.put += other.put;
+        self.sample_infohashes += other.sample_infohashes;
+        self.unknown += other.unknown;
+        self.malformed += other.malformed;
+    }
+}
+
+/// The methods of the requests, to count the replies to them by.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Method {
+    Ping,
+    FindNode,
+    GetPeers,
+    AnnouncePeer,
+    Get,
+    Put,
+    SampleInfohashes,
+    /// A method we don't understand.
+    Unknown,
+    /// A known method with missing or invalid arguments.
+    Malformed,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        message::{error_code, Error, PingRequest},
+        test,
+    };
+
+    #[test]
+    fn positive_record_and_merge() {
+        let ping = MessageBody::Request(Request::Ping(PingRequest {
+            id: test::dummy_node_id(),
+        }));
+        let error = MessageBody::Error(Error {
+            code: error_code::PROTOCOL_ERROR,
+            message: "Protocol Error".to_owned(),
+        });
+
+        let mut a = Stats::default();
+        a.record_sent(&ping, 10);
+        a.record_received(&error);
+
+        let mut b = Stats::default();
+        b.record_sent(&ping, 20);
+        b.record_sent(&error, 30);
+        b.responses_received.record_response(Method::Get);
+
+        a.merge(b);
+
+        assert_eq!(a.requests_sent.ping, 2);
+        assert_eq!(a.requests_sent.total(), 2);
+        assert_eq!(a.responses_received.get, 1);
+        assert_eq!(a.responses_received.total(), 1);
+        assert_eq!(a.bytes_sent, 60);
+        assert_eq!(a.errors_sent.get(&error_code::PROTOCOL_ERROR), Some(&1));
+        assert_eq!(a.errors_received.get(&error_code::PROTOCOL_ERROR), Some(&1));
+    }
+}
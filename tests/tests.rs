@@ -1,7 +1,14 @@
-use btdht::{InfoHash, MainlineDht};
+use btdht::{
+    AnnounceOptions, DhtConfig, DhtEvent, InfoHash, IpVersion, LookupEvent, MainlineDht, NodeId,
+    NodeStatus,
+};
 use futures_util::StreamExt;
-use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
-use tokio::net::UdpSocket;
+use std::{
+    collections::HashSet,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+use tokio::{net::UdpSocket, sync::mpsc, time};
 
 #[tokio::test(flavor = "multi_thread")]
 async fn announce_and_lookup_v4() {
@@ -30,6 +37,7 @@ async fn announce_and_lookup(addr_family: AddrFamily) {
     let a_node = MainlineDht::builder()
         .add_node(bootstrap_node_addr)
         .set_read_only(false)
+        .set_config(small_network_config())
         .start(a_socket)
         .unwrap();
 
@@ -38,6 +46,7 @@ async fn announce_and_lookup(addr_family: AddrFamily) {
     let b_node = MainlineDht::builder()
         .add_node(bootstrap_node_addr)
         .set_read_only(false)
+        .set_config(small_network_config())
         .start(b_socket)
         .unwrap();
 
@@ -57,6 +66,578 @@ async fn announce_and_lookup(addr_family: AddrFamily) {
     assert_eq!(search.next().await, Some(a_addr))
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn announce_repeats_until_dropped() {
+    let the_info_hash = InfoHash::sha1(b"foo");
+
+    let (r_addr, _r_node) = start_node(AddrFamily::V4, None).await;
+    let (a_addr, a_node) = start_node(AddrFamily::V4, Some(r_addr)).await;
+    let (_, b_node) = start_node(AddrFamily::V4, Some(r_addr)).await;
+
+    let options = AnnounceOptions::new()
+        .set_interval(Duration::from_millis(200))
+        .set_jitter(Duration::ZERO);
+    let mut announcement = a_node.announce(the_info_hash, options);
+
+    // Each round announces to the router and ends with a summary.
+    for _ in 0..2 {
+        let mut accepted = false;
+
+        loop {
+            let event = time::timeout(Duration::from_secs(10), announcement.next())
+                .await
+                .unwrap()
+                .unwrap();
+
+            match event {
+                LookupEvent::AnnounceAccepted(node) => accepted |= node.addr == r_addr,
+                LookupEvent::AnnounceRejected { .. } => panic!("unexpected {:?}", event),
+                LookupEvent::Finished(_) => break,
+                _ => (),
+            }
+        }
+
+        assert!(accepted);
+    }
+
+    let mut search = b_node.search(the_info_hash, false);
+    assert_eq!(search.next().await, Some(a_addr));
+
+    // No more rounds once the handle is dropped.
+    drop(announcement);
+    let mut events = a_node.events();
+    let started = time::timeout(Duration::from_millis(600), async {
+        while let Some(event) = events.next().await {
+            if let DhtEvent::LookupStarted { .. } = event {
+                return;
+            }
+        }
+    })
+    .await;
+    assert!(started.is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn dual_stack_search() {
+    let the_info_hash = InfoHash::sha1(b"foo");
+
+    // Single stack nodes storing the announces, one for each address family.
+    let (s4_addr, _s4_node) = start_node(AddrFamily::V4, None).await;
+    let (s6_addr, _s6_node) = start_node(AddrFamily::V6, None).await;
+
+    // Dual-stack node knowing both of them.
+    let x4_socket = UdpSocket::bind(localhost(AddrFamily::V4)).await.unwrap();
+    let x4_addr = x4_socket.local_addr().unwrap();
+    let x6_socket = UdpSocket::bind(localhost(AddrFamily::V6)).await.unwrap();
+    let x_node = MainlineDht::builder()
+        .add_node(s4_addr)
+        .add_node(s6_addr)
+        .set_read_only(false)
+        .set_config(small_network_config())
+        .start_dual(x4_socket, x6_socket)
+        .unwrap();
+    assert!(x_node.bootstrapped(None).await);
+
+    // Peers announcing over each address family.
+    let (p4_addr, p4_node) = start_node(AddrFamily::V4, Some(s4_addr)).await;
+    let (p6_addr, p6_node) = start_node(AddrFamily::V6, Some(s6_addr)).await;
+    assert_eq!(p4_node.search(the_info_hash, true).next().await, None);
+    assert_eq!(p6_node.search(the_info_hash, true).next().await, None);
+
+    // Dual-stack node knowing only the IPv4 address of the other one. It learns about the IPv6
+    // node from the `nodes6` of the responses it gets over IPv4.
+    let d4_socket = UdpSocket::bind(localhost(AddrFamily::V4)).await.unwrap();
+    let d6_socket = UdpSocket::bind(localhost(AddrFamily::V6)).await.unwrap();
+    let d_node = MainlineDht::builder()
+        .add_node(x4_addr)
+        .set_config(small_network_config().set_refresh_interval(Duration::from_millis(100)))
+        .start_dual(d4_socket, d6_socket)
+        .unwrap();
+    assert!(d_node.bootstrapped(None).await);
+    d_node.find_node(NodeId::sha1(b"bar")).await;
+
+    time::timeout(Duration::from_secs(10), async {
+        while !d_node.routing_table().await.iter().any(|table| {
+            table.ip_version == IpVersion::V6
+                && table
+                    .buckets
+                    .iter()
+                    .flat_map(|bucket| &bucket.nodes)
+                    .any(|node| node.addr == s6_addr && node.status == NodeStatus::Good)
+        }) {
+            time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .unwrap();
+
+    // The search is performed over both address families.
+    let peers: HashSet<_> = d_node.search(the_info_hash, false).collect().await;
+    assert_eq!(peers, [p4_addr, p6_addr].iter().copied().collect());
+
+    // Each address family sends its own summary.
+    let summaries: Vec<_> = d_node
+        .lookup(the_info_hash, false)
+        .filter_map(|event| async move {
+            match event {
+                LookupEvent::Finished(summary) => Some(summary.ip_version),
+                _ => None,
+            }
+        })
+        .collect()
+        .await;
+    assert_eq!(summaries.len(), 2);
+    assert!(summaries.contains(&IpVersion::V4));
+    assert!(summaries.contains(&IpVersion::V6));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn start_dual_with_mismatched_sockets() {
+    let v4_socket = UdpSocket::bind(localhost(AddrFamily::V4)).await.unwrap();
+    let v6_socket = UdpSocket::bind(localhost(AddrFamily::V6)).await.unwrap();
+
+    // Sockets swapped
+    assert!(MainlineDht::builder()
+        .start_dual(v6_socket, v4_socket)
+        .is_err());
+
+    let v4_socket = UdpSocket::bind(localhost(AddrFamily::V4)).await.unwrap();
+    let v6_socket = UdpSocket::bind(localhost(AddrFamily::V6)).await.unwrap();
+    let dht = MainlineDht::builder()
+        .start_dual(v4_socket, v6_socket)
+        .unwrap();
+
+    assert!(dht.bootstrapped(None).await);
+    assert!(dht.local_addr().await.unwrap().is_ipv4());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn dual_stack_shares_node_id() {
+    let v4_socket = UdpSocket::bind(localhost(AddrFamily::V4)).await.unwrap();
+    let v6_socket = UdpSocket::bind(localhost(AddrFamily::V6)).await.unwrap();
+    let dht = MainlineDht::builder()
+        .start_dual(v4_socket, v6_socket)
+        .unwrap();
+
+    let tables = dht.routing_table().await;
+    assert_eq!(tables.len(), 2);
+    assert_eq!(tables[0].node_id, tables[1].node_id);
+
+    // Both families get the id back from the snapshot.
+    let snapshot = dht.export_routing_table().await.unwrap();
+    assert_eq!(snapshot.node_id, tables[0].node_id);
+
+    let v4_socket = UdpSocket::bind(localhost(AddrFamily::V4)).await.unwrap();
+    let v6_socket = UdpSocket::bind(localhost(AddrFamily::V6)).await.unwrap();
+    let dht = MainlineDht::builder()
+        .restore_routing_table(snapshot.clone())
+        .start_dual(v4_socket, v6_socket)
+        .unwrap();
+
+    let tables = dht.routing_table().await;
+    assert!(tables.iter().all(|table| table.node_id == snapshot.node_id));
+
+    // Each socket is given back as the family it ran on.
+    let shutdown = dht.shutdown(false).await.unwrap();
+    let v4_socket = shutdown.v4_socket.unwrap().downcast::<UdpSocket>().unwrap();
+    let v6_socket = shutdown.v6_socket.unwrap().downcast::<UdpSocket>().unwrap();
+    assert!(v4_socket.local_addr().unwrap().is_ipv4());
+    assert!(v6_socket.local_addr().unwrap().is_ipv6());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn find_node_returns_closest_responding_nodes() {
+    let target = NodeId::sha1(b"target");
+
+    // Router knowing all the other nodes.
+    let (r_addr, r_node) = start_node(AddrFamily::V4, None).await;
+    let mut live_ids = vec![r_node.routing_table().await[0].node_id];
+    let mut live_nodes = Vec::new();
+
+    for _ in 0..6 {
+        let (addr, node) = start_node(AddrFamily::V4, Some(r_addr)).await;
+        live_ids.push(node.routing_table().await[0].node_id);
+        r_node.ping(addr).await.unwrap();
+        live_nodes.push(node);
+    }
+
+    // Node closest to the target which stops responding after the router learned about it.
+    let d_socket = UdpSocket::bind(localhost(AddrFamily::V4)).await.unwrap();
+    let d_addr = d_socket.local_addr().unwrap();
+    let d_node = MainlineDht::builder()
+        .set_node_id(target)
+        .set_read_only(false)
+        .start(d_socket)
+        .unwrap();
+    r_node.ping(d_addr).await.unwrap();
+    d_node.shutdown(false).await.unwrap();
+
+    let q_socket = UdpSocket::bind(localhost(AddrFamily::V4)).await.unwrap();
+    let q_node = MainlineDht::builder()
+        .add_node(r_addr)
+        .set_config(small_network_config().set_bucket_size(4))
+        .start(q_socket)
+        .unwrap();
+    assert!(q_node.bootstrapped(None).await);
+
+    // Only the nodes which responded are returned, up to the bucket size.
+    live_ids.sort_by_key(|id| *id ^ target);
+    live_ids.truncate(4);
+
+    let ids: Vec<_> = q_node
+        .find_node(target)
+        .await
+        .into_iter()
+        .map(|node| node.id)
+        .collect();
+    assert_eq!(ids, live_ids);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn ping() {
+    let ping_timeout = Duration::from_secs(2);
+    let a_socket = UdpSocket::bind(localhost(AddrFamily::V4)).await.unwrap();
+    let a_node = MainlineDht::builder()
+        .set_config(DhtConfig::new().set_ping_timeout(ping_timeout))
+        .start(a_socket)
+        .unwrap();
+
+    let b_id = NodeId::sha1(b"b");
+    let b_socket = UdpSocket::bind(localhost(AddrFamily::V4)).await.unwrap();
+    let b_addr = b_socket.local_addr().unwrap();
+    let _b_node = MainlineDht::builder()
+        .set_node_id(b_id)
+        .set_read_only(false)
+        .start(b_socket)
+        .unwrap();
+
+    let response = a_node.ping(b_addr).await.unwrap();
+    assert_eq!(response.id, b_id);
+    assert!(response.rtt > Duration::ZERO);
+    assert!(response.rtt < ping_timeout);
+
+    let stats = a_node.stats().await.unwrap();
+    assert_eq!(stats.requests_sent.ping, 1);
+    assert_eq!(stats.responses_received.ping, 1);
+    assert_eq!(stats.responses_received.total(), 1);
+    assert!(stats.bytes_sent > 0);
+    assert!(stats.bytes_received > 0);
+
+    let tables = a_node.routing_table().await;
+    assert_eq!(tables.len(), 1);
+    assert!(tables[0]
+        .buckets
+        .iter()
+        .flat_map(|bucket| &bucket.nodes)
+        .any(|node| node.id == b_id && node.rtt.is_some()));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn ping_ignores_error_from_other_address() {
+    let a_socket = UdpSocket::bind(localhost(AddrFamily::V4)).await.unwrap();
+    let a_addr = a_socket.local_addr().unwrap();
+    let a_node = MainlineDht::builder().start(a_socket).unwrap();
+
+    let b_socket = UdpSocket::bind(localhost(AddrFamily::V4)).await.unwrap();
+    let b_addr = b_socket.local_addr().unwrap();
+    let c_socket = UdpSocket::bind(localhost(AddrFamily::V4)).await.unwrap();
+
+    let responder = tokio::spawn(async move {
+        let mut buffer = vec![0; 1500];
+        let (len, _) = b_socket.recv_from(&mut buffer).await.unwrap();
+        let trans_id = transaction_id(&buffer[..len]);
+
+        // Error with the right transaction id from someone else than the pinged node.
+        let mut error = b"d1:eli201e7:Generice1:t".to_vec();
+        error.extend_from_slice(&trans_id);
+        error.extend_from_slice(b"1:y1:ee");
+        c_socket.send_to(&error, a_addr).await.unwrap();
+        time::sleep(Duration::from_millis(100)).await;
+
+        let mut response = b"d1:rd2:id20:bbbbbbbbbbbbbbbbbbbbe1:t".to_vec();
+        response.extend_from_slice(&trans_id);
+        response.extend_from_slice(b"1:y1:re");
+        b_socket.send_to(&response, a_addr).await.unwrap();
+    });
+
+    let response = a_node.ping(b_addr).await.unwrap();
+    assert_eq!(response.id.as_ref(), b"bbbbbbbbbbbbbbbbbbbb");
+    responder.await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn dropped_search_is_aborted() {
+    // Node which answers everything except `get_peers`, which it only reports.
+    let f_socket = UdpSocket::bind(localhost(AddrFamily::V4)).await.unwrap();
+    let f_addr = f_socket.local_addr().unwrap();
+    let (get_peers_tx, mut get_peers_rx) = mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        let mut buffer = vec![0; 1500];
+
+        loop {
+            let (len, addr) = f_socket.recv_from(&mut buffer).await.unwrap();
+            let request = &buffer[..len];
+
+            if request.windows(11).any(|window| window == b"9:get_peers") {
+                get_peers_tx.send(()).unwrap_or(());
+                continue;
+            }
+
+            let mut response = b"d1:rd2:id20:ffffffffffffffffffffe1:t".to_vec();
+            response.extend_from_slice(&transaction_id(request));
+            response.extend_from_slice(b"1:y1:re");
+            f_socket.send_to(&response, addr).await.unwrap();
+        }
+    });
+
+    let lookup_timeout = Duration::from_millis(500);
+    let q_socket = UdpSocket::bind(localhost(AddrFamily::V4)).await.unwrap();
+    let q_node = MainlineDht::builder()
+        .add_node(f_addr)
+        .set_config(small_network_config().set_lookup_timeout(lookup_timeout))
+        .start(q_socket)
+        .unwrap();
+    assert!(q_node.bootstrapped(None).await);
+
+    let search = q_node.search(InfoHash::sha1(b"foo"), false);
+    time::timeout(Duration::from_secs(5), get_peers_rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    let timeouts = q_node.stats().await.unwrap().timeouts;
+
+    drop(search);
+    time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(q_node.stats().await.unwrap().active_lookups, 0);
+
+    // Neither the request timeout fires nor anything else is sent.
+    time::sleep(lookup_timeout * 2).await;
+    assert_eq!(q_node.stats().await.unwrap().timeouts, timeouts);
+    assert!(get_peers_rx.try_recv().is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn shutdown_returns_socket() {
+    let socket = UdpSocket::bind(localhost(AddrFamily::V4)).await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    let id = NodeId::sha1(b"a");
+    let dht = MainlineDht::builder()
+        .set_node_id(id)
+        .start(socket)
+        .unwrap();
+    let other = dht.clone();
+
+    let mut shutdown = dht.shutdown(false).await.unwrap();
+    assert_eq!(shutdown.routing_table.node_id, id);
+    assert!(other.get_state().await.is_none());
+    assert!(other.shutdown(false).await.is_none());
+
+    // The socket can be used to start again.
+    assert!(shutdown.v6_socket.is_none());
+    let socket = *shutdown
+        .v4_socket
+        .take()
+        .unwrap()
+        .downcast::<UdpSocket>()
+        .unwrap();
+    assert_eq!(socket.local_addr().unwrap(), addr);
+
+    let dht = MainlineDht::builder()
+        .restore_routing_table(shutdown.routing_table)
+        .start(socket)
+        .unwrap();
+    assert_eq!(dht.local_addr().await.unwrap(), addr);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn shutdown_finishes_lookups() {
+    let (r_addr, _r_node) = start_node(AddrFamily::V4, None).await;
+    let (_, a_node) = start_node(AddrFamily::V4, Some(r_addr)).await;
+
+    let mut lookup = a_node.lookup(InfoHash::sha1(b"foo"), true);
+
+    // Shut down while the lookup waits for the endgame, after the router gave us its token.
+    loop {
+        match lookup.next().await.unwrap() {
+            LookupEvent::NodeResponded(node) if node.addr == r_addr => break,
+            _ => (),
+        }
+    }
+
+    let shutdown = tokio::spawn(a_node.shutdown(true));
+
+    // The outcome of the announce sent on shutdown is still reported.
+    let events: Vec<_> = lookup.collect().await;
+    assert!(events.iter().any(|event| matches!(
+        event,
+        LookupEvent::AnnounceAccepted(node) if node.addr == r_addr
+    )));
+    assert!(matches!(events.last(), Some(LookupEvent::Finished(_))));
+
+    assert!(shutdown.await.unwrap().unwrap().v4_socket.is_some());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn events_report_lag() {
+    let socket = UdpSocket::bind(localhost(AddrFamily::V4)).await.unwrap();
+    let dht = MainlineDht::builder().start(socket).unwrap();
+    assert!(dht.bootstrapped(None).await);
+
+    let mut events = dht.events();
+
+    // With nobody to ask, each search just starts and finishes.
+    let num_searches = 200;
+    for _ in 0..num_searches {
+        assert_eq!(dht.search(InfoHash::sha1(b"foo"), false).next().await, None);
+    }
+
+    dht.shutdown(false).await.unwrap();
+
+    // The oldest events are dropped as the stream hasn't been polled.
+    let missed = match events.next().await {
+        Some(DhtEvent::Lagged {
+            ip_version: IpVersion::V4,
+            missed,
+        }) => missed,
+        event => panic!("unexpected {:?}", event),
+    };
+
+    let rest: Vec<_> = events.collect().await;
+    assert_eq!(missed as usize + rest.len(), 2 * num_searches);
+    assert!(matches!(rest.last(), Some(DhtEvent::LookupFinished { .. })));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn start_with_invalid_config() {
+    let socket = UdpSocket::bind(localhost(AddrFamily::V4)).await.unwrap();
+    let config = DhtConfig::new()
+        .set_bucket_size(2)
+        .set_lookup_initial_pick(3);
+
+    assert!(MainlineDht::builder()
+        .set_config(config)
+        .start(socket)
+        .is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reject_malformed_and_unknown_requests() {
+    let node_socket = UdpSocket::bind(localhost(AddrFamily::V4)).await.unwrap();
+    let node_addr = node_socket.local_addr().unwrap();
+    let node = MainlineDht::builder()
+        .set_read_only(false)
+        .start(node_socket)
+        .unwrap();
+
+    let socket = UdpSocket::bind(localhost(AddrFamily::V4)).await.unwrap();
+
+    // Known method without a token: Protocol Error.
+    let response = exchange(
+        &socket,
+        node_addr,
+        "d1:ad2:id20:abcdefghij01234567899:info_hash20:mnopqrstuvwxyz1234564:porti6881ee1:q13:announce_peer1:t2:aa1:y1:qe",
+    )
+    .await;
+    assert!(response.contains("1:eli203e"), "{}", response);
+
+    // Known method without a target: Protocol Error, not answered like find_node.
+    let response = exchange(
+        &socket,
+        node_addr,
+        "d1:ad2:id20:abcdefghij0123456789e1:q3:get1:t2:aa1:y1:qe",
+    )
+    .await;
+    assert!(response.contains("1:eli203e"), "{}", response);
+
+    // Unknown method without a target: Method Unknown.
+    let response = exchange(
+        &socket,
+        node_addr,
+        "d1:ad2:id20:abcdefghij0123456789e1:q3:foo1:t2:aa1:y1:qe",
+    )
+    .await;
+    assert!(response.contains("1:eli204e"), "{}", response);
+
+    // Unknown method with a target: answered like find_node.
+    let response = exchange(
+        &socket,
+        node_addr,
+        "d1:ad2:id20:abcdefghij01234567896:target20:mnopqrstuvwxyz123456e1:q3:foo1:t2:aa1:y1:qe",
+    )
+    .await;
+    assert!(response.contains("1:y1:r"), "{}", response);
+
+    // The error replies are counted by their code, the response by the method it answers.
+    let stats = node.stats().await.unwrap();
+    assert_eq!(stats.errors_sent.get(&203), Some(&2));
+    assert_eq!(stats.errors_sent.get(&204), Some(&1));
+    assert_eq!(stats.responses_sent.find_node, 1);
+    assert_eq!(stats.responses_sent.total(), 1);
+}
+
+// Send the raw request and return the raw response.
+async fn exchange(socket: &UdpSocket, addr: SocketAddr, request: &str) -> String {
+    socket.send_to(request.as_bytes(), addr).await.unwrap();
+
+    let mut buffer = vec![0; 1500];
+    let (len, _) = time::timeout(Duration::from_secs(5), socket.recv_from(&mut buffer))
+        .await
+        .unwrap()
+        .unwrap();
+
+    String::from_utf8_lossy(&buffer[..len]).into_owned()
+}
+
+// Extract the bencoded transaction id (including its length prefix) from the raw request. The
+// keys are sorted, so it follows the arguments which could contain anything.
+fn transaction_id(message: &[u8]) -> Vec<u8> {
+    let start = message
+        .windows(4)
+        .rposition(|window| window == b"e1:q")
+        .unwrap();
+    let start = start
+        + message[start..]
+            .windows(3)
+            .position(|window| window == b"1:t")
+            .unwrap()
+        + 3;
+    let colon = start + message[start..].iter().position(|b| *b == b':').unwrap();
+    let len: usize = std::str::from_utf8(&message[start..colon])
+        .unwrap()
+        .parse()
+        .unwrap();
+
+    message[start..colon + 1 + len].to_vec()
+}
+
+// Start a node which bootstraps from the given one, if any.
+async fn start_node(
+    addr_family: AddrFamily,
+    bootstrap_node_addr: Option<SocketAddr>,
+) -> (SocketAddr, MainlineDht) {
+    let socket = UdpSocket::bind(localhost(addr_family)).await.unwrap();
+    let addr = socket.local_addr().unwrap();
+
+    let mut builder = MainlineDht::builder()
+        .set_read_only(false)
+        .set_config(small_network_config());
+
+    if let Some(bootstrap_node_addr) = bootstrap_node_addr {
+        builder = builder.add_node(bootstrap_node_addr);
+    }
+
+    let node = builder.start(socket).unwrap();
+    assert!(node.bootstrapped(None).await);
+
+    (addr, node)
+}
+
+// The few nodes of the test networks are enough for the bootstrap to succeed.
+fn small_network_config() -> DhtConfig {
+    DhtConfig::new().set_good_node_threshold(1)
+}
+
 #[derive(Copy, Clone)]
 enum AddrFamily {
     V4,